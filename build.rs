@@ -0,0 +1,25 @@
+//! Records the git commit the server was built from, for the /version route
+
+use std::process::Command;
+
+fn main() {
+    // Some build environments, like Heroku, don't have the git history,
+    // but tell us the commit instead
+    let sha = std::env::var("SOURCE_VERSION")
+        .ok()
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+        })
+        .map(|sha| sha.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=GIT_SHA={sha}");
+    println!("cargo:rerun-if-env-changed=SOURCE_VERSION");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
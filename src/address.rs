@@ -0,0 +1,261 @@
+//! Parsing raw Japanese address strings back into their components
+//!
+//! This is the inverse of the `full_address` field we add when serializing
+//! a [`Property`](crate::property::Property). It's a best-effort parser,
+//! since real addresses come in many more shapes than we handle here.
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::prefecture::strip_prefecture;
+
+/// The components of a Japanese address, matching the address fields of a property
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AddressParts {
+    pub prefecture: String,
+    pub city: String,
+    pub town: String,
+    pub chome: String,
+    pub banchi: String,
+    pub go: String,
+    pub building: String,
+}
+
+/// The reasons an address string can fail to parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressParseError {
+    UnknownPrefecture,
+    MissingCity,
+    MissingTown,
+    MissingNumber(&'static str),
+}
+
+impl fmt::Display for AddressParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressParseError::UnknownPrefecture => {
+                write!(f, "address must start with a known prefecture")
+            }
+            AddressParseError::MissingCity => write!(f, "address is missing a city"),
+            AddressParseError::MissingTown => write!(f, "address is missing a town"),
+            AddressParseError::MissingNumber(part) => write!(f, "address is missing the {part}"),
+        }
+    }
+}
+
+impl std::error::Error for AddressParseError {}
+
+/// Splits a raw address into its components.
+///
+/// We accept both the formal style that we output ourselves, such as
+/// `東京都中央区日本橋4丁目16番地12号国立競技場`, and the hyphenated style,
+/// such as `東京都中央区日本橋4-16-12 国立競技場`.
+pub fn parse_address(address: &str) -> Result<AddressParts, AddressParseError> {
+    let address = address.trim();
+
+    let (prefecture, rest) =
+        strip_prefecture(address).ok_or(AddressParseError::UnknownPrefecture)?;
+    let (city, rest) = split_city(rest).ok_or(AddressParseError::MissingCity)?;
+
+    // The town runs up until the first block number
+    let town_end = rest.find(is_digit).unwrap_or(rest.len());
+    let (town, rest) = rest.split_at(town_end);
+
+    if town.is_empty() {
+        return Err(AddressParseError::MissingTown);
+    }
+
+    let (chome, rest) = take_number(rest).ok_or(AddressParseError::MissingNumber("chome"))?;
+    let rest = strip_marker(rest, &["丁目"]);
+    let (banchi, rest) = take_number(rest).ok_or(AddressParseError::MissingNumber("banchi"))?;
+    let rest = strip_marker(rest, &["番地", "番"]);
+    let (go, rest) = take_number(rest).ok_or(AddressParseError::MissingNumber("go"))?;
+    let rest = strip_marker(rest, &["号"]);
+
+    Ok(AddressParts {
+        prefecture: prefecture.to_owned(),
+        city: city.to_owned(),
+        town: town.to_owned(),
+        chome: chome.to_owned(),
+        banchi: banchi.to_owned(),
+        go: go.to_owned(),
+        building: rest.trim().to_owned(),
+    })
+}
+
+/// Cities end in 市, 区, 町, or 村. Towns and villages inside a district
+/// are written with the district first, as in `xx郡yy町`, so we keep the
+/// district as part of the city.
+///
+/// Those characters can be part of a city's name too. One in the first
+/// character never ends the city, as in 町田市 or 郡山市, and a 市 shortly
+/// after a 市, 町, or 村 ends the city instead, as in 四日市市, 大町市,
+/// or 東村山市, as long as there's still a town after it.
+fn split_city(s: &str) -> Option<(&str, &str)> {
+    let first_len = s.chars().next()?.len_utf8();
+    let end = first_len + s[first_len..].find(['市', '区', '町', '村', '郡'])?;
+    let marker_len = '市'.len_utf8();
+    let city_end = end + marker_len;
+
+    if s[end..].starts_with('郡') {
+        // A 市 before the town means the 郡 was part of a name, as in 大和郡山市
+        let town_end = city_end + s[city_end..].find(['市', '町', '村'])?;
+        return Some(s.split_at(town_end + marker_len));
+    }
+
+    if s[end..].starts_with('区') {
+        return Some(s.split_at(city_end));
+    }
+
+    let longer = s[city_end..]
+        .char_indices()
+        .take(2)
+        .find(|(_, c)| *c == '市')
+        .map(|(offset, _)| city_end + offset + marker_len)
+        .filter(|&longer| s[longer..].starts_with(|c| !is_digit(c)));
+
+    Some(s.split_at(longer.unwrap_or(city_end)))
+}
+
+fn is_digit(c: char) -> bool {
+    c.is_ascii_digit() || ('０'..='９').contains(&c)
+}
+
+/// Takes the run of leading digits off of the string
+fn take_number(s: &str) -> Option<(&str, &str)> {
+    let end = s.find(|c| !is_digit(c)).unwrap_or(s.len());
+
+    match end {
+        0 => None,
+        _ => Some(s.split_at(end)),
+    }
+}
+
+/// Removes a leading marker, which can be one of the given kanji markers
+/// or any of the hyphens commonly used in their place
+fn strip_marker<'a>(s: &'a str, markers: &[&str]) -> &'a str {
+    markers
+        .iter()
+        .find_map(|marker| s.strip_prefix(marker))
+        .or_else(|| s.strip_prefix(['-', '－', '−', 'ー', '‐']))
+        .unwrap_or(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn city_and_town(address: &str) -> (String, String) {
+        let parts = parse_address(address).unwrap();
+        (parts.city, parts.town)
+    }
+
+    #[test]
+    fn parses_the_formal_style() {
+        assert_eq!(
+            parse_address("東京都中央区日本橋4丁目16番地12号国立競技場"),
+            Ok(AddressParts {
+                prefecture: "東京都".to_owned(),
+                city: "中央区".to_owned(),
+                town: "日本橋".to_owned(),
+                chome: "4".to_owned(),
+                banchi: "16".to_owned(),
+                go: "12".to_owned(),
+                building: "国立競技場".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn parses_the_hyphenated_style() {
+        let parts = parse_address("東京都中央区日本橋４－１６－１２ 国立競技場").unwrap();
+
+        assert_eq!(parts.town, "日本橋");
+        assert_eq!(
+            (
+                parts.chome.as_str(),
+                parts.banchi.as_str(),
+                parts.go.as_str()
+            ),
+            ("４", "１６", "１２")
+        );
+        assert_eq!(parts.building, "国立競技場");
+    }
+
+    #[test]
+    fn splits_at_the_first_marker() {
+        assert_eq!(
+            city_and_town("東京都渋谷区神南1丁目2番地3号"),
+            ("渋谷区".to_owned(), "神南".to_owned())
+        );
+        assert_eq!(
+            city_and_town("神奈川県横浜市中区山下町1-2-3"),
+            ("横浜市".to_owned(), "中区山下町".to_owned())
+        );
+        assert_eq!(
+            city_and_town("東京都新宿区市谷本村町1-2-3"),
+            ("新宿区".to_owned(), "市谷本村町".to_owned())
+        );
+    }
+
+    #[test]
+    fn skips_a_marker_at_the_start_of_the_name() {
+        assert_eq!(
+            city_and_town("東京都町田市原町田1-2-3"),
+            ("町田市".to_owned(), "原町田".to_owned())
+        );
+        assert_eq!(
+            city_and_town("千葉県市川市八幡1-2-3"),
+            ("市川市".to_owned(), "八幡".to_owned())
+        );
+        assert_eq!(
+            city_and_town("福島県郡山市朝日1-2-3"),
+            ("郡山市".to_owned(), "朝日".to_owned())
+        );
+    }
+
+    #[test]
+    fn keeps_markers_inside_the_name() {
+        assert_eq!(
+            city_and_town("三重県四日市市諏訪町1-2-3"),
+            ("四日市市".to_owned(), "諏訪町".to_owned())
+        );
+        assert_eq!(
+            city_and_town("長野県大町市大町1-2-3"),
+            ("大町市".to_owned(), "大町".to_owned())
+        );
+        assert_eq!(
+            city_and_town("東京都東村山市本町1-2-3"),
+            ("東村山市".to_owned(), "本町".to_owned())
+        );
+        assert_eq!(
+            city_and_town("奈良県大和郡山市北郡山町1-2-3"),
+            ("大和郡山市".to_owned(), "北郡山町".to_owned())
+        );
+    }
+
+    #[test]
+    fn keeps_the_district_with_the_town() {
+        assert_eq!(
+            city_and_town("神奈川県足柄下郡箱根町湯本1-2-3"),
+            ("足柄下郡箱根町".to_owned(), "湯本".to_owned())
+        );
+    }
+
+    #[test]
+    fn reports_what_is_missing() {
+        assert_eq!(
+            parse_address("Tokyo"),
+            Err(AddressParseError::UnknownPrefecture)
+        );
+        assert_eq!(
+            parse_address("東京都渋谷区1-2-3"),
+            Err(AddressParseError::MissingTown)
+        );
+        assert_eq!(
+            parse_address("東京都渋谷区神南"),
+            Err(AddressParseError::MissingNumber("chome"))
+        );
+    }
+}
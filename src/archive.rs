@@ -0,0 +1,129 @@
+//! Reading the CSV files out of an uploaded ZIP archive
+//!
+//! Data is sometimes distributed as a ZIP of regional CSV files,
+//! which we import as if the files had been uploaded together.
+
+use std::io::{Cursor, Read};
+
+use zip::ZipArchive;
+
+use crate::import::ImportError;
+
+/// Checks if the data looks like a ZIP archive, from its magic bytes.
+/// Excel workbooks are ZIP archives too, so check for those first.
+pub fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04")
+}
+
+/// Checks if the archive has an entry with exactly this name
+pub fn has_entry(data: &[u8], name: &str) -> bool {
+    ZipArchive::new(Cursor::new(data))
+        .is_ok_and(|archive| archive.file_names().any(|entry| entry == name))
+}
+
+/// Extracts the contents of every `.csv` file in the archive, in the order
+/// they were added to it. Anything else, like a README, is ignored.
+///
+/// A small archive can expand into a huge amount of data, so we stop
+/// once the files add up to more than `max_bytes`. The sizes in the archive
+/// are whatever its creator wrote, so we count what we actually read.
+pub fn csv_entries(data: &[u8], max_bytes: usize) -> Result<Vec<Vec<u8>>, ImportError> {
+    let invalid = |error: zip::result::ZipError| ImportError::InvalidArchive(error.to_string());
+
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(invalid)?;
+    let mut entries = vec![];
+    let mut remaining = max_bytes;
+
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i).map_err(invalid)?;
+
+        // macOS adds a hidden copy of each file's metadata, which isn't data
+        let name = entry.name();
+        let is_csv = entry.is_file()
+            && name.to_ascii_lowercase().ends_with(".csv")
+            && !name.starts_with("__MACOSX/");
+
+        if !is_csv {
+            continue;
+        }
+
+        // Reading one byte past the limit tells us if there was more
+        let mut contents = vec![];
+        entry
+            .take((remaining as u64).saturating_add(1))
+            .read_to_end(&mut contents)
+            .map_err(|error| ImportError::InvalidArchive(error.to_string()))?;
+
+        remaining = remaining
+            .checked_sub(contents.len())
+            .ok_or(ImportError::ArchiveTooLarge(max_bytes))?;
+        entries.push(contents);
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+    use super::*;
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        for (name, contents) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn reads_only_the_csv_files() {
+        let data = zip(&[
+            ("tokyo.csv", b"a,b\n"),
+            ("README.txt", b"hello"),
+            ("__MACOSX/._tokyo.csv", b"junk"),
+            ("osaka.CSV", b"c,d\n"),
+        ]);
+
+        assert!(is_zip(&data));
+        assert!(has_entry(&data, "README.txt"));
+        assert_eq!(
+            csv_entries(&data, usize::MAX).unwrap(),
+            vec![b"a,b\n".to_vec(), b"c,d\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn allows_files_that_add_up_to_the_limit() {
+        let data = zip(&[("a.csv", b"12345"), ("b.csv", b"67890")]);
+
+        assert_eq!(csv_entries(&data, 10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rejects_files_that_unpack_past_the_limit() {
+        // A megabyte of zeros compresses down to almost nothing
+        let bomb = vec![0; 1024 * 1024];
+        let data = zip(&[("bomb.csv", &bomb)]);
+        assert!(data.len() < 10 * 1024);
+
+        assert_eq!(
+            csv_entries(&data, 1024),
+            Err(ImportError::ArchiveTooLarge(1024))
+        );
+    }
+
+    #[test]
+    fn counts_the_limit_across_files() {
+        let data = zip(&[("a.csv", b"12345"), ("b.csv", b"67890")]);
+
+        assert_eq!(csv_entries(&data, 9), Err(ImportError::ArchiveTooLarge(9)));
+    }
+}
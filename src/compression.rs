@@ -0,0 +1,162 @@
+//! Choosing how to compress responses
+//!
+//! The compression itself is handled by `tower_http`, but when a client
+//! accepts several encodings equally, it always breaks the tie the same way.
+//! We pick the encoding ourselves instead, so that operators can decide
+//! whether to spend more CPU time on smaller responses.
+
+use std::str::FromStr;
+
+use tower_http::CompressionLevel;
+
+/// The encodings we can compress responses with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Br,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Br => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "br" => Ok(Encoding::Br),
+            "gzip" => Ok(Encoding::Gzip),
+            "deflate" => Ok(Encoding::Deflate),
+            other => Err(format!("unknown compression algorithm `{other}`")),
+        }
+    }
+}
+
+/// Parses a comma-separated list of encodings, such as `br,gzip,deflate`
+pub fn parse_encodings(s: &str) -> Result<Vec<Encoding>, String> {
+    s.split(',')
+        .filter(|encoding| !encoding.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Parses a compression quality, which is either one of
+/// `fastest`, `best`, or `default`, or a number for the algorithm's own scale
+pub fn parse_quality(s: &str) -> Option<CompressionLevel> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "fastest" => Some(CompressionLevel::Fastest),
+        "best" => Some(CompressionLevel::Best),
+        "default" => Some(CompressionLevel::Default),
+        number => number.parse().ok().map(CompressionLevel::Precise),
+    }
+}
+
+/// Picks the first of our preferred encodings that the client accepts,
+/// given the value of their `Accept-Encoding` header.
+///
+/// The client's q-values are only used to rule encodings out with `q=0`.
+/// Otherwise our order wins, since the client is telling us it can
+/// handle any of them.
+pub fn choose_encoding(accept_encoding: &str, preferences: &[Encoding]) -> Option<Encoding> {
+    let accepted: Vec<(&str, bool)> = accept_encoding
+        .split(',')
+        .map(|value| {
+            let mut parts = value.split(';');
+            let name = parts.next().unwrap_or_default().trim();
+            let rejected = parts.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+
+            (name, !rejected)
+        })
+        .collect();
+
+    let is_accepted = |encoding: Encoding| {
+        let listed = accepted
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(encoding.as_str()));
+
+        match listed {
+            Some((_, accepted)) => *accepted,
+            // A wildcard covers anything the client didn't list by name
+            None => accepted
+                .iter()
+                .any(|(name, accepted)| *name == "*" && *accepted),
+        }
+    };
+
+    preferences
+        .iter()
+        .copied()
+        .find(|encoding| is_accepted(*encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_our_order_over_the_clients() {
+        let preferences = [Encoding::Gzip, Encoding::Br];
+
+        assert_eq!(
+            choose_encoding("br, gzip", &preferences),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            choose_encoding("br;q=1.0, gzip;q=0.5", &preferences),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            choose_encoding("BR, gzip;q=0", &preferences),
+            Some(Encoding::Br)
+        );
+        assert_eq!(choose_encoding("deflate", &preferences), None);
+    }
+
+    #[test]
+    fn lets_a_wildcard_cover_unlisted_encodings() {
+        let preferences = [Encoding::Br, Encoding::Gzip];
+
+        assert_eq!(choose_encoding("*", &preferences), Some(Encoding::Br));
+        assert_eq!(
+            choose_encoding("br;q=0, *", &preferences),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(choose_encoding("*;q=0", &preferences), None);
+    }
+
+    #[test]
+    fn parses_the_compression_settings() {
+        assert_eq!(
+            parse_encodings("br, GZIP,"),
+            Ok(vec![Encoding::Br, Encoding::Gzip])
+        );
+        assert_eq!(
+            parse_encodings("br,zstd"),
+            Err("unknown compression algorithm `zstd`".to_string())
+        );
+
+        assert!(matches!(
+            parse_quality(" Best "),
+            Some(CompressionLevel::Best)
+        ));
+        assert!(matches!(
+            parse_quality("4"),
+            Some(CompressionLevel::Precise(4))
+        ));
+        assert!(parse_quality("high").is_none());
+    }
+}
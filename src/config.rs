@@ -0,0 +1,331 @@
+//! Settings for the server, read from environment variables at startup
+
+use std::{
+    ffi::OsString, fmt::Display, num::NonZeroUsize, path::PathBuf, str::FromStr, sync::Arc,
+    time::Duration,
+};
+
+use rayon::ThreadPool;
+use tower_http::CompressionLevel;
+
+use crate::{
+    compression::{self, Encoding},
+    export::StreamMode,
+    import::{ControlCharacters, OversizedFields},
+    logging::LogFormat,
+    property_type::{self, CanonicalType, TypeMapping},
+    station::StationAliases,
+    timestamp::Timezone,
+    validation::ValidationRules,
+};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Where to save a snapshot of the db after each change,
+    /// so that the data survives a restart (`SNAPSHOT_PATH`)
+    pub snapshot_path: Option<PathBuf>,
+    /// A CSV file to import on startup, so the API has data to serve
+    /// right away (`SEED_FILE`)
+    pub seed_file: Option<PathBuf>,
+    /// How many properties to return per page when the client
+    /// doesn't ask for a specific `limit` (`DEFAULT_PAGE_SIZE`)
+    pub default_page_size: usize,
+    /// The most properties a client can ask for in one page.
+    /// Larger limits are clamped down to this (`MAX_PAGE_SIZE`)
+    pub max_page_size: usize,
+    /// How many groups aggregations like `/by_station` send when the client
+    /// doesn't ask for a specific `limit` (`DEFAULT_GROUPS`)
+    pub default_groups: usize,
+    /// The most groups that aggregations like `/by_station` send when
+    /// asked for a `limit`. Larger limits are clamped down to this (`MAX_GROUPS`).
+    pub max_groups: usize,
+    /// The canonical property types left out of the list unless a client
+    /// asks for them, like `commercial` for a residential site (`HIDDEN_TYPES`).
+    pub hidden_types: Vec<CanonicalType>,
+    /// The most rows an uploaded file can have (`MAX_ROWS`)
+    pub max_rows: Option<usize>,
+    /// The most bytes the CSV files in an uploaded ZIP archive can unpack to
+    /// (`MAX_ARCHIVE_BYTES`)
+    pub max_archive_bytes: usize,
+    /// The most characters a field in an uploaded file can have (`MAX_FIELD_LEN`)
+    pub max_field_len: Option<usize>,
+    /// Whether rows with longer fields are skipped or truncated
+    /// (`OVERSIZED_FIELDS`)
+    pub oversized_fields: OversizedFields,
+    /// Whether control characters in uploaded fields are stripped out,
+    /// or their rows are skipped (`CONTROL_CHARACTERS`)
+    pub control_characters: ControlCharacters,
+    /// Whether to keep the raw row each property was imported from,
+    /// which clients can see with `include_raw` (`STORE_RAW_ROWS`)
+    pub store_raw_rows: bool,
+    /// How long a client has to finish sending an upload
+    /// (`UPLOAD_TIMEOUT_SECS`)
+    pub upload_timeout: Duration,
+    /// How long a change waits for other changes to finish,
+    /// before giving up with 503 Service Unavailable (`LOCK_TIMEOUT_MS`)
+    pub lock_timeout: Duration,
+    /// How long list responses are cached for, unless the data changes first.
+    /// Zero turns the cache off. (`LIST_CACHE_TTL_MS`)
+    pub list_cache_ttl: Duration,
+    /// How many threads to parse large uploads on (`PARSE_THREADS`).
+    /// Setting it to 0 uses one for each core.
+    pub parse_threads: usize,
+    /// The threads themselves, which the caller starts
+    /// with [`parse_pool`](crate::import::parse_pool), so that every upload shares them
+    pub parse_pool: Option<Arc<ThreadPool>>,
+    /// Whether deleting a property only marks it as deleted, so that it
+    /// can be recovered, instead of removing it (`SOFT_DELETE`)
+    pub soft_delete: bool,
+    /// The name of the form field that uploaded files are sent in (`FIELD_NAME`)
+    pub upload_field_name: String,
+    /// The largest file we'll download when importing from a URL, in bytes
+    /// (`URL_UPLOAD_MAX_BYTES`)
+    pub url_upload_max_bytes: usize,
+    /// The encodings we compress responses with, in order of preference.
+    /// An empty list turns compression off (`COMPRESSION_ALGORITHMS`)
+    pub compression_algorithms: Vec<Encoding>,
+    /// How hard to work at compressing responses (`COMPRESSION_QUALITY`)
+    pub compression_quality: CompressionLevel,
+    /// Whether to write logs as JSON or as human-readable text (`LOG_FORMAT`)
+    pub log_format: LogFormat,
+    /// The timezone that timestamps in responses are written in (`TIMEZONE`)
+    pub timezone: Timezone,
+    /// Whether streamed exports are copied out of the db when they start,
+    /// or read from it as they go (`STREAM_MODE`)
+    pub stream_mode: StreamMode,
+    /// A JSON file of extra rules that imported rows have to follow
+    /// (`VALIDATION_RULES`)
+    pub validation_rules_path: Option<PathBuf>,
+    /// The rules from that file. Reading the file can fail, so it's
+    /// left to the caller to load them with [`ValidationRules::load`].
+    pub validation_rules: ValidationRules,
+    /// A JSON file mapping other names for stations to the ones we store
+    /// (`STATION_ALIASES`)
+    pub station_aliases_path: Option<PathBuf>,
+    /// The aliases from that file, which the caller loads
+    /// with [`StationAliases::load`], like the validation rules
+    pub station_aliases: StationAliases,
+    /// A JSON file mapping property types to their canonical types,
+    /// on top of the built-in ones (`PROPERTY_TYPES`)
+    pub property_types_path: Option<PathBuf>,
+    /// The mappings from that file, which the caller loads
+    /// with [`TypeMapping::load`]
+    pub property_types: TypeMapping,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            snapshot_path: None,
+            seed_file: None,
+            default_page_size: 50,
+            max_page_size: 500,
+            default_groups: 100,
+            max_groups: 1000,
+            hidden_types: vec![],
+            max_rows: None,
+            max_archive_bytes: 100 * 1024 * 1024,
+            max_field_len: None,
+            oversized_fields: OversizedFields::default(),
+            control_characters: ControlCharacters::default(),
+            store_raw_rows: false,
+            upload_timeout: Duration::from_secs(60),
+            lock_timeout: Duration::from_secs(5),
+            list_cache_ttl: Duration::from_secs(1),
+            parse_threads: 1,
+            parse_pool: None,
+            soft_delete: false,
+            upload_field_name: "file".to_string(),
+            url_upload_max_bytes: 10 * 1024 * 1024,
+            compression_algorithms: vec![Encoding::Br, Encoding::Gzip, Encoding::Deflate],
+            compression_quality: CompressionLevel::Default,
+            log_format: LogFormat::Pretty,
+            timezone: Timezone::UTC,
+            stream_mode: StreamMode::Snapshot,
+            validation_rules_path: None,
+            validation_rules: ValidationRules::default(),
+            station_aliases_path: None,
+            station_aliases: StationAliases::default(),
+            property_types_path: None,
+            property_types: TypeMapping::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the settings from the environment.
+    ///
+    /// Starting up with a default in place of a setting that was mistyped
+    /// would be hard to notice, so invalid settings are an error instead.
+    pub fn from_env() -> Result<Self, String> {
+        Config::from_vars(|name| std::env::var_os(name))
+    }
+
+    /// Reads the settings from `var`, which looks up a variable by name
+    fn from_vars(var: impl Fn(&str) -> Option<OsString>) -> Result<Self, String> {
+        let defaults = Config::default();
+
+        // An empty page could never get the client anywhere,
+        // so the page sizes have to be at least one
+        let max_page_size = parse_env::<NonZeroUsize>(&var, "MAX_PAGE_SIZE")?
+            .map_or(defaults.max_page_size, usize::from);
+        let default_page_size = parse_env::<NonZeroUsize>(&var, "DEFAULT_PAGE_SIZE")?
+            .map_or(defaults.default_page_size, usize::from)
+            // The default shouldn't be something we'd have to clamp
+            .min(max_page_size);
+        // Likewise for the number of groups in an aggregation
+        let max_groups =
+            parse_env::<NonZeroUsize>(&var, "MAX_GROUPS")?.map_or(defaults.max_groups, usize::from);
+        let default_groups = parse_env::<NonZeroUsize>(&var, "DEFAULT_GROUPS")?
+            .map_or(defaults.default_groups, usize::from)
+            .min(max_groups);
+
+        Ok(Config {
+            snapshot_path: var("SNAPSHOT_PATH").map(PathBuf::from),
+            seed_file: var("SEED_FILE").map(PathBuf::from),
+            default_page_size,
+            max_page_size,
+            default_groups,
+            max_groups,
+            hidden_types: parse_env_with(&var, "HIDDEN_TYPES", property_type::parse_types)?
+                .unwrap_or(defaults.hidden_types),
+            max_rows: parse_env(&var, "MAX_ROWS")?,
+            max_archive_bytes: parse_env(&var, "MAX_ARCHIVE_BYTES")?
+                .unwrap_or(defaults.max_archive_bytes),
+            max_field_len: parse_env(&var, "MAX_FIELD_LEN")?,
+            oversized_fields: parse_env(&var, "OVERSIZED_FIELDS")?
+                .unwrap_or(defaults.oversized_fields),
+            control_characters: parse_env(&var, "CONTROL_CHARACTERS")?
+                .unwrap_or(defaults.control_characters),
+            store_raw_rows: parse_env(&var, "STORE_RAW_ROWS")?.unwrap_or(defaults.store_raw_rows),
+            upload_timeout: parse_env(&var, "UPLOAD_TIMEOUT_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.upload_timeout),
+            lock_timeout: parse_env(&var, "LOCK_TIMEOUT_MS")?
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.lock_timeout),
+            list_cache_ttl: parse_env(&var, "LIST_CACHE_TTL_MS")?
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.list_cache_ttl),
+            parse_threads: match parse_env(&var, "PARSE_THREADS")? {
+                Some(0) => std::thread::available_parallelism().map_or(1, usize::from),
+                Some(threads) => threads,
+                None => defaults.parse_threads,
+            },
+            parse_pool: defaults.parse_pool,
+            soft_delete: parse_env(&var, "SOFT_DELETE")?.unwrap_or(defaults.soft_delete),
+            upload_field_name: parse_env::<String>(&var, "FIELD_NAME")?
+                .filter(|name| !name.trim().is_empty())
+                .unwrap_or(defaults.upload_field_name),
+            url_upload_max_bytes: parse_env(&var, "URL_UPLOAD_MAX_BYTES")?
+                .unwrap_or(defaults.url_upload_max_bytes),
+            compression_algorithms: parse_env_with(
+                &var,
+                "COMPRESSION_ALGORITHMS",
+                compression::parse_encodings,
+            )?
+            .unwrap_or(defaults.compression_algorithms),
+            compression_quality: parse_env_with(&var, "COMPRESSION_QUALITY", |value| {
+                compression::parse_quality(value)
+                    .ok_or_else(|| "expected fastest, best, default, or a number".to_string())
+            })?
+            .unwrap_or(defaults.compression_quality),
+            log_format: parse_env(&var, "LOG_FORMAT")?.unwrap_or(defaults.log_format),
+            timezone: parse_env(&var, "TIMEZONE")?.unwrap_or(defaults.timezone),
+            stream_mode: parse_env(&var, "STREAM_MODE")?.unwrap_or(defaults.stream_mode),
+            validation_rules_path: var("VALIDATION_RULES").map(PathBuf::from),
+            validation_rules: defaults.validation_rules,
+            station_aliases_path: var("STATION_ALIASES").map(PathBuf::from),
+            station_aliases: defaults.station_aliases,
+            property_types_path: var("PROPERTY_TYPES").map(PathBuf::from),
+            property_types: defaults.property_types,
+        })
+    }
+}
+
+/// Reads and parses an environment variable with `var`,
+/// treating an unset variable as missing
+fn parse_env<T>(var: impl Fn(&str) -> Option<OsString>, name: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    parse_env_with(var, name, str::parse)
+}
+
+/// Reads an environment variable with `var`, and parses it with `parse`
+fn parse_env_with<T, E: Display>(
+    var: impl Fn(&str) -> Option<OsString>,
+    name: &str,
+    parse: impl FnOnce(&str) -> Result<T, E>,
+) -> Result<Option<T>, String> {
+    let Some(value) = var(name) else {
+        return Ok(None);
+    };
+    let value = value
+        .into_string()
+        .map_err(|value| format!("invalid {name} {value:?}: not valid UTF-8"))?;
+
+    parse(&value)
+        .map(Some)
+        .map_err(|error| format!("invalid {name} `{value}`: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Result<Config, String> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        Config::from_vars(|name| vars.get(name).map(OsString::from))
+    }
+
+    #[test]
+    fn uses_the_defaults_for_unset_settings() {
+        let config = from_vars(&[("MAX_ROWS", "10"), ("SOFT_DELETE", "true")]).unwrap();
+        assert_eq!(config.max_rows, Some(10));
+        assert!(config.soft_delete);
+        assert_eq!(config.max_page_size, Config::default().max_page_size);
+        assert_eq!(config.stream_mode, StreamMode::Snapshot);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let error = from_vars(&[("MAX_ROWS", "lots")]).unwrap_err();
+        assert_eq!(
+            error,
+            "invalid MAX_ROWS `lots`: invalid digit found in string"
+        );
+
+        for (name, value) in [
+            ("SOFT_DELETE", "yes please"),
+            ("TIMEZONE", "Mars"),
+            ("STREAM_MODE", "sometimes"),
+            ("MAX_FIELD_LEN", "-1"),
+            ("COMPRESSION_ALGORITHMS", "zstd"),
+            ("COMPRESSION_QUALITY", "high"),
+            ("LOG_FORMAT", "xml"),
+            ("HIDDEN_TYPES", "castle"),
+        ] {
+            let error = from_vars(&[(name, value)]).unwrap_err();
+            assert!(error.starts_with(&format!("invalid {name}")), "{error}");
+        }
+    }
+
+    #[test]
+    fn rejects_page_sizes_of_zero() {
+        assert!(from_vars(&[("MAX_PAGE_SIZE", "0")]).is_err());
+        assert!(from_vars(&[("DEFAULT_PAGE_SIZE", "0")]).is_err());
+    }
+
+    #[test]
+    fn keeps_the_default_number_of_groups_within_the_maximum() {
+        assert!(from_vars(&[("MAX_GROUPS", "0")]).is_err());
+        assert!(from_vars(&[("DEFAULT_GROUPS", "0")]).is_err());
+
+        let config = from_vars(&[("MAX_GROUPS", "10")]).unwrap();
+        assert_eq!(config.default_groups, 10);
+    }
+}
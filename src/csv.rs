@@ -0,0 +1,302 @@
+//! Splitting CSV text into records and fields
+//!
+//! Fields can be wrapped in quotes, so that they can hold commas and newlines.
+//! Most files use double quotes, with a quote inside a field written twice,
+//! but some tools write single quotes, or escape quotes with a backslash,
+//! so both characters can be configured.
+
+use std::{collections::HashMap, fmt, iter::Peekable, str::CharIndices};
+
+/// The characters a CSV file is written with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dialect {
+    /// The character that separates the fields in a record
+    pub delimiter: char,
+    /// The character that fields are wrapped in to hold special characters
+    pub quote: char,
+    /// The character that makes the next one part of the field.
+    /// When this is the same as the quote, a quote is escaped by doubling it.
+    pub escape: char,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect {
+            delimiter: ',',
+            quote: '"',
+            escape: '"',
+        }
+    }
+}
+
+/// A dialect option that isn't a single character
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidDialect {
+    pub option: &'static str,
+    pub value: String,
+}
+
+impl fmt::Display for InvalidDialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` must be a single character, found `{}`",
+            self.option, self.value
+        )
+    }
+}
+
+impl std::error::Error for InvalidDialect {}
+
+/// Parses a dialect option that has to be exactly one character
+pub fn single_char(option: &'static str, value: &str) -> Result<char, InvalidDialect> {
+    let mut chars = value.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(InvalidDialect {
+            option,
+            value: value.to_string(),
+        }),
+    }
+}
+
+/// The delimiters we can detect, in order of preference when it's a tie
+const DELIMITERS: [char; 3] = [',', '\t', ';'];
+
+/// How many lines from the header on we look at to detect the delimiter
+const SNIFF_LINES: usize = 5;
+
+/// Works out which delimiter the file uses, from the header row and the
+/// few lines after it. Whichever delimiter appears the same number of times
+/// on the most lines wins, so that a comma in a building name doesn't make
+/// a tab-separated file look like CSV. If none of them appear, it's a comma.
+pub fn sniff_delimiter(text: &str, quote: char, header_row: usize) -> char {
+    let lines: Vec<&str> = text
+        .lines()
+        .skip(header_row)
+        .filter(|line| !line.trim().is_empty())
+        .take(SNIFF_LINES)
+        .collect();
+
+    DELIMITERS
+        .into_iter()
+        .filter_map(|delimiter| {
+            // How many lines each number of delimiters was found on
+            let mut frequencies: HashMap<usize, usize> = HashMap::new();
+            for line in &lines {
+                match count_unquoted(line, delimiter, quote) {
+                    0 => {}
+                    count => *frequencies.entry(count).or_default() += 1,
+                }
+            }
+
+            frequencies
+                .into_iter()
+                .max_by_key(|(count, lines)| (*lines, *count))
+                .map(|(count, lines)| (delimiter, lines, count))
+        })
+        // `max_by_key` keeps the last of equal keys, so we go through them backwards
+        .rev()
+        .max_by_key(|(_, lines, count)| (*lines, *count))
+        .map_or(',', |(delimiter, _, _)| delimiter)
+}
+
+/// Counts how many times the character appears outside of quotes
+fn count_unquoted(line: &str, target: char, quote: char) -> usize {
+    let mut quoted = false;
+    let mut count = 0;
+
+    for c in line.chars() {
+        if c == quote {
+            quoted = !quoted;
+        } else if c == target && !quoted {
+            count += 1;
+        }
+    }
+
+    count
+}
+
+/// One record of a CSV file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub fields: Vec<String>,
+    /// The record as it was written, without its line ending
+    pub raw: &'a str,
+}
+
+/// Splits CSV text into records, one at a time as they're needed.
+///
+/// Records end with either `\n` or `\r\n`, and the last one doesn't need
+/// a line ending at all. Line endings inside quoted fields are kept as
+/// part of the field. A quote that's never closed runs to the end of the file.
+pub fn records(text: &str, dialect: Dialect) -> Records<'_> {
+    Records {
+        text,
+        dialect,
+        chars: text.char_indices().peekable(),
+        start: 0,
+    }
+}
+
+/// The records of some CSV text, from [`records`]
+#[derive(Debug, Clone)]
+pub struct Records<'a> {
+    text: &'a str,
+    dialect: Dialect,
+    chars: Peekable<CharIndices<'a>>,
+    /// Where the next record starts in the text
+    start: usize,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record<'a>;
+
+    fn next(&mut self) -> Option<Record<'a>> {
+        let text = self.text;
+        let dialect = self.dialect;
+        let doubled_quotes = dialect.escape == dialect.quote;
+
+        let mut fields = vec![];
+        let mut field = String::new();
+        let mut quoted = false;
+
+        while let Some((i, c)) = self.chars.next() {
+            if quoted {
+                if c == dialect.quote && doubled_quotes {
+                    // Two quotes in a row are a literal quote, otherwise it's the end
+                    if self
+                        .chars
+                        .next_if(|&(_, next)| next == dialect.quote)
+                        .is_some()
+                    {
+                        field.push(c);
+                    } else {
+                        quoted = false;
+                    }
+                } else if c == dialect.escape {
+                    field.extend(self.chars.next().map(|(_, next)| next));
+                } else if c == dialect.quote {
+                    quoted = false;
+                } else {
+                    field.push(c);
+                }
+
+                continue;
+            }
+
+            match c {
+                c if c == dialect.delimiter => fields.push(std::mem::take(&mut field)),
+                '\n' => {
+                    let raw = &text[self.start..i];
+                    fields.push(field);
+                    self.start = i + 1;
+                    return Some(end_record(fields, raw));
+                }
+                // Only the quote at the very start of a field opens a quoted field
+                c if c == dialect.quote && field.is_empty() => quoted = true,
+                c if c == dialect.escape && !doubled_quotes => {
+                    field.extend(self.chars.next().map(|(_, next)| next));
+                }
+                c => field.push(c),
+            }
+        }
+
+        if self.start < text.len() {
+            let raw = &text[self.start..];
+            self.start = text.len();
+            fields.push(field);
+            return Some(end_record(fields, raw));
+        }
+
+        None
+    }
+}
+
+/// Finishes a record, taking the `\r` off a `\r\n` line ending
+fn end_record(mut fields: Vec<String>, raw: &str) -> Record<'_> {
+    let Some(raw) = raw.strip_suffix('\r') else {
+        return Record { fields, raw };
+    };
+
+    if let Some(last) = fields.last_mut() {
+        if last.ends_with('\r') {
+            last.pop();
+        }
+    }
+
+    Record { fields, raw }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(text: &str, dialect: Dialect) -> Vec<Vec<String>> {
+        records(text, dialect).map(|record| record.fields).collect()
+    }
+
+    #[test]
+    fn reads_quoted_fields() {
+        let text = "a,\"b,\"\"c\"\"\",\"d\ne\"\r\nf,g";
+
+        assert_eq!(
+            fields(text, Dialect::default()),
+            [vec!["a", "b,\"c\"", "d\ne"], vec!["f", "g"]]
+        );
+        assert_eq!(
+            records(text, Dialect::default()).next().unwrap().raw,
+            "a,\"b,\"\"c\"\"\",\"d\ne\""
+        );
+    }
+
+    #[test]
+    fn reads_other_quote_and_escape_characters() {
+        let dialect = Dialect {
+            quote: '\'',
+            escape: '\\',
+            ..Dialect::default()
+        };
+
+        assert_eq!(
+            fields(r"'it\'s, here',a\,b,\\", dialect),
+            [vec!["it's, here", "a,b", "\\"]]
+        );
+    }
+
+    #[test]
+    fn runs_an_unclosed_quote_to_the_end() {
+        assert_eq!(fields("a,\"b\nc", Dialect::default()), [vec!["a", "b\nc"]]);
+    }
+
+    #[test]
+    fn takes_dialect_options_of_one_character() {
+        assert_eq!(single_char("quote", "'"), Ok('\''));
+        assert_eq!(
+            single_char("quote", "''"),
+            Err(InvalidDialect {
+                option: "quote",
+                value: "''".to_string(),
+            })
+        );
+        assert!(single_char("quote", "").is_err());
+    }
+
+    #[test]
+    fn sniffs_the_delimiter_from_the_first_lines() {
+        let quote = '"';
+
+        assert_eq!(sniff_delimiter("a,b,c\n1,2,3\n", quote, 0), ',');
+        assert_eq!(sniff_delimiter("a\tb\tc\n1\t2\t3\n", quote, 0), '\t');
+        assert_eq!(sniff_delimiter("a;b;c\n1;2;3\n", quote, 0), ';');
+        // A comma in one field doesn't outweigh the tabs on every line
+        assert_eq!(
+            sniff_delimiter("a\tb\tc\n1\t\"x, y\"\t3\n1\tx, y\t3\n", quote, 0),
+            '\t'
+        );
+        // Lines before the header don't count
+        assert_eq!(sniff_delimiter("title; notes\na,b\n1,2\n", quote, 1), ',');
+        assert_eq!(sniff_delimiter("abc\n", quote, 0), ',');
+    }
+}
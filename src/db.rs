@@ -0,0 +1,285 @@
+//! The in-memory db, split into shards that can be locked on their own
+//!
+//! Edits to a single property only lock the shard it's in, so edits to
+//! properties in different shards can happen at the same time. Anything
+//! that reads the whole db locks every shard, so it always sees the same
+//! data from start to finish.
+//!
+//! Each shard is kept behind an [`Arc`], so that a [`DbSnapshot`] of the
+//! whole db can be taken without copying it. A shard is only copied when
+//! it's edited while a snapshot still holds it.
+
+use std::{collections::btree_map, iter::Peekable, mem, sync::Arc};
+
+use tokio::sync::{RwLock, RwLockMappedWriteGuard, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::property::{Db, Property};
+
+/// How many shards the db is split into
+pub const SHARDS: usize = 16;
+
+/// The properties, stored by id across several separately locked shards
+#[derive(Debug)]
+pub struct ShardedDb {
+    /// Each property lives in the shard at its id modulo the number of shards.
+    /// Shards are always locked in this order, so that readers and writers
+    /// can't end up waiting on each other.
+    shards: Vec<RwLock<Arc<Db>>>,
+}
+
+impl ShardedDb {
+    pub fn new(db: Db) -> Self {
+        let mut shards: Vec<Db> = (0..SHARDS).map(|_| Db::new()).collect();
+
+        for (id, property) in db {
+            shards[id % SHARDS].insert(id, property);
+        }
+
+        ShardedDb {
+            shards: shards
+                .into_iter()
+                .map(|shard| RwLock::new(Arc::new(shard)))
+                .collect(),
+        }
+    }
+
+    /// Locks every shard for reading, for a consistent view of the whole db
+    pub async fn read(&self) -> DbView<'_> {
+        let mut shards = Vec::with_capacity(self.shards.len());
+
+        for shard in &self.shards {
+            shards.push(shard.read().await);
+        }
+
+        DbView { shards }
+    }
+
+    /// The whole db as it is now, which can be read from without holding
+    /// any locks. Later edits don't show up in it.
+    pub async fn snapshot(&self) -> DbSnapshot {
+        DbSnapshot {
+            shards: self
+                .read()
+                .await
+                .shards
+                .iter()
+                .map(|shard| Arc::clone(shard))
+                .collect(),
+        }
+    }
+
+    /// Locks the shard that the property with this id would be in,
+    /// whether or not the property exists
+    pub async fn write(&self, id: usize) -> RwLockMappedWriteGuard<'_, Db> {
+        let shard = self.shards[id % self.shards.len()].write().await;
+        RwLockWriteGuard::map(shard, Arc::make_mut)
+    }
+
+    /// Adds a property, or replaces the one with the same id.
+    /// This needs exclusive access, so it doesn't have to lock anything.
+    pub fn insert(&mut self, property: Property) {
+        let shard = property.id % self.shards.len();
+        Arc::make_mut(self.shards[shard].get_mut()).insert(property.id, property);
+    }
+
+    /// Takes all of the properties out of the db, leaving it empty.
+    ///
+    /// This needs exclusive access, so it's used for changes to the whole db,
+    /// like uploads, which put the properties back with [`ShardedDb::new`].
+    pub fn take(&mut self) -> Db {
+        self.shards
+            .iter_mut()
+            .flat_map(|shard| Arc::unwrap_or_clone(mem::take(shard.get_mut())))
+            .collect()
+    }
+}
+
+impl Default for ShardedDb {
+    fn default() -> Self {
+        ShardedDb::new(Db::new())
+    }
+}
+
+impl From<Db> for ShardedDb {
+    fn from(db: Db) -> Self {
+        ShardedDb::new(db)
+    }
+}
+
+/// Every shard of the db, locked for reading
+pub struct DbView<'a> {
+    shards: Vec<RwLockReadGuard<'a, Arc<Db>>>,
+}
+
+impl DbView<'_> {
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.is_empty())
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Property> {
+        self.shards[id % self.shards.len()].get(&id)
+    }
+
+    /// The highest id in the db, if there are any properties
+    pub fn last_id(&self) -> Option<usize> {
+        self.shards
+            .iter()
+            .filter_map(|shard| shard.keys().next_back())
+            .max()
+            .copied()
+    }
+
+    /// Every property, in id order, just like iterating over a single map
+    pub fn values(&self) -> Values<'_> {
+        self.values_from(0)
+    }
+
+    /// The properties with this id or higher, in id order
+    pub fn values_from(&self, id: usize) -> Values<'_> {
+        Values {
+            shards: self
+                .shards
+                .iter()
+                .map(|shard| shard.range(id..).peekable())
+                .collect(),
+        }
+    }
+}
+
+/// Every shard of the db, as it was when [`ShardedDb::snapshot`] was called
+#[derive(Debug, Clone)]
+pub struct DbSnapshot {
+    shards: Vec<Arc<Db>>,
+}
+
+impl DbSnapshot {
+    pub fn get(&self, id: usize) -> Option<&Property> {
+        self.shards[id % self.shards.len()].get(&id)
+    }
+
+    /// The properties with this id or higher, in id order
+    pub fn values_from(&self, id: usize) -> Values<'_> {
+        Values {
+            shards: self
+                .shards
+                .iter()
+                .map(|shard| shard.range(id..).peekable())
+                .collect(),
+        }
+    }
+}
+
+/// The properties of every shard, merged back into id order
+#[derive(Clone)]
+pub struct Values<'a> {
+    shards: Vec<Peekable<btree_map::Range<'a, usize, Property>>>,
+}
+
+impl<'a> Iterator for Values<'a> {
+    type Item = &'a Property;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Each shard is already in id order, so the next property
+        // is whichever one has the lowest id out of the shards' next ones
+        let next = self
+            .shards
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, shard)| shard.peek().map(|(id, _)| (i, **id)))
+            .min_by_key(|(_, id)| *id)
+            .map(|(i, _)| i)?;
+
+        self.shards[next].next().map(|(_, property)| property)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    use super::*;
+
+    fn db(ids: impl IntoIterator<Item = usize>) -> ShardedDb {
+        ids.into_iter()
+            .map(|id| {
+                let property = Property::from_input(
+                    id,
+                    PropertyInput::default(),
+                    &TypeMapping::default(),
+                    &StationAliases::default(),
+                );
+                (id, property)
+            })
+            .collect::<Db>()
+            .into()
+    }
+
+    #[tokio::test]
+    async fn reads_every_shard_back_in_id_order() {
+        let ids = [1, 2, 15, 16, 17, 33, 100];
+        let mut db = db(ids);
+
+        let view = db.read().await;
+        assert_eq!(view.len(), ids.len());
+        assert_eq!(view.last_id(), Some(100));
+        assert_eq!(
+            view.values()
+                .map(|property| property.id)
+                .collect::<Vec<_>>(),
+            ids
+        );
+        assert_eq!(
+            view.values_from(16)
+                .map(|property| property.id)
+                .collect::<Vec<_>>(),
+            [16, 17, 33, 100]
+        );
+        assert_eq!(view.get(33).map(|property| property.id), Some(33));
+        assert!(view.get(34).is_none());
+        drop(view);
+
+        assert_eq!(db.take().into_keys().collect::<Vec<_>>(), ids);
+        assert!(db.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_locks_one_shard_for_an_edit() {
+        let db = db([1, 2]);
+        let wait = Duration::from_millis(50);
+
+        let shard = db.write(1).await;
+        assert!(tokio::time::timeout(wait, db.write(2)).await.is_ok());
+        // The same shard, and reads of the whole db, have to wait
+        assert!(tokio::time::timeout(wait, db.write(1 + SHARDS))
+            .await
+            .is_err());
+        assert!(tokio::time::timeout(wait, db.read()).await.is_err());
+
+        drop(shard);
+        assert!(tokio::time::timeout(wait, db.read()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn keeps_a_snapshot_as_it_was_taken() {
+        let mut db = db([1, 2]);
+        let snapshot = db.snapshot().await;
+
+        db.write(1).await.remove(&1);
+        db.insert(Property {
+            id: 3,
+            ..snapshot.get(2).unwrap().clone()
+        });
+
+        let ids = |values: Values| values.map(|property| property.id).collect::<Vec<_>>();
+        assert_eq!(ids(snapshot.values_from(0)), [1, 2]);
+        assert_eq!(ids(db.read().await.values()), [2, 3]);
+        // Shards that weren't edited are still shared with the snapshot
+        assert!(Arc::ptr_eq(&snapshot.shards[2], &db.read().await.shards[2]));
+    }
+}
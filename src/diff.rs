@@ -0,0 +1,144 @@
+//! Comparing two datasets to see what changed between them
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::property::Property;
+
+/// The properties that were added, removed, or changed between two datasets
+#[derive(Debug, Serialize)]
+pub struct Diff<P> {
+    pub added: Vec<P>,
+    pub removed: Vec<P>,
+    pub changed: Vec<Change<P>>,
+}
+
+/// A property that exists in both datasets, but with different data
+#[derive(Debug, Serialize)]
+pub struct Change<P> {
+    pub before: P,
+    pub after: P,
+}
+
+impl<P> Diff<P> {
+    /// Converts each of the properties, such as into views for a response
+    pub fn map<Q>(self, mut f: impl FnMut(P) -> Q) -> Diff<Q> {
+        Diff {
+            added: self.added.into_iter().map(&mut f).collect(),
+            removed: self.removed.into_iter().map(&mut f).collect(),
+            changed: self
+                .changed
+                .into_iter()
+                .map(|change| Change {
+                    before: f(change.before),
+                    after: f(change.after),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Compares two datasets, matching properties up by their address.
+///
+/// Ids are assigned by row number, so they aren't stable between uploads.
+/// The address, on the other hand, stays the same from one monthly export
+/// to the next, even if the price or the row order changes.
+pub fn diff<'a>(
+    before: impl IntoIterator<Item = &'a Property>,
+    after: impl IntoIterator<Item = &'a Property>,
+) -> Diff<&'a Property> {
+    let before: HashMap<_, _> = before
+        .into_iter()
+        .map(|property| (property.address_key(), property))
+        .collect();
+    let after: HashMap<_, _> = after
+        .into_iter()
+        .map(|property| (property.address_key(), property))
+        .collect();
+
+    let mut added: Vec<_> = after
+        .iter()
+        .filter(|(key, _)| !before.contains_key(*key))
+        .map(|(_, property)| *property)
+        .collect();
+
+    let mut removed: Vec<_> = before
+        .iter()
+        .filter(|(key, _)| !after.contains_key(*key))
+        .map(|(_, property)| *property)
+        .collect();
+
+    let mut changed: Vec<_> = after
+        .iter()
+        .filter_map(|(key, after)| {
+            let before = before.get(key)?;
+            (before.content_key() != after.content_key()).then_some(Change {
+                before: *before,
+                after: *after,
+            })
+        })
+        .collect();
+
+    added.sort_unstable_by_key(|property| property.id);
+    removed.sort_unstable_by_key(|property| property.id);
+    changed.sort_unstable_by_key(|change| change.after.id);
+
+    Diff {
+        added,
+        removed,
+        changed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    use super::*;
+
+    fn property(id: usize, town: &str, price: &str) -> Property {
+        let input = PropertyInput {
+            prefecture: "東京都".to_string(),
+            city: "渋谷区".to_string(),
+            town: town.to_string(),
+            price: price.to_string(),
+            ..Default::default()
+        };
+        Property::from_input(
+            id,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        )
+    }
+
+    #[test]
+    fn matches_properties_by_address() {
+        let before = [
+            property(1, "神南", "1000万円"),
+            property(2, "宇田川町", "2000万円"),
+            property(3, "道玄坂", "3000万円"),
+        ];
+        // The rows moved around, so the ids don't line up with the ones before
+        let after = [
+            property(1, "道玄坂", "3000万円"),
+            property(2, "神南", "1500万円"),
+            property(3, "桜丘町", "4000万円"),
+        ];
+
+        let diff = diff(&before, &after);
+
+        let towns = |properties: &[&Property]| -> Vec<String> {
+            properties
+                .iter()
+                .map(|property| property.town.clone())
+                .collect()
+        };
+        assert_eq!(towns(&diff.added), ["桜丘町"]);
+        assert_eq!(towns(&diff.removed), ["宇田川町"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].before.price, "1000万円");
+        assert_eq!(diff.changed[0].after.price, "1500万円");
+    }
+}
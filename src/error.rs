@@ -0,0 +1,135 @@
+//! A shared error type, so that every handler reports errors the same way
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{fetch::FetchError, import::ImportError};
+
+/// An error response, serialized as JSON in the shape:
+/// `{ "error": { "code": "not_found", "message": "Property not found" } }`
+///
+/// The `code` is a stable, machine-readable identifier,
+/// while the `message` is meant for humans.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    /// Extra data about the error, such as which rows of a file were invalid
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    /// Attaches extra data to the error, sent as a `details` field
+    pub fn with_details(mut self, details: impl Serialize) -> Self {
+        self.details = serde_json::to_value(details).ok();
+        self
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::BAD_REQUEST, code, message)
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: ErrorDetails<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetails<'a> {
+    code: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<&'a serde_json::Value>,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: ErrorDetails {
+                code: self.code,
+                message: &self.message,
+                details: self.details.as_ref(),
+            },
+        };
+
+        (self.status, Json(body)).into_response()
+    }
+}
+
+impl From<ImportError> for ApiError {
+    fn from(error: ImportError) -> Self {
+        match error {
+            ImportError::TooManyRows(_) => ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "too_many_rows",
+                error.to_string(),
+            ),
+            ImportError::InvalidWorkbook(_) => {
+                ApiError::bad_request("invalid_workbook", error.to_string())
+            }
+            ImportError::InvalidArchive(_) => {
+                ApiError::bad_request("invalid_archive", error.to_string())
+            }
+            ImportError::ArchiveTooLarge(_) => ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "archive_too_large",
+                error.to_string(),
+            ),
+            ImportError::SheetNotFound { .. } => {
+                ApiError::bad_request("sheet_not_found", error.to_string())
+            }
+            ImportError::InvalidRows(ref rows) => ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_rows",
+                error.to_string(),
+            )
+            .with_details(rows),
+            _ => ApiError::bad_request("invalid_csv", error.to_string()),
+        }
+    }
+}
+
+impl From<FetchError> for ApiError {
+    fn from(error: FetchError) -> Self {
+        match error {
+            FetchError::InvalidUrl(_) | FetchError::UnsupportedScheme(_) => {
+                ApiError::bad_request("invalid_url", error.to_string())
+            }
+            FetchError::ForbiddenAddress(_) => {
+                ApiError::bad_request("forbidden_url", error.to_string())
+            }
+            FetchError::TooLarge(_) => ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "file_too_large",
+                error.to_string(),
+            ),
+            FetchError::Timeout => ApiError::new(
+                StatusCode::GATEWAY_TIMEOUT,
+                "fetch_timeout",
+                error.to_string(),
+            ),
+            FetchError::Status(_) | FetchError::TooManyRedirects | FetchError::Request(_) => {
+                ApiError::new(StatusCode::BAD_GATEWAY, "fetch_failed", error.to_string())
+            }
+        }
+    }
+}
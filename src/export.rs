@@ -0,0 +1,846 @@
+//! Exporting property data as CSV files, fixed-width text files,
+//! or as MessagePack for syncing clients
+
+use std::{
+    borrow::Borrow,
+    fmt::{self, Write},
+    iter,
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+use crate::property::{Property, ViewOptions};
+
+/// The columns of an exported file, in order.
+/// These match the field names in our JSON output.
+pub const COLUMNS: [&str; 12] = [
+    "id",
+    "prefecture",
+    "city",
+    "town",
+    "chome",
+    "banchi",
+    "go",
+    "building",
+    "price",
+    "nearest_station",
+    "property_type",
+    "land_area",
+];
+
+/// Options for how the CSV file is written.
+/// These can be deserialized directly from a request's query string.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportOptions {
+    /// Whether the last row ends with a newline. RFC 4180 allows either,
+    /// but some tools insist on one or the other.
+    #[serde(default = "default_true")]
+    pub trailing_newline: bool,
+    /// How to defuse values that a spreadsheet would run as a formula
+    #[serde(default)]
+    pub formula_escape: FormulaEscape,
+    /// A comma-separated list of the columns to include, in the order they
+    /// should appear, such as `id,prefecture,price`. Defaults to all of them.
+    pub columns: Option<String>,
+    /// Whether to mask the parts of the address that pinpoint each property,
+    /// the same as in the JSON output
+    #[serde(default)]
+    pub mask_address: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            trailing_newline: true,
+            formula_escape: FormulaEscape::default(),
+            columns: None,
+            mask_address: false,
+        }
+    }
+}
+
+/// Spreadsheet apps treat a cell starting with `=`, `+`, `-`, or `@` as a
+/// formula, so a malicious value in an uploaded file could run code on the
+/// machine of whoever opens the export. This is known as CSV injection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FormulaEscape {
+    /// Prefix the value with a single quote, so it's shown as text
+    #[default]
+    Quote,
+    /// Leave the value as it is, for tools that read the raw data
+    None,
+}
+
+/// The characters that can start a formula. Tabs and carriage returns
+/// are included too, since some apps strip them before checking.
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+fn default_true() -> bool {
+    true
+}
+
+/// A requested column that isn't one of [`COLUMNS`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownColumn(pub String);
+
+impl fmt::Display for UnknownColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown column `{}`, the available columns are: {}",
+            self.0,
+            COLUMNS.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownColumn {}
+
+/// The reasons the requested columns can't be exported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidColumns {
+    Unknown(UnknownColumn),
+    /// The list of columns was given, but didn't name any
+    Empty,
+}
+
+impl fmt::Display for InvalidColumns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidColumns::Unknown(error) => error.fmt(f),
+            InvalidColumns::Empty => write!(
+                f,
+                "no columns were given, the available columns are: {}",
+                COLUMNS.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidColumns {}
+
+impl ExportOptions {
+    /// Works out the position in [`COLUMNS`] of each column to export
+    fn column_indices(&self) -> Result<Vec<usize>, InvalidColumns> {
+        let Some(columns) = &self.columns else {
+            return Ok((0..COLUMNS.len()).collect());
+        };
+
+        let indices: Vec<usize> = columns
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                COLUMNS
+                    .iter()
+                    .position(|column| *column == name)
+                    .ok_or_else(|| InvalidColumns::Unknown(UnknownColumn(name.to_string())))
+            })
+            .collect::<Result<_, _>>()?;
+
+        // A file without any columns would only be blank lines
+        if indices.is_empty() {
+            return Err(InvalidColumns::Empty);
+        }
+
+        Ok(indices)
+    }
+}
+
+/// What a streamed export shows when the data changes partway through it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StreamMode {
+    /// The export reads from a snapshot of the db taken when it starts,
+    /// so it's the data from one point in time, no matter what changes while
+    /// it's sent. The snapshot shares the data with the db, and only the
+    /// parts that are edited during the export are ever copied.
+    #[default]
+    Snapshot,
+    /// The export is read from the db a batch at a time as it's sent,
+    /// so that it doesn't need a copy of all the data.
+    ///
+    /// Properties are still sent in id order, each one at most once, and
+    /// each as it was at some point during the export, never half-changed.
+    /// Changes to properties that haven't been sent yet show up in the export,
+    /// including new properties with higher ids, and ones that were deleted.
+    Live,
+}
+
+impl FromStr for StreamMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "snapshot" => Ok(StreamMode::Snapshot),
+            "live" => Ok(StreamMode::Live),
+            other => Err(format!("unknown stream mode `{other}`")),
+        }
+    }
+}
+
+/// Writes the parts of a CSV export, with the columns it was asked for
+#[derive(Debug, Clone)]
+pub struct CsvWriter {
+    indices: Vec<usize>,
+    formula_escape: FormulaEscape,
+    trailing_newline: bool,
+    mask_address: bool,
+}
+
+impl CsvWriter {
+    pub fn new(options: &ExportOptions) -> Result<Self, InvalidColumns> {
+        Ok(CsvWriter {
+            indices: options.column_indices()?,
+            formula_escape: options.formula_escape,
+            trailing_newline: options.trailing_newline,
+            mask_address: options.mask_address,
+        })
+    }
+
+    pub fn header(&self) -> String {
+        // We wrote the header ourselves, so there's nothing to escape
+        let mut header = String::new();
+        write_row(
+            &mut header,
+            self.indices.iter().map(|&i| COLUMNS[i]),
+            FormulaEscape::None,
+        );
+
+        header
+    }
+
+    /// Writes one property's row.
+    ///
+    /// Each row starts with the newline that ends the one before it,
+    /// so that we can leave the last newline off without backtracking.
+    pub fn row(&self, property: &Property) -> String {
+        let property = property.masked_if(self.mask_address);
+        let id = property.id.to_string();
+        let values = column_values(&property, &id);
+
+        let mut row = String::from("\n");
+        write_row(
+            &mut row,
+            self.indices.iter().map(|&i| values[i]),
+            self.formula_escape,
+        );
+        row
+    }
+
+    /// What comes after the last row, if anything
+    pub fn end(&self) -> Option<String> {
+        self.trailing_newline.then(|| String::from("\n"))
+    }
+}
+
+/// The value of each of the [`COLUMNS`] for a property, in the same order
+fn column_values<'a>(property: &'a Property, id: &'a str) -> [&'a str; COLUMNS.len()] {
+    // NOTE: These must be in the same order as `COLUMNS`
+    [
+        id,
+        &property.prefecture,
+        &property.city,
+        &property.town,
+        &property.chome,
+        &property.banchi,
+        &property.go,
+        &property.building,
+        &property.price,
+        &property.nearest_station,
+        &property.property_type,
+        &property.land_area,
+    ]
+}
+
+/// The width of each of the [`COLUMNS`] in a fixed-width export,
+/// when a client doesn't give its own
+pub const DEFAULT_WIDTHS: [usize; COLUMNS.len()] = [8, 4, 10, 12, 3, 4, 4, 20, 12, 10, 8, 8];
+
+/// The widest a column can be, so that a client can't ask for records
+/// so long that the export runs out of memory
+pub const MAX_WIDTH: usize = 1024;
+
+/// Which columns go in a fixed-width export, in order, and how many
+/// characters wide each one is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedWidthSpec {
+    columns: Vec<(usize, usize)>,
+}
+
+impl Default for FixedWidthSpec {
+    fn default() -> Self {
+        FixedWidthSpec {
+            columns: DEFAULT_WIDTHS.into_iter().enumerate().collect(),
+        }
+    }
+}
+
+/// The reasons a fixed-width spec can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidWidthSpec {
+    UnknownColumn(UnknownColumn),
+    InvalidWidth {
+        column: String,
+        width: String,
+    },
+    /// Each column can only be included once
+    DuplicateColumn(String),
+    /// A spec without any columns would write empty lines
+    Empty,
+}
+
+impl fmt::Display for InvalidWidthSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidWidthSpec::UnknownColumn(error) => error.fmt(f),
+            InvalidWidthSpec::InvalidWidth { column, width } => write!(
+                f,
+                "invalid width `{width}` for column `{column}`, expected a number from 1 to {MAX_WIDTH}"
+            ),
+            InvalidWidthSpec::DuplicateColumn(column) => {
+                write!(f, "column `{column}` is included more than once")
+            }
+            InvalidWidthSpec::Empty => write!(
+                f,
+                "no columns were given, the available columns are: {}",
+                COLUMNS.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidWidthSpec {}
+
+impl FromStr for FixedWidthSpec {
+    type Err = InvalidWidthSpec;
+
+    /// Parses a comma-separated list of columns and their widths,
+    /// like `id:8,prefecture:4,price:12`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let columns: Vec<(usize, usize)> = s
+            .split(',')
+            .map(str::trim)
+            .filter(|column| !column.is_empty())
+            .map(|column| {
+                let (name, width) = column.split_once(':').unwrap_or((column, ""));
+                let (name, width) = (name.trim(), width.trim());
+
+                let index = COLUMNS
+                    .iter()
+                    .position(|column| *column == name)
+                    .ok_or_else(|| {
+                        InvalidWidthSpec::UnknownColumn(UnknownColumn(name.to_string()))
+                    })?;
+
+                let width = width
+                    .parse()
+                    .ok()
+                    .filter(|width| (1..=MAX_WIDTH).contains(width))
+                    .ok_or_else(|| InvalidWidthSpec::InvalidWidth {
+                        column: name.to_string(),
+                        width: width.to_string(),
+                    })?;
+
+                Ok((index, width))
+            })
+            .collect::<Result<_, _>>()?;
+
+        if columns.is_empty() {
+            return Err(InvalidWidthSpec::Empty);
+        }
+
+        for (i, (index, _)) in columns.iter().enumerate() {
+            if columns[..i].iter().any(|(earlier, _)| earlier == index) {
+                return Err(InvalidWidthSpec::DuplicateColumn(
+                    COLUMNS[*index].to_string(),
+                ));
+            }
+        }
+
+        Ok(FixedWidthSpec { columns })
+    }
+}
+
+/// Writes the properties out as fixed-width records, for older systems that
+/// read each field from the same position on every line.
+///
+/// Each field is padded with spaces on the right to the width of its column,
+/// or cut short if it's longer, and the fields are joined without any
+/// delimiters. Widths are counted in characters, not bytes. Line breaks in
+/// values are replaced with spaces, so that every record is one line.
+/// There's no header, and every record ends with a newline.
+pub fn write_fixed_width<P: Borrow<Property>>(
+    properties: impl IntoIterator<Item = P>,
+    spec: &FixedWidthSpec,
+) -> String {
+    let mut text = String::new();
+
+    for property in properties {
+        let property = property.borrow();
+        let id = property.id.to_string();
+        let values = column_values(property, &id);
+
+        for &(index, width) in &spec.columns {
+            let mut written = 0;
+
+            for c in values[index].chars().take(width) {
+                text.push(if matches!(c, '\n' | '\r') { ' ' } else { c });
+                written += 1;
+            }
+
+            text.extend(iter::repeat_n(' ', width - written));
+        }
+
+        text.push('\n');
+    }
+
+    text
+}
+
+/// Writes the properties out as CSV text, starting with a header row
+pub fn write_csv<'a>(
+    properties: impl IntoIterator<Item = &'a Property>,
+    options: &ExportOptions,
+) -> Result<String, InvalidColumns> {
+    Ok(csv_chunks(properties, options, 0)?.collect())
+}
+
+/// Writes the properties out as CSV text a piece at a time, so that a large
+/// export can be streamed without building the whole file in memory.
+///
+/// Rows are grouped into chunks of at least `chunk_size` bytes,
+/// so that we don't send lots of tiny pieces.
+pub fn csv_chunks<P: Borrow<Property>>(
+    properties: impl IntoIterator<Item = P>,
+    options: &ExportOptions,
+    chunk_size: usize,
+) -> Result<impl Iterator<Item = String>, InvalidColumns> {
+    let writer = CsvWriter::new(options)?;
+    let header = writer.header();
+    let end = writer.end();
+
+    let rows = properties
+        .into_iter()
+        .map(move |property| writer.row(property.borrow()));
+
+    Ok(Chunks {
+        lines: iter::once(header).chain(rows).chain(end),
+        chunk_size,
+    })
+}
+
+/// The content type of a MessagePack export
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+/// Writes the properties out as a sequence of MessagePack maps, one after
+/// another, a piece at a time. Each map has the same fields as our JSON output,
+/// so clients can decode them with the same types.
+///
+/// Properties are grouped into chunks of at least `chunk_size` bytes.
+/// The chunks are only encoded as they're asked for, so a slow client
+/// doesn't make us buffer the rest of the export.
+pub fn msgpack_chunks<P: Borrow<Property>>(
+    properties: impl IntoIterator<Item = P>,
+    options: ViewOptions,
+    chunk_size: usize,
+) -> impl Iterator<Item = Vec<u8>> {
+    let mut properties = properties.into_iter().peekable();
+
+    iter::from_fn(move || {
+        properties.peek()?;
+
+        let mut chunk = Vec::new();
+        while chunk.len() < chunk_size.max(1) {
+            let Some(property) = properties.next() else {
+                break;
+            };
+
+            // Writing to a Vec can't fail, and neither can serializing a property
+            rmp_serde::encode::write_named(&mut chunk, &property.borrow().view(&options))
+                .expect("properties can always be encoded");
+        }
+
+        Some(chunk)
+    })
+}
+
+/// Groups lines of text together into chunks of at least a minimum size
+struct Chunks<I> {
+    lines: I,
+    chunk_size: usize,
+}
+
+impl<I: Iterator<Item = String>> Iterator for Chunks<I> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let mut chunk = self.lines.next()?;
+
+        while chunk.len() < self.chunk_size {
+            match self.lines.next() {
+                Some(line) => chunk.push_str(&line),
+                None => break,
+            }
+        }
+
+        Some(chunk)
+    }
+}
+
+fn write_row<'a>(
+    csv: &mut String,
+    values: impl IntoIterator<Item = &'a str>,
+    formula_escape: FormulaEscape,
+) {
+    for (i, value) in values.into_iter().enumerate() {
+        if i > 0 {
+            csv.push(',');
+        }
+
+        write_field(csv, value, formula_escape);
+    }
+}
+
+/// Writes a single field, quoting it if it contains anything that
+/// would otherwise break the row apart, as described in RFC 4180
+fn write_field(csv: &mut String, value: &str, formula_escape: FormulaEscape) {
+    let escaped;
+    let value = match formula_escape {
+        FormulaEscape::Quote if value.starts_with(FORMULA_PREFIXES) => {
+            escaped = format!("'{value}");
+            &escaped
+        }
+        _ => value,
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        // Writing to a String can't fail
+        let _ = write!(csv, "\"{}\"", value.replace('"', "\"\""));
+    } else {
+        csv.push_str(value);
+    }
+}
+
+/// Fills in a template for an export's filename, such as
+/// `properties_{prefecture}_{date}.csv`.
+///
+/// `{prefecture}` is replaced with the prefecture the export was filtered to,
+/// or `all` if it wasn't, and `{date}` with the date, like `2024-06-01`.
+/// The result is sanitized, and given a `.csv` extension if it doesn't have one.
+pub fn render_filename(template: &str, prefecture: Option<&str>, date: &str) -> String {
+    let filename = template
+        .replace("{prefecture}", prefecture.unwrap_or("all"))
+        .replace("{date}", date);
+
+    let filename = sanitize_filename(&filename);
+
+    match filename.as_str() {
+        "" | ".csv" => "properties.csv".to_string(),
+        _ if filename.to_ascii_lowercase().ends_with(".csv") => filename,
+        _ => filename + ".csv",
+    }
+}
+
+/// Strips out anything from a filename that could break the header it goes in,
+/// like quotes or newlines, or that could be read as a path, like slashes
+pub fn sanitize_filename(filename: &str) -> String {
+    filename
+        .chars()
+        .filter(|c| {
+            !c.is_control()
+                && !matches!(
+                    c,
+                    '"' | '\\' | '/' | ';' | ':' | '*' | '?' | '<' | '>' | '|'
+                )
+        })
+        .collect::<String>()
+        .trim()
+        .trim_start_matches('.')
+        .to_string()
+}
+
+/// Builds a `Content-Disposition` header value that downloads a file.
+///
+/// Header values need to be ASCII, so names like `properties_東京都.csv`
+/// are percent-encoded into the `filename*` parameter, as described in
+/// RFC 6266. Older clients that don't understand it use the ASCII fallback.
+pub fn content_disposition(filename: &str, fallback: &str) -> String {
+    let fallback: String = fallback
+        .chars()
+        .filter(|c| c.is_ascii_graphic() && *c != '"' && *c != '\\')
+        .collect();
+
+    if filename.is_ascii() && filename == fallback {
+        return format!("attachment; filename=\"{fallback}\"");
+    }
+
+    let mut encoded = String::new();
+
+    for byte in filename.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => {
+                let _ = write!(encoded, "%{byte:02X}");
+            }
+        }
+    }
+
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    use super::*;
+
+    #[test]
+    fn parses_a_width_spec() {
+        let spec: FixedWidthSpec = "id:3, price:6".parse().unwrap();
+
+        assert_eq!(spec.columns, vec![(0, 3), (8, 6)]);
+    }
+
+    #[test]
+    fn rejects_widths_out_of_range() {
+        for width in ["0", "1025", "abc", ""] {
+            assert_eq!(
+                format!("id:{width}").parse::<FixedWidthSpec>(),
+                Err(InvalidWidthSpec::InvalidWidth {
+                    column: "id".to_string(),
+                    width: width.to_string(),
+                })
+            );
+        }
+
+        assert!(format!("id:{MAX_WIDTH}").parse::<FixedWidthSpec>().is_ok());
+    }
+
+    #[test]
+    fn rejects_repeated_columns() {
+        assert_eq!(
+            "id:4,price:4,id:4".parse::<FixedWidthSpec>(),
+            Err(InvalidWidthSpec::DuplicateColumn("id".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_a_spec_without_any_columns() {
+        for spec in ["", " , "] {
+            assert_eq!(spec.parse::<FixedWidthSpec>(), Err(InvalidWidthSpec::Empty));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_columns() {
+        assert_eq!(
+            "rent:4".parse::<FixedWidthSpec>(),
+            Err(InvalidWidthSpec::UnknownColumn(UnknownColumn(
+                "rent".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn pads_and_cuts_fields_to_their_width() {
+        let input = PropertyInput {
+            prefecture: "東京都".to_string(),
+            price: "1000万円\n".to_string(),
+            ..Default::default()
+        };
+        let property = Property::from_input(
+            7,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        );
+        let spec = "id:3,prefecture:2,price:8".parse().unwrap();
+
+        assert_eq!(write_fixed_width([&property], &spec), "7  東京1000万円  \n");
+    }
+
+    fn property(id: usize, input: PropertyInput) -> Property {
+        Property::from_input(
+            id,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        )
+    }
+
+    fn with_town(id: usize, town: &str) -> Property {
+        let input = PropertyInput {
+            town: town.to_string(),
+            ..Default::default()
+        };
+        property(id, input)
+    }
+
+    #[test]
+    fn ends_the_last_row_with_a_newline_only_when_asked() {
+        let properties = [with_town(1, "神南"), with_town(2, "梅田")];
+        let options = ExportOptions {
+            columns: Some("id,town".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            write_csv(&properties, &options).unwrap(),
+            "id,town\n1,神南\n2,梅田\n"
+        );
+
+        let options = ExportOptions {
+            trailing_newline: false,
+            ..options
+        };
+        assert_eq!(
+            write_csv(&properties, &options).unwrap(),
+            "id,town\n1,神南\n2,梅田"
+        );
+        assert_eq!(write_csv([], &options).unwrap(), "id,town");
+    }
+
+    #[test]
+    fn defuses_values_that_look_like_formulas() {
+        let properties = [
+            with_town(1, "=HYPERLINK(\"http://evil\")"),
+            with_town(2, "+81"),
+            with_town(3, "@SUM(1)"),
+            with_town(4, "神南-1"),
+        ];
+        let options = ExportOptions {
+            columns: Some("town".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            write_csv(&properties, &options).unwrap(),
+            "town\n\"'=HYPERLINK(\"\"http://evil\"\")\"\n'+81\n'@SUM(1)\n神南-1\n"
+        );
+
+        let options = ExportOptions {
+            formula_escape: FormulaEscape::None,
+            ..options
+        };
+        assert_eq!(
+            write_csv(&properties[1..3], &options).unwrap(),
+            "town\n+81\n@SUM(1)\n"
+        );
+    }
+
+    #[test]
+    fn percent_encodes_filenames_that_are_not_ascii() {
+        assert_eq!(
+            content_disposition("properties.csv", "properties.csv"),
+            "attachment; filename=\"properties.csv\""
+        );
+        assert_eq!(
+            content_disposition("物件.csv", "properties.csv"),
+            "attachment; filename=\"properties.csv\"; filename*=UTF-8''%E7%89%A9%E4%BB%B6.csv"
+        );
+    }
+
+    #[test]
+    fn exports_only_the_columns_asked_for() {
+        let input = PropertyInput {
+            town: "神南".to_string(),
+            price: "1000万円".to_string(),
+            ..Default::default()
+        };
+        let options = ExportOptions {
+            columns: Some(" price, id ,town,".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            write_csv([&property(1, input)], &options).unwrap(),
+            "price,id,town\n1000万円,1,神南\n"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_export_columns() {
+        let options = ExportOptions {
+            columns: Some("id,rent".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            write_csv([], &options).unwrap_err(),
+            InvalidColumns::Unknown(UnknownColumn("rent".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_an_empty_list_of_columns() {
+        let options = ExportOptions {
+            columns: Some(" , ".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(write_csv([], &options).unwrap_err(), InvalidColumns::Empty);
+    }
+
+    #[test]
+    fn groups_rows_into_chunks() {
+        let properties: Vec<Property> = (1..=5).map(|id| with_town(id, "神南")).collect();
+        let options = ExportOptions {
+            columns: Some("id".to_string()),
+            ..Default::default()
+        };
+
+        let chunks: Vec<String> = csv_chunks(&properties, &options, 4).unwrap().collect();
+        assert_eq!(chunks, ["id\n1", "\n2\n3", "\n4\n5", "\n"]);
+        assert_eq!(chunks.concat(), write_csv(&properties, &options).unwrap());
+    }
+
+    #[test]
+    fn fills_in_filename_templates() {
+        assert_eq!(
+            render_filename(
+                "properties_{prefecture}_{date}",
+                Some("東京都"),
+                "2024-06-01"
+            ),
+            "properties_東京都_2024-06-01.csv"
+        );
+        assert_eq!(
+            render_filename("{prefecture}.CSV", None, "2024-06-01"),
+            "all.CSV"
+        );
+        // Nothing from the template can turn the filename into a path
+        assert_eq!(
+            render_filename("../{date}/\"x\".csv", None, "2024-06-01"),
+            "2024-06-01x.csv"
+        );
+        assert_eq!(render_filename("/", None, "2024-06-01"), "properties.csv");
+    }
+
+    #[test]
+    fn streams_properties_as_messagepack_maps() {
+        let mut located = with_town(3, "日本橋");
+        located.latitude = Some(35.68);
+        located.complete = false;
+        let properties = [with_town(1, "神南"), with_town(2, "梅田"), located];
+
+        let chunks: Vec<Vec<u8>> = msgpack_chunks(&properties, ViewOptions::default(), 1).collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(
+            msgpack_chunks(&properties, ViewOptions::default(), 1 << 20).count(),
+            1
+        );
+
+        // Each map decodes to the same fields as the JSON output,
+        // which also checks that the field counts are right
+        let mut bytes = &chunks.concat()[..];
+        for property in &properties {
+            let decoded: serde_json::Value = rmp_serde::from_read(&mut bytes).unwrap();
+            assert_eq!(decoded, serde_json::to_value(property).unwrap());
+        }
+        assert!(bytes.is_empty());
+    }
+}
@@ -0,0 +1,134 @@
+//! Versions of axum's extractors that report bad requests as an [`ApiError`],
+//! so that a client gets the same JSON error shape as from the handlers,
+//! rather than axum's plain text
+
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        FromRequest, FromRequestParts,
+    },
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::error::ApiError;
+
+/// The query string, deserialized like [`axum::extract::Query`]
+#[derive(Debug, Clone, Copy, Default, FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct Query<T>(pub T);
+
+/// The path parameters, deserialized like [`axum::extract::Path`]
+#[derive(Debug, Clone, Copy, Default, FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct Path<T>(pub T);
+
+/// A JSON request body, deserialized like [`axum::Json`].
+/// It can be sent as a response too, so handlers only need the one `Json`.
+#[derive(Debug, Clone, Copy, Default, FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::new(rejection.status(), "invalid_query", rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        ApiError::new(rejection.status(), "invalid_path", rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::new(rejection.status(), "invalid_json", rejection.body_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{self, Body},
+        http::{header, Request, StatusCode},
+        routing::{get, post},
+        Router,
+    };
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Params {
+        limit: usize,
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/query",
+                get(|Query(params): Query<Params>| async move { params.limit.to_string() }),
+            )
+            .route("/path/:id", get(|Path(_): Path<usize>| async {}))
+            .route("/json", post(|Json(_): Json<Vec<usize>>| async {}))
+    }
+
+    async fn error(request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = app().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn reports_a_bad_query_as_json() {
+        let request = Request::get("/query?limit=abc")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = error(request).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "invalid_query");
+    }
+
+    #[tokio::test]
+    async fn reports_a_bad_path_as_json() {
+        let request = Request::get("/path/abc").body(Body::empty()).unwrap();
+        let (status, body) = error(request).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "invalid_path");
+    }
+
+    #[tokio::test]
+    async fn reports_a_bad_body_as_json() {
+        let request = Request::post("/json")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("[1, \"two\"]"))
+            .unwrap();
+        let (status, body) = error(request).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["code"], "invalid_json");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_status_of_a_missing_content_type() {
+        let request = Request::post("/json").body(Body::from("[1]")).unwrap();
+        let (status, body) = error(request).await;
+
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["error"]["code"], "invalid_json");
+    }
+}
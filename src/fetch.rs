@@ -0,0 +1,259 @@
+//! Downloading files to import from a URL
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use axum::body::Bytes;
+use reqwest::{header::LOCATION, redirect, Response, Url};
+
+/// How many redirects we follow before giving up
+pub const MAX_REDIRECTS: usize = 5;
+
+/// The reasons a download can fail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchError {
+    InvalidUrl(String),
+    /// Only `http` and `https` URLs can be fetched, so that clients
+    /// can't make us read local files or talk other protocols
+    UnsupportedScheme(String),
+    /// The host is on our own network, like `localhost` or `10.0.0.1`,
+    /// which clients shouldn't be able to reach through us
+    ForbiddenAddress(IpAddr),
+    TooManyRedirects,
+    /// The file is larger than the limit, in bytes
+    TooLarge(usize),
+    Timeout,
+    /// The server responded, but not with a success status
+    Status(u16),
+    Request(String),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::InvalidUrl(error) => write!(f, "invalid url: {error}"),
+            FetchError::UnsupportedScheme(scheme) => {
+                write!(f, "unsupported url scheme `{scheme}`, use http or https")
+            }
+            FetchError::ForbiddenAddress(address) => {
+                write!(f, "the url points to a private address ({address})")
+            }
+            FetchError::TooManyRedirects => {
+                write!(f, "the url redirected more than {MAX_REDIRECTS} times")
+            }
+            FetchError::TooLarge(limit) => {
+                write!(f, "the file is larger than the limit of {limit} bytes")
+            }
+            FetchError::Timeout => write!(f, "the file took too long to download"),
+            FetchError::Status(status) => write!(f, "the server responded with status {status}"),
+            FetchError::Request(error) => write!(f, "failed to download the file: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            FetchError::Timeout
+        } else {
+            FetchError::Request(error.to_string())
+        }
+    }
+}
+
+/// Downloads the file at the URL, giving up if it's larger than `max_bytes`
+/// or takes longer than `timeout`.
+///
+/// The URL comes from a client, so we only fetch from public addresses.
+/// That goes for every redirect too, which we follow one at a time.
+pub async fn fetch(url: &str, max_bytes: usize, timeout: Duration) -> Result<Bytes, FetchError> {
+    let mut url = Url::parse(url).map_err(|error| FetchError::InvalidUrl(error.to_string()))?;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let response = get(&url, timeout).await?;
+
+        if !response.status().is_redirection() {
+            return read_body(response, max_bytes).await;
+        }
+
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or(FetchError::Status(response.status().as_u16()))?;
+
+        url = url
+            .join(location)
+            .map_err(|error| FetchError::InvalidUrl(error.to_string()))?;
+    }
+
+    Err(FetchError::TooManyRedirects)
+}
+
+/// Requests the URL, without following redirects
+async fn get(url: &Url, timeout: Duration) -> Result<Response, FetchError> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(FetchError::UnsupportedScheme(url.scheme().to_string()));
+    }
+
+    let host = url
+        .host_str()
+        .ok_or_else(|| FetchError::InvalidUrl("the url has no host".to_string()))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    // IPv6 hosts are written in brackets, which the lookup doesn't expect
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|error| FetchError::Request(error.to_string()))?
+        .collect();
+
+    if addresses.is_empty() {
+        return Err(FetchError::Request(format!(
+            "no addresses found for `{host}`"
+        )));
+    }
+
+    if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+        return Err(FetchError::ForbiddenAddress(address.ip()));
+    }
+
+    // The client connects to the addresses we checked, rather than looking
+    // the host up again, and maybe getting a different answer the second time
+    let client = reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(redirect::Policy::none())
+        .resolve_to_addrs(host, &addresses)
+        .build()?;
+
+    Ok(client.get(url.clone()).send().await?)
+}
+
+async fn read_body(mut response: Response, max_bytes: usize) -> Result<Bytes, FetchError> {
+    if !response.status().is_success() {
+        return Err(FetchError::Status(response.status().as_u16()));
+    }
+
+    // The length header can't be trusted, but it lets us
+    // give up early on files we know are too large
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_bytes as u64)
+    {
+        return Err(FetchError::TooLarge(max_bytes));
+    }
+
+    let mut data = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        if data.len() + chunk.len() > max_bytes {
+            return Err(FetchError::TooLarge(max_bytes));
+        }
+
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(data.into())
+}
+
+/// Checks if the address is reachable from the internet at large, as opposed
+/// to being on this machine, the local network, or reserved for something else.
+/// That includes the link-local addresses cloud providers serve metadata on.
+pub fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => is_public_v4(address),
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(mapped) => is_public_v4(mapped),
+            None => is_public_v6(address),
+        },
+    }
+}
+
+fn is_public_v4(address: Ipv4Addr) -> bool {
+    let [first, second, ..] = address.octets();
+    // 100.64.0.0/10 is shared between the customers of an ISP
+    let is_shared = first == 100 && (second & 0b1100_0000) == 64;
+
+    !(address.is_unspecified()
+        || address.is_loopback()
+        || address.is_private()
+        || address.is_link_local()
+        || address.is_broadcast()
+        || address.is_documentation()
+        || address.is_multicast()
+        || is_shared
+        || first == 0
+        || first >= 240)
+}
+
+fn is_public_v6(address: Ipv6Addr) -> bool {
+    !(address.is_unspecified()
+        || address.is_loopback()
+        || address.is_multicast()
+        || address.is_unique_local()
+        || address.is_unicast_link_local())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_public_addresses() {
+        for address in ["8.8.8.8", "203.0.114.1", "2001:4860:4860::8888"] {
+            assert!(is_public(address.parse().unwrap()), "{address}");
+        }
+    }
+
+    #[test]
+    fn forbids_local_addresses() {
+        for address in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(address.parse().unwrap()), "{address}");
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_to_fetch_from_local_hosts() {
+        let timeout = Duration::from_secs(1);
+
+        for url in [
+            "http://127.0.0.1:1/data.csv",
+            "http://localhost:1/data.csv",
+            "http://[::1]:1/data.csv",
+            "http://169.254.169.254/latest/meta-data/",
+        ] {
+            assert!(
+                matches!(
+                    fetch(url, 1024, timeout).await,
+                    Err(FetchError::ForbiddenAddress(_))
+                ),
+                "{url}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_other_schemes() {
+        assert_eq!(
+            fetch("file:///etc/passwd", 1024, Duration::from_secs(1)).await,
+            Err(FetchError::UnsupportedScheme("file".to_string()))
+        );
+    }
+}
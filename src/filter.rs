@@ -0,0 +1,184 @@
+//! Filtering which properties are included in a list
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::{config::Config, prefecture, property::Property, property_type::CanonicalType};
+
+/// The filters a client can send in the query string.
+/// A property has to match all of the filters that are set.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PropertyFilter {
+    /// Matches the prefecture exactly, such as `東京都`
+    pub prefecture: Option<String>,
+    /// Matches the prefecture by its romaji name, such as `tokyo` for 東京都
+    pub romaji: Option<String>,
+    /// Matches part of the formal address, such as `日本橋4丁目`
+    pub full_address_contains: Option<String>,
+    /// Includes properties that were soft deleted, which are left out otherwise
+    #[serde(default)]
+    pub include_deleted: bool,
+    /// Only matches properties that changed after this time, like
+    /// `2024-06-01T09:30:00Z`, for clients syncing just the changes
+    pub updated_since: Option<DateTime<Utc>>,
+    /// Matches the canonical property type, such as `condo`
+    pub canonical_type: Option<CanonicalType>,
+    /// Includes the property types that the list hides by default
+    #[serde(default)]
+    pub include_hidden_types: bool,
+    /// The property types hidden by default, which come from the config
+    /// rather than the query string. See [`PropertyFilter::with_config`].
+    #[serde(skip)]
+    pub hidden_types: Vec<CanonicalType>,
+}
+
+impl PropertyFilter {
+    /// The filters with the server's own settings filled in,
+    /// like the property types it hides
+    pub fn with_config(self, config: &Config) -> Self {
+        PropertyFilter {
+            hidden_types: config.hidden_types.clone(),
+            ..self
+        }
+    }
+
+    /// Checks the filters against a property.
+    ///
+    /// When filtering a whole list, prefer [`PropertyFilter::matcher`],
+    /// which only does the lookups once.
+    pub fn matches(&self, property: &Property) -> bool {
+        self.matcher()(property)
+    }
+
+    /// Checks if a property is one of the types that the list hides by default.
+    /// Asking for them, either all at once or by filtering to one of the
+    /// types, shows them again.
+    pub fn is_hidden(&self, property: &Property) -> bool {
+        !self.include_hidden_types
+            && self.canonical_type.is_none()
+            && self.hidden_types.contains(&property.canonical_type)
+    }
+
+    /// Builds a function that checks a property against the filters,
+    /// doing any lookups up front
+    pub fn matcher(&self) -> impl Fn(&Property) -> bool + '_ {
+        // An unknown romaji name can't match anything, but we still want
+        // to respond with an empty list rather than an error
+        let prefecture = self
+            .romaji
+            .as_deref()
+            .map(|romaji| prefecture::find_by_romaji(romaji).map(|prefecture| prefecture.name));
+
+        move |property| {
+            let romaji_matches = match prefecture {
+                Some(Some(name)) => property.prefecture == name,
+                Some(None) => false,
+                None => true,
+            };
+
+            // The full address isn't stored, so we have to build it for
+            // each property. We save that for last, since it's the slowest check.
+            romaji_matches
+                && (self.include_deleted || !property.deleted)
+                && !self.is_hidden(property)
+                && self
+                    .canonical_type
+                    .is_none_or(|canonical_type| property.canonical_type == canonical_type)
+                && self.updated_since.is_none_or(|since| {
+                    property
+                        .updated_at
+                        .is_some_and(|updated_at| updated_at > since)
+                })
+                && self
+                    .prefecture
+                    .as_deref()
+                    .is_none_or(|prefecture| property.prefecture == prefecture)
+                && self
+                    .full_address_contains
+                    .as_deref()
+                    .is_none_or(|fragment| property.full_address().contains(fragment))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    use super::*;
+
+    fn property(id: usize, property_type: &str) -> Property {
+        let input = PropertyInput {
+            prefecture: "東京都".to_string(),
+            property_type: property_type.to_string(),
+            ..Default::default()
+        };
+        Property::from_input(
+            id,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        )
+    }
+
+    #[test]
+    fn matches_the_canonical_type() {
+        let filter = PropertyFilter {
+            canonical_type: Some(CanonicalType::Land),
+            ..Default::default()
+        };
+
+        assert!(filter.matches(&property(1, "土地")));
+        assert!(filter.matches(&property(2, "売地")));
+        assert!(!filter.matches(&property(3, "マンション")));
+        assert!(PropertyFilter::default().matches(&property(3, "マンション")));
+    }
+
+    #[test]
+    fn matches_properties_updated_after_the_time() {
+        let time = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        let filter = PropertyFilter {
+            updated_since: Some(time("2024-06-01T00:00:00Z")),
+            ..Default::default()
+        };
+
+        let mut property = property(1, "土地");
+        property.updated_at = Some(time("2024-06-01T09:30:00+09:00"));
+        assert!(filter.matches(&property));
+
+        // The time itself is already synced
+        property.updated_at = Some(time("2024-06-01T09:00:00+09:00"));
+        assert!(!filter.matches(&property));
+
+        property.updated_at = None;
+        assert!(!filter.matches(&property));
+    }
+
+    #[test]
+    fn hides_the_hidden_types_unless_asked_for() {
+        let land = property(1, "土地");
+        let condo = property(2, "マンション");
+        let hiding_land = PropertyFilter::default().with_config(&Config {
+            hidden_types: vec![CanonicalType::Land],
+            ..Config::default()
+        });
+
+        assert!(hiding_land.is_hidden(&land));
+        assert!(!hiding_land.matches(&land));
+        assert!(!hiding_land.is_hidden(&condo));
+        assert!(!PropertyFilter::default().is_hidden(&land));
+
+        let include_hidden = PropertyFilter {
+            include_hidden_types: true,
+            ..hiding_land.clone()
+        };
+        assert!(!include_hidden.is_hidden(&land));
+
+        let land_only = PropertyFilter {
+            canonical_type: Some(CanonicalType::Land),
+            ..hiding_land
+        };
+        assert!(!land_only.is_hidden(&land));
+        assert!(land_only.matches(&land));
+    }
+}
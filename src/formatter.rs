@@ -0,0 +1,305 @@
+//! The named ways a property's address can be written out as one string
+//!
+//! Each format is registered in [`FORMATTERS`] under the name clients pass
+//! as `full_address_format`. Adding a format only takes a new entry there,
+//! without touching the serializer or the routes.
+
+use serde::{Deserialize, Deserializer};
+
+use crate::property::Property;
+
+/// A named function that writes out the address of a property
+#[derive(Debug)]
+pub struct AddressFormatter {
+    pub name: &'static str,
+    pub format: fn(&Property) -> String,
+}
+
+/// Every address format we support. The first one is the default.
+pub const FORMATTERS: &[AddressFormatter] = &[
+    // With each number's marker, like `1丁目2番地3号`
+    AddressFormatter {
+        name: "formal",
+        format: formal,
+    },
+    // Just the numbers, like `1-2-3`
+    AddressFormatter {
+        name: "hyphenated",
+        format: hyphenated,
+    },
+    // The chome with its marker, and the rest hyphenated, like `1丁目2-3`.
+    // This is a very common way to write addresses day to day.
+    AddressFormatter {
+        name: "mixed",
+        format: mixed,
+    },
+    // As it would be written on an envelope, with the numbers hyphenated
+    // and the building on its own after a space
+    AddressFormatter {
+        name: "postal",
+        format: postal,
+    },
+    // In Western order, with the prefecture in romaji
+    AddressFormatter {
+        name: "english",
+        format: Property::full_address_en,
+    },
+];
+
+/// Finds a registered address format by its name
+pub fn find(name: &str) -> Option<AddressFormat> {
+    FORMATTERS
+        .iter()
+        .find(|formatter| formatter.name.eq_ignore_ascii_case(name.trim()))
+        .map(AddressFormat)
+}
+
+/// One of the registered address formats, chosen per request
+#[derive(Debug, Clone, Copy)]
+pub struct AddressFormat(&'static AddressFormatter);
+
+impl AddressFormat {
+    /// The formal way to display Japanese addresses
+    pub const FORMAL: AddressFormat = AddressFormat(&FORMATTERS[0]);
+
+    pub fn name(self) -> &'static str {
+        self.0.name
+    }
+
+    /// Writes out the address of the property in this format
+    pub fn format(self, property: &Property) -> String {
+        (self.0.format)(property)
+    }
+}
+
+impl Default for AddressFormat {
+    fn default() -> Self {
+        AddressFormat::FORMAL
+    }
+}
+
+impl<'de> Deserialize<'de> for AddressFormat {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let name = String::deserialize(deserializer)?;
+
+        find(&name).ok_or_else(|| {
+            let names = FORMATTERS
+                .iter()
+                .map(|formatter| formatter.name)
+                .collect::<Vec<_>>();
+
+            serde::de::Error::custom(format!(
+                "unknown address format `{name}`, expected one of: {}",
+                names.join(", ")
+            ))
+        })
+    }
+}
+
+/// The markers that can follow the chome, banchi, and go, in that order.
+/// The banchi is sometimes written with just 番.
+const MARKERS: [&[&str]; 3] = [&["丁目"], &["番地", "番"], &["号"]];
+
+/// The chome, banchi, and go of a property, without their markers.
+///
+/// Some sources include the markers in the fields themselves, like a chome
+/// of `1丁目`, so we take them off to avoid writing them twice.
+pub fn block_numbers(property: &Property) -> [&str; 3] {
+    let fields = [&property.chome, &property.banchi, &property.go];
+
+    let mut numbers = [""; 3];
+    for ((number, field), markers) in numbers.iter_mut().zip(fields).zip(MARKERS) {
+        let field = field.trim();
+        *number = markers
+            .iter()
+            .find_map(|marker| field.strip_suffix(marker))
+            .unwrap_or(field)
+            .trim_end();
+    }
+
+    numbers
+}
+
+/// Takes the leading zeros off of a block number, like `03` to `3`,
+/// in ASCII or full-width digits. A number that's just zeros keeps one of them.
+pub fn strip_leading_zeros(number: &str) -> &str {
+    let is_digit = |c: char| c.is_ascii_digit() || ('０'..='９').contains(&c);
+    let mut number = number;
+
+    while let Some(rest) = number.strip_prefix(['0', '０']) {
+        if !rest.starts_with(is_digit) {
+            break;
+        }
+
+        number = rest;
+    }
+
+    number
+}
+
+fn formal(property: &Property) -> String {
+    let [chome, banchi, go] = block_numbers(property);
+    let block = format!("{chome}丁目{banchi}番地{go}号");
+
+    join_address(property, &block, "")
+}
+
+fn hyphenated(property: &Property) -> String {
+    let block = hyphenate(&block_numbers(property));
+
+    join_address(property, &block, "")
+}
+
+fn mixed(property: &Property) -> String {
+    let [chome, banchi, go] = block_numbers(property);
+    let chome = if chome.is_empty() {
+        String::new()
+    } else {
+        format!("{chome}丁目")
+    };
+
+    let block = chome + &hyphenate(&[banchi, go]);
+
+    join_address(property, &block, "")
+}
+
+fn postal(property: &Property) -> String {
+    let block = hyphenate(&block_numbers(property));
+    let separator = if property.building.is_empty() {
+        ""
+    } else {
+        " "
+    };
+
+    join_address(property, &block, separator)
+}
+
+/// Puts the address together from the largest area to the smallest,
+/// with the block numbers already written out
+fn join_address(property: &Property, block: &str, before_building: &str) -> String {
+    format!(
+        "{}{}{}{}{}{}",
+        &property.prefecture,
+        &property.city,
+        &property.town,
+        block,
+        before_building,
+        &property.building,
+    )
+}
+
+/// Joins the block numbers that are set with hyphens, like `1-2-3`
+fn hyphenate(numbers: &[&str]) -> String {
+    numbers
+        .iter()
+        .filter(|number| !number.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    use super::*;
+
+    fn block(chome: &str, banchi: &str, go: &str, building: &str) -> Property {
+        let input = PropertyInput {
+            prefecture: "東京都".to_string(),
+            city: "中央区".to_string(),
+            town: "日本橋".to_string(),
+            chome: chome.to_string(),
+            banchi: banchi.to_string(),
+            go: go.to_string(),
+            building: building.to_string(),
+            ..Default::default()
+        };
+        Property::from_input(
+            1,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        )
+    }
+
+    fn format(name: &str, property: &Property) -> String {
+        find(name).unwrap().format(property)
+    }
+
+    #[test]
+    fn hyphenates_the_block_numbers() {
+        let property = block("4", "16", "12", "");
+        assert_eq!(format("hyphenated", &property), "東京都中央区日本橋4-16-12");
+
+        // Numbers that aren't set are left out, rather than leaving a gap
+        let property = block("4", "16", "", "");
+        assert_eq!(format("hyphenated", &property), "東京都中央区日本橋4-16");
+    }
+
+    #[test]
+    fn takes_markers_off_before_hyphenating() {
+        let property = block("4丁目", "16番", "12号", "");
+        assert_eq!(format("hyphenated", &property), "東京都中央区日本橋4-16-12");
+        assert_eq!(
+            format("formal", &property),
+            "東京都中央区日本橋4丁目16番地12号"
+        );
+    }
+
+    #[test]
+    fn writes_each_registered_format() {
+        let property = block("4", "16", "12", "国立競技場");
+
+        assert_eq!(
+            format("mixed", &property),
+            "東京都中央区日本橋4丁目16-12国立競技場"
+        );
+        assert_eq!(
+            format("postal", &property),
+            "東京都中央区日本橋4-16-12 国立競技場"
+        );
+        assert_eq!(
+            format("English", &property),
+            "国立競技場, 4-16-12 日本橋, 中央区, Tokyo"
+        );
+        assert_eq!(AddressFormat::default().name(), "formal");
+    }
+
+    #[test]
+    fn lists_the_formats_for_an_unknown_name() {
+        let error = serde_json::from_str::<AddressFormat>(r#""western""#).unwrap_err();
+
+        assert!(error.to_string().starts_with(
+            "unknown address format `western`, expected one of: formal, hyphenated, mixed, postal, english"
+        ));
+    }
+
+    #[test]
+    fn does_not_repeat_markers_already_in_the_data() {
+        let property = block(" 4丁目 ", "16番地", "12 号", "");
+        assert_eq!(block_numbers(&property), ["4", "16", "12"]);
+        assert_eq!(
+            format("formal", &property),
+            "東京都中央区日本橋4丁目16番地12号"
+        );
+        assert_eq!(format("mixed", &property), "東京都中央区日本橋4丁目16-12");
+
+        // Markers are only taken off the end, and only the field's own
+        let property = block("丁目4", "16号", "", "");
+        assert_eq!(block_numbers(&property), ["丁目4", "16号", ""]);
+    }
+
+    #[test]
+    fn takes_leading_zeros_off_block_numbers() {
+        assert_eq!(strip_leading_zeros("03"), "3");
+        assert_eq!(strip_leading_zeros("００４"), "４");
+        assert_eq!(strip_leading_zeros("000"), "0");
+        assert_eq!(strip_leading_zeros("10"), "10");
+        assert_eq!(strip_leading_zeros("0番"), "0番");
+        assert_eq!(strip_leading_zeros(""), "");
+    }
+}
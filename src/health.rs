@@ -0,0 +1,110 @@
+//! Reporting on the health and resource use of the server
+
+use std::mem;
+
+use serde::Serialize;
+
+use crate::property::Property;
+
+/// Approximately how much memory the server and its data are using
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryUsage {
+    /// The resident set size of the whole process, as reported by the OS.
+    /// This is `null` on platforms where we don't know how to read it.
+    pub resident_bytes: Option<u64>,
+    /// An estimate of the bytes the db takes up, based on the string lengths.
+    /// It doesn't include the overhead of the shards' trees,
+    /// so the real number is a bit higher.
+    pub db_bytes: usize,
+}
+
+/// Measures the memory use of the process and the given properties
+pub fn memory_usage<'a>(properties: impl IntoIterator<Item = &'a Property>) -> MemoryUsage {
+    MemoryUsage {
+        resident_bytes: resident_bytes(),
+        db_bytes: properties.into_iter().map(estimate_size).sum(),
+    }
+}
+
+/// Estimates the bytes a property takes up, including its strings.
+/// The coordinates and other fields that aren't on the heap
+/// are part of the size of the property itself.
+fn estimate_size(property: &Property) -> usize {
+    let strings: usize = property
+        .content_key()
+        .iter()
+        .chain(property.raw.as_deref().as_slice())
+        .map(|field| field.len())
+        .sum();
+
+    // Each entry also holds its id as the key
+    mem::size_of::<usize>() + mem::size_of::<Property>() + strings
+}
+
+/// Reads the resident set size from `/proc`, which only exists on Linux
+fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+
+    // The line looks like `VmRSS:     12345 kB`
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    use super::*;
+
+    fn property(town: &str) -> Property {
+        let input = PropertyInput {
+            town: town.to_string(),
+            latitude: Some(35.0),
+            longitude: Some(139.0),
+            ..Default::default()
+        };
+        Property::from_input(
+            1,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        )
+    }
+
+    #[test]
+    fn counts_the_strings_of_each_property() {
+        let base = estimate_size(&property(""));
+
+        assert!(base >= mem::size_of::<Property>());
+        assert_eq!(estimate_size(&property("神南")), base + "神南".len());
+    }
+
+    #[test]
+    fn counts_the_raw_row() {
+        let mut with_raw = property("");
+        with_raw.raw = Some("東京都,渋谷区".to_string());
+
+        assert_eq!(
+            estimate_size(&with_raw),
+            estimate_size(&property("")) + "東京都,渋谷区".len()
+        );
+    }
+
+    #[test]
+    fn adds_up_every_property() {
+        let properties = [property("a"), property("bc")];
+
+        assert_eq!(
+            memory_usage(&properties).db_bytes,
+            estimate_size(&properties[0]) + estimate_size(&properties[1])
+        );
+    }
+}
@@ -0,0 +1,115 @@
+//! Remembering responses by their `Idempotency-Key`, so that a client
+//! retrying a request doesn't end up doing the same work twice
+
+use std::collections::{HashMap, VecDeque};
+
+use axum::{
+    body::Bytes,
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+/// The header clients send to mark retries of the same request
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// The header we add to responses that were replayed from the cache
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// How many responses are remembered. Upload responses hold the whole
+/// dataset, so we keep this small to bound the memory use.
+pub const CAPACITY: usize = 16;
+
+/// A JSON response, or an empty `204 No Content` response,
+/// stored so that it can be sent again
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    pub body: Bytes,
+}
+
+impl IntoResponse for CachedResponse {
+    fn into_response(self) -> Response {
+        // A 204 response can't have a body, so it doesn't have a type either
+        let mut response = match self.status {
+            StatusCode::NO_CONTENT => self.status.into_response(),
+            _ => (
+                self.status,
+                [(header::CONTENT_TYPE, "application/json")],
+                self.body,
+            )
+                .into_response(),
+        };
+
+        response.headers_mut().extend(self.headers);
+        response
+    }
+}
+
+/// The most recent responses, by idempotency key.
+/// Once it's full, the oldest response is forgotten to make room.
+#[derive(Debug, Clone, Default)]
+pub struct IdempotencyCache {
+    order: VecDeque<String>,
+    responses: HashMap<String, CachedResponse>,
+}
+
+impl IdempotencyCache {
+    pub fn get(&self, key: &str) -> Option<&CachedResponse> {
+        self.responses.get(key)
+    }
+
+    pub fn insert(&mut self, key: String, response: CachedResponse) {
+        if self.responses.insert(key.clone(), response).is_some() {
+            return;
+        }
+
+        self.order.push_back(key);
+
+        while self.order.len() > CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.responses.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &'static str) -> CachedResponse {
+        CachedResponse {
+            status: StatusCode::OK,
+            headers: vec![],
+            body: Bytes::from_static(body.as_bytes()),
+        }
+    }
+
+    #[test]
+    fn forgets_the_oldest_response_once_full() {
+        let mut cache = IdempotencyCache::default();
+
+        for i in 0..=CAPACITY {
+            cache.insert(format!("key-{i}"), response("[]"));
+        }
+
+        assert!(cache.get("key-0").is_none());
+        assert!(cache.get("key-1").is_some());
+        assert!(cache.get(&format!("key-{CAPACITY}")).is_some());
+    }
+
+    #[test]
+    fn replaces_the_response_for_a_repeated_key() {
+        let mut cache = IdempotencyCache::default();
+        cache.insert("a".to_string(), response("first"));
+        cache.insert("a".to_string(), response("second"));
+
+        for i in 1..CAPACITY {
+            cache.insert(format!("key-{i}"), response("[]"));
+        }
+
+        // Inserting the key again didn't use up a second place in the order
+        assert_eq!(cache.get("a").unwrap().body, "second");
+    }
+}
@@ -0,0 +1,1164 @@
+//! Importing property data from CSV files
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::Arc,
+};
+
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    csv::{self, Dialect},
+    property::{Property, MAX_ID},
+    property_type::TypeMapping,
+    station::StationAliases,
+    validation::{self, ValidationRules},
+};
+
+/// The property fields that are read from a CSV file,
+/// in the order that we expect to find them by default
+pub const FIELDS: [&str; 11] = [
+    "prefecture",
+    "city",
+    "town",
+    "chome",
+    "banchi",
+    "go",
+    "building",
+    "price",
+    "nearest_station",
+    "property_type",
+    "land_area",
+];
+
+/// Columns that can be left out of a file entirely. By default, the
+/// coordinates are expected right after the columns in [`FIELDS`], in this order.
+/// The id is only read from a column with a matching header, or from a mapping.
+pub const OPTIONAL_FIELDS: [&str; 3] = ["latitude", "longitude", "id"];
+
+/// Points to a column in the source CSV file,
+/// either by its position or by its name in the header row
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ColumnRef {
+    Index(usize),
+    Name(String),
+}
+
+/// Maps each property field to the column it should be read from.
+/// This lets users upload exports from other vendors without
+/// having to reorder the columns first.
+///
+/// For example: `{ "prefecture": "県", "city": 3, ... }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct ColumnMapping(pub HashMap<String, ColumnRef>);
+
+/// The position of each field in a row, in the same order as [`FIELDS`]
+type ColumnIndices = [usize; FIELDS.len()];
+
+/// The documented column order, where each field is in its own position
+const DEFAULT_INDICES: ColumnIndices = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+/// The position of each optional field, in the same order as [`OPTIONAL_FIELDS`],
+/// or `None` if the file doesn't have it
+type OptionalIndices = [Option<usize>; OPTIONAL_FIELDS.len()];
+
+const DEFAULT_OPTIONAL_INDICES: OptionalIndices = [Some(11), Some(12), None];
+
+/// The header names we recognize for each field, in English and in Japanese.
+/// These are in the same order as [`FIELDS`], followed by [`OPTIONAL_FIELDS`].
+///
+/// When a new optional field is added, older files simply won't have
+/// a column for it, so adding its names here is all it takes to import
+/// both old and new files.
+const HEADER_NAMES: [&[&str]; FIELDS.len() + OPTIONAL_FIELDS.len()] = [
+    &["prefecture", "都道府県"],
+    &["city", "市区町村"],
+    &["town", "町名"],
+    &["chome", "丁目"],
+    &["banchi", "番地"],
+    &["go", "号"],
+    &["building", "建物名"],
+    &["price", "価格"],
+    &["nearest_station", "最寄駅"],
+    &["property_type", "物件タイプ"],
+    &["land_area", "敷地面積"],
+    &["latitude", "緯度"],
+    &["longitude", "経度"],
+    &["id", "物件ID"],
+];
+
+/// Works out where each field is from the names in the header row,
+/// for files that come without a mapping.
+///
+/// If every required field has a recognized column, the columns can be in
+/// any order, and optional fields without a column are left out.
+/// Otherwise, we fall back to the documented column order.
+fn detect_columns(header: &[&str]) -> (ColumnIndices, OptionalIndices) {
+    let find = |names: &[&str]| {
+        header.iter().position(|column| {
+            let column = column.trim();
+            names.iter().any(|name| column.eq_ignore_ascii_case(name))
+        })
+    };
+
+    let mut indices = DEFAULT_INDICES;
+
+    for (index, names) in indices.iter_mut().zip(&HEADER_NAMES) {
+        match find(names) {
+            Some(position) => *index = position,
+            None => return (DEFAULT_INDICES, DEFAULT_OPTIONAL_INDICES),
+        }
+    }
+
+    let mut optional = [None; OPTIONAL_FIELDS.len()];
+
+    for (index, names) in optional.iter_mut().zip(&HEADER_NAMES[FIELDS.len()..]) {
+        *index = find(names);
+    }
+
+    (indices, optional)
+}
+
+/// The reasons an import can fail as a whole, as opposed to just skipping a row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportError {
+    UnknownField(String),
+    UnmappedField(&'static str),
+    UnknownColumn(String),
+    /// The upload has more rows than the configured limit, across all of its files
+    TooManyRows(usize),
+    /// The file looked like an Excel workbook, but couldn't be read as one
+    InvalidWorkbook(String),
+    /// The file looked like a ZIP archive, but couldn't be read as one
+    InvalidArchive(String),
+    /// The files in a ZIP archive add up to more than the limit, in bytes
+    ArchiveTooLarge(usize),
+    /// The requested worksheet isn't in the workbook
+    SheetNotFound {
+        requested: String,
+        available: Vec<String>,
+    },
+    /// Some rows were invalid, and the import was strict
+    InvalidRows(Vec<SkippedRow>),
+}
+
+/// A row that was left out of the import, and why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedRow {
+    /// The row number, counting from 1 after the header
+    pub row: usize,
+    pub reason: String,
+}
+
+/// A row that was imported, but has something that looks wrong with it,
+/// like a price that isn't a number
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RowWarning {
+    /// The row number, counting from 1 after the header
+    pub row: usize,
+    pub reason: String,
+}
+
+/// The result of a successful import
+#[derive(Debug, Clone, Default)]
+pub struct Import {
+    pub properties: Vec<Property>,
+    /// The row each of the properties came from, in the same order
+    pub rows: Vec<usize>,
+    pub skipped: Vec<SkippedRow>,
+    pub warnings: Vec<RowWarning>,
+    /// How many rows the file has after the header, including blank and skipped ones
+    pub row_count: usize,
+}
+
+impl Import {
+    /// Takes out the properties at these positions in the list, after their
+    /// rows have been added to the skipped ones, along with their warnings
+    pub fn remove_replaced(&mut self, mut positions: Vec<usize>) {
+        if positions.is_empty() {
+            return;
+        }
+
+        positions.sort_unstable();
+        let mut positions = positions.into_iter().peekable();
+        let mut position = 0;
+        let rows = std::mem::take(&mut self.rows);
+
+        (self.properties, self.rows) = std::mem::take(&mut self.properties)
+            .into_iter()
+            .zip(rows)
+            .filter(|_| {
+                let keep = positions.next_if_eq(&position).is_none();
+                position += 1;
+                keep
+            })
+            .unzip();
+
+        self.skipped.sort_by_key(|skipped| skipped.row);
+
+        let skipped_rows: HashSet<usize> = self.skipped.iter().map(|row| row.row).collect();
+        self.warnings
+            .retain(|warning| !skipped_rows.contains(&warning.row));
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::UnknownField(field) => {
+                write!(f, "mapping contains unknown field `{field}`")
+            }
+            ImportError::UnmappedField(field) => write!(f, "mapping is missing field `{field}`"),
+            ImportError::UnknownColumn(column) => {
+                write!(f, "mapping refers to unknown column `{column}`")
+            }
+            ImportError::TooManyRows(limit) => {
+                write!(f, "upload has more than the limit of {limit} rows")
+            }
+            ImportError::InvalidWorkbook(error) => write!(f, "invalid workbook: {error}"),
+            ImportError::InvalidArchive(error) => write!(f, "invalid ZIP archive: {error}"),
+            ImportError::ArchiveTooLarge(limit) => {
+                write!(
+                    f,
+                    "the files in the archive add up to more than {limit} bytes"
+                )
+            }
+            ImportError::SheetNotFound {
+                requested,
+                available,
+            } => write!(
+                f,
+                "sheet `{requested}` not found, the available sheets are: {}",
+                available.join(", ")
+            ),
+            ImportError::InvalidRows(rows) => match rows.len() {
+                1 => write!(f, "1 row is invalid"),
+                count => write!(f, "{count} rows are invalid"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl ColumnMapping {
+    /// Works out the position of each field using the header row.
+    /// Optional fields that aren't in the mapping are left out.
+    fn resolve(&self, header: &[&str]) -> Result<(ColumnIndices, OptionalIndices), ImportError> {
+        if let Some(field) = self.0.keys().find(|field| {
+            !FIELDS.contains(&field.as_str()) && !OPTIONAL_FIELDS.contains(&field.as_str())
+        }) {
+            return Err(ImportError::UnknownField(field.clone()));
+        }
+
+        let find = |column: &ColumnRef| match column {
+            ColumnRef::Index(i) => Ok(*i),
+            ColumnRef::Name(name) => header
+                .iter()
+                .position(|column| column.trim() == name)
+                .ok_or_else(|| ImportError::UnknownColumn(name.clone())),
+        };
+
+        let mut indices = DEFAULT_INDICES;
+
+        for (index, field) in indices.iter_mut().zip(FIELDS) {
+            *index = match self.0.get(field) {
+                Some(column) => find(column)?,
+                None => return Err(ImportError::UnmappedField(field)),
+            };
+        }
+
+        let mut optional = [None; OPTIONAL_FIELDS.len()];
+
+        for (index, field) in optional.iter_mut().zip(OPTIONAL_FIELDS) {
+            *index = self.0.get(field).map(find).transpose()?;
+        }
+
+        Ok((indices, optional))
+    }
+}
+
+/// What to do with a field that's longer than the limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OversizedFields {
+    /// Skip the whole row
+    #[default]
+    Skip,
+    /// Cut the field down to the limit, and keep the row
+    Truncate,
+}
+
+impl FromStr for OversizedFields {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "skip" => Ok(OversizedFields::Skip),
+            "truncate" => Ok(OversizedFields::Truncate),
+            other => Err(format!("unknown oversized field mode `{other}`")),
+        }
+    }
+}
+
+/// What to do with a field that has control characters in it, like null bytes
+/// or vertical tabs, which can break whatever reads the data later.
+/// Tabs and line breaks are fine, since quoted fields can have them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ControlCharacters {
+    /// Remove the control characters, and keep the row
+    #[default]
+    Strip,
+    /// Skip the whole row
+    Reject,
+}
+
+impl FromStr for ControlCharacters {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "strip" => Ok(ControlCharacters::Strip),
+            "reject" => Ok(ControlCharacters::Reject),
+            other => Err(format!("unknown control character mode `{other}`")),
+        }
+    }
+}
+
+/// Checks for the control characters that [`ControlCharacters`] handles
+fn is_stray_control(c: char) -> bool {
+    c.is_control() && !matches!(c, '\t' | '\n' | '\r')
+}
+
+/// Strips the control characters out of a value, and cuts it down to the
+/// length limit, so that huge values don't take up memory. Rows with values
+/// that had to be kept as they were have already been skipped.
+fn clean_field<'a>(value: &'a str, options: &ImportOptions) -> Cow<'a, str> {
+    let value = if value.contains(is_stray_control) {
+        Cow::Owned(value.replace(is_stray_control, ""))
+    } else {
+        Cow::Borrowed(value)
+    };
+
+    match options.max_field_len {
+        Some(max) if value.chars().count() > max => Cow::Owned(value.chars().take(max).collect()),
+        _ => value,
+    }
+}
+
+/// Settings that control how a CSV file is imported
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// Where to find each field. Without a mapping,
+    /// the columns must be in the same order as [`FIELDS`].
+    pub mapping: Option<ColumnMapping>,
+    /// Keep rows that are missing columns, instead of skipping them.
+    /// The missing fields are left empty, and the property is flagged as incomplete.
+    pub keep_partial: bool,
+    /// The most data rows an upload can have, not counting the header,
+    /// across all of its files
+    pub max_rows: Option<usize>,
+    /// How many rows the files before this one had, which count towards `max_rows`
+    pub previous_rows: usize,
+    /// The most bytes the CSV files in a ZIP archive can unpack to
+    pub max_archive_bytes: Option<usize>,
+    /// Which worksheet to import from an Excel workbook, by name or index.
+    /// Defaults to the first one.
+    pub sheet: Option<String>,
+    /// Fail the whole import if any row is invalid, instead of skipping it
+    pub strict: bool,
+    /// Extra rules that rows have to follow, or else they're skipped
+    pub rules: ValidationRules,
+    /// Which row is the header, counting from 0. Any rows before it are ignored.
+    pub header_row: usize,
+    /// Skip rows with values that can't be right, like negative prices
+    pub sanity_check: bool,
+    /// The most characters a field can have
+    pub max_field_len: Option<usize>,
+    /// What to do with fields that are longer than `max_field_len`
+    pub oversized_fields: OversizedFields,
+    /// What to do with fields that have control characters in them
+    pub control_characters: ControlCharacters,
+    /// Keep each row as it was written, for tracking down how it was parsed.
+    /// This is off by default, since it takes as much memory again as the data.
+    /// Every cell of the row then has to follow the limits on the fields.
+    pub keep_raw: bool,
+    /// Other names for stations, which are replaced with the names we store
+    pub station_aliases: StationAliases,
+    /// Maps the property types to the canonical types stored alongside them
+    pub property_types: TypeMapping,
+    /// The threads to parse large files on.
+    /// Without them, files are parsed on the calling thread.
+    pub parse_pool: Option<Arc<ThreadPool>>,
+    /// The quote and escape characters that CSV files are written with.
+    /// The delimiter is detected from each file, so it's ignored here.
+    pub dialect: Dialect,
+    /// Only parse this many rows after the header, and ignore the rest
+    pub row_limit: Option<usize>,
+    /// Added to the row numbers to get the ids, when a file has no id column.
+    /// This lets several files be imported together without their ids clashing.
+    pub id_offset: usize,
+}
+
+impl ImportOptions {
+    /// The options the operator configured, which apply to every import.
+    /// Callers fill in the rest from the request.
+    pub fn from_config(config: &Config) -> Self {
+        ImportOptions {
+            max_rows: config.max_rows,
+            max_archive_bytes: Some(config.max_archive_bytes),
+            max_field_len: config.max_field_len,
+            oversized_fields: config.oversized_fields,
+            control_characters: config.control_characters,
+            keep_raw: config.store_raw_rows,
+            rules: config.validation_rules.clone(),
+            station_aliases: config.station_aliases.clone(),
+            property_types: config.property_types.clone(),
+            parse_pool: config.parse_pool.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Parses the CSV text into properties.
+///
+/// The first row is treated as the header, unless `header_row` says otherwise.
+/// Rows that are missing columns are skipped, unless `keep_partial` is set.
+///
+/// The last row is imported whether or not it ends with a newline,
+/// and rows can end with either `\n` or `\r\n`. Fields can be quoted,
+/// using the quote and escape characters from the `dialect` option.
+///
+/// Fields can be separated by commas, tabs, or semicolons,
+/// which we detect from the header row.
+pub fn parse_csv(text: &str, options: &ImportOptions) -> Result<Import, ImportError> {
+    let dialect = Dialect {
+        delimiter: csv::sniff_delimiter(text, options.dialect.quote, options.header_row),
+        ..options.dialect
+    };
+
+    parse_rows(csv::records(text, dialect), options)
+}
+
+/// A row of cells, from either a CSV file or a spreadsheet
+pub trait Row: Sync {
+    type Cell: AsRef<str> + Sync;
+
+    fn cells(&self) -> &[Self::Cell];
+
+    /// The row as it was written in the file. By default, this is the
+    /// cells joined with commas, as if the row had been saved as CSV.
+    fn raw(&self) -> String {
+        self.cells()
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl<S: AsRef<str> + Sync> Row for Vec<S> {
+    type Cell = S;
+
+    fn cells(&self) -> &[S] {
+        self
+    }
+}
+
+impl Row for csv::Record<'_> {
+    type Cell = String;
+
+    fn cells(&self) -> &[String] {
+        &self.fields
+    }
+
+    fn raw(&self) -> String {
+        self.raw.to_string()
+    }
+}
+
+impl<T: Row> Row for &T {
+    type Cell = T::Cell;
+
+    fn cells(&self) -> &[T::Cell] {
+        (*self).cells()
+    }
+
+    fn raw(&self) -> String {
+        (*self).raw()
+    }
+}
+
+/// Parses rows that have already been split into columns into properties.
+/// This lets us share the same logic between CSV files and spreadsheets.
+///
+/// The first row is treated as the header, unless `header_row` says otherwise.
+pub fn parse_rows<R: Row>(
+    rows: impl Iterator<Item = R>,
+    options: &ImportOptions,
+) -> Result<Import, ImportError> {
+    // Anything before the header is metadata, like a title or an export date
+    let mut rows = rows.skip(options.header_row);
+
+    let header = rows.next();
+    let header: Vec<&str> = header
+        .as_ref()
+        .map(|header| header.cells().iter().map(AsRef::as_ref).collect())
+        .unwrap_or_default();
+
+    let rows = rows.take(options.row_limit.unwrap_or(usize::MAX));
+
+    // Each row is split out of the file as it's read, so we stop reading
+    // as soon as there's one too many, instead of splitting up the whole file
+    let rows: Vec<R> = match options.max_rows {
+        Some(max_rows) => {
+            let remaining = max_rows.saturating_sub(options.previous_rows);
+            let rows: Vec<R> = rows.take(remaining.saturating_add(1)).collect();
+            if rows.len() > remaining {
+                return Err(ImportError::TooManyRows(max_rows));
+            }
+            rows
+        }
+        None => rows.collect(),
+    };
+
+    let (indices, optional) = match &options.mapping {
+        Some(mapping) => mapping.resolve(&header)?,
+        None => detect_columns(&header),
+    };
+
+    let mut import = Import {
+        row_count: rows.len(),
+        ..Default::default()
+    };
+
+    // The row each id was last seen in, and where its property is in the list,
+    // so that a file with an id column can't use the same id twice
+    let mut seen_ids: HashMap<usize, (usize, usize)> = HashMap::new();
+    let mut replaced = vec![];
+
+    // Map those columns into properties
+    // We increment the index to start from 1.
+    // This way, we can match the rows in the CSV file
+    let parse = |(i, columns): (usize, &R)| {
+        let id = options.id_offset.saturating_add(i + 1);
+        parse_row(id, columns, &indices, &optional, options)
+    };
+
+    // Each row only depends on itself, so large files can be parsed on several
+    // threads. The results are collected in file order either way, so the ids
+    // and everything after this come out the same as parsing them one by one.
+    // Small files are quicker to parse on one thread than to split up
+    let pool = options
+        .parse_pool
+        .as_deref()
+        .filter(|_| rows.len() >= 2 * PARALLEL_CHUNK_ROWS);
+    let parsed: Vec<_> = match pool {
+        Some(pool) => pool.install(|| {
+            rows.par_iter()
+                .enumerate()
+                .with_min_len(PARALLEL_CHUNK_ROWS)
+                .map(parse)
+                .collect()
+        }),
+        None => rows.iter().enumerate().map(parse).collect(),
+    };
+
+    for (i, parsed) in parsed.into_iter().enumerate() {
+        let row = i + 1;
+
+        match parsed {
+            Ok(Some(property)) => {
+                // When an id is repeated, the last row with it wins,
+                // and the earlier one is reported as skipped
+                let position = import.properties.len();
+                if let Some((earlier_row, earlier_position)) =
+                    seen_ids.insert(property.id, (row, position))
+                {
+                    replaced.push(earlier_position);
+                    import.skipped.push(SkippedRow {
+                        row: earlier_row,
+                        reason: format!("id {} is used again in row {row}", property.id),
+                    });
+                }
+
+                import.warnings.extend(
+                    warnings(&property)
+                        .into_iter()
+                        .map(|reason| RowWarning { row, reason }),
+                );
+
+                import.properties.push(property);
+                import.rows.push(row);
+            }
+            Ok(None) => {}
+            Err(reason) => import.skipped.push(SkippedRow { row, reason }),
+        }
+    }
+
+    import.remove_replaced(replaced);
+
+    if options.strict && !import.skipped.is_empty() {
+        return Err(ImportError::InvalidRows(import.skipped));
+    }
+
+    Ok(import)
+}
+
+/// The fewest rows we hand to a thread at once when parsing in parallel,
+/// so that the threads spend their time parsing rather than coordinating
+const PARALLEL_CHUNK_ROWS: usize = 1024;
+
+/// Builds the thread pool to parse large files with, which is shared by
+/// every import. There's no pool for a single thread, since files are
+/// parsed on the calling thread without one.
+pub fn parse_pool(threads: usize) -> Result<Option<ThreadPool>, ThreadPoolBuildError> {
+    if threads <= 1 {
+        return Ok(None);
+    }
+
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("parse-{i}"))
+        .build()
+        .map(Some)
+}
+
+/// Finds anything about an imported property that looks wrong,
+/// but isn't bad enough to skip it for
+fn warnings(property: &Property) -> Vec<String> {
+    let incomplete = (!property.complete).then(|| "missing columns were left empty".to_string());
+
+    let unparsed = [
+        ("price", &property.price, property.price_value().is_some()),
+        (
+            "land_area",
+            &property.land_area,
+            property.land_area_value().is_some(),
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, value, parsed)| !value.trim().is_empty() && !parsed)
+    .map(|(field, value, _)| format!("`{field}` isn't a number we understand: `{value}`"));
+
+    incomplete.into_iter().chain(unparsed).collect()
+}
+
+/// Parses a single row, returning `None` for blank rows
+/// and the reason the row was skipped if it's invalid,
+/// including if it breaks any of the validation rules or sanity checks
+fn parse_row(
+    id: usize,
+    row: &impl Row,
+    indices: &ColumnIndices,
+    optional: &OptionalIndices,
+    options: &ImportOptions,
+) -> Result<Option<Property>, String> {
+    let columns = row.cells();
+
+    // A blank line isn't a partial row, it's just not a row at all
+    if columns.iter().all(|value| value.as_ref().trim().is_empty()) {
+        return Ok(None);
+    }
+
+    let complete = indices.iter().all(|&index| index < columns.len());
+
+    if !complete && !options.keep_partial {
+        let expected = indices.iter().max().map_or(0, |index| index + 1);
+        return Err(format!(
+            "expected at least {expected} columns, found {}",
+            columns.len()
+        ));
+    }
+
+    // The cells that end up stored, by their position in the row, which all
+    // have to follow the limits. When the raw row is kept, that's every cell.
+    let stored = || {
+        let fields = indices
+            .iter()
+            .filter_map(|&index| Some((index, columns.get(index)?.as_ref())));
+        let others = columns
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| options.keep_raw && !indices.contains(index))
+            .map(|(index, value)| (index, value.as_ref()));
+
+        fields.chain(others)
+    };
+    let describe = |index: usize| match indices.iter().position(|&field| field == index) {
+        Some(field) => format!("`{}`", FIELDS[field]),
+        None => format!("column {}", index + 1),
+    };
+
+    if let (Some(max), OversizedFields::Skip) = (options.max_field_len, options.oversized_fields) {
+        if let Some((index, _)) = stored().find(|(_, value)| value.chars().count() > max) {
+            return Err(format!(
+                "{} is longer than the limit of {max} characters",
+                describe(index)
+            ));
+        }
+    }
+
+    if options.control_characters == ControlCharacters::Reject {
+        let found = stored().find_map(|(index, value)| {
+            value
+                .chars()
+                .find(|&c| is_stray_control(c))
+                .map(|c| (index, c))
+        });
+
+        if let Some((index, c)) = found {
+            return Err(format!(
+                "{} contains the control character U+{:04X}",
+                describe(index),
+                c as u32
+            ));
+        }
+    }
+
+    // Pull each value out of its mapped column and convert it to an owned string.
+    // Missing columns become empty strings, which only happens for partial rows.
+    let column = |field: usize| {
+        let value = columns
+            .get(indices[field])
+            .map_or("", |value| value.as_ref());
+
+        clean_field(value, options).into_owned()
+    };
+
+    // The raw row is only written out again from its cells
+    // if cleaning them up changed something
+    let raw = options.keep_raw.then(|| {
+        let cleaned: Vec<Cow<str>> = columns
+            .iter()
+            .map(|value| clean_field(value.as_ref(), options))
+            .collect();
+
+        if cleaned.iter().any(|value| matches!(value, Cow::Owned(_))) {
+            cleaned.join(",")
+        } else {
+            row.raw()
+        }
+    });
+
+    // Coordinates that are missing or out of range are just left out,
+    // since a property is still useful without them
+    let coordinate = |field: usize, limit: f64| {
+        optional[field]
+            .and_then(|index| columns.get(index))
+            .and_then(|value| value.as_ref().trim().parse::<f64>().ok())
+            .filter(|value| value.abs() <= limit)
+    };
+
+    // Files exported from here, or from other systems, can carry their own ids.
+    // Otherwise, the row number is used.
+    let id = match optional[2].and_then(|index| columns.get(index)) {
+        Some(value) => value
+            .as_ref()
+            .trim()
+            .parse()
+            .map_err(|_| format!("`id` must be a whole number, found `{}`", value.as_ref()))?,
+        None => id,
+    };
+
+    if id > MAX_ID {
+        return Err(format!("`id` can be at most {MAX_ID}, found {id}"));
+    }
+
+    // NOTE: The field numbers here must match the order of `FIELDS`
+    // and `OPTIONAL_FIELDS`
+    let property = Property {
+        id,
+        prefecture: column(0),
+        city: column(1),
+        town: column(2),
+        chome: column(3),
+        banchi: column(4),
+        go: column(5),
+        building: column(6),
+        price: column(7),
+        nearest_station: options.station_aliases.canonicalize(&column(8)).to_string(),
+        canonical_type: options.property_types.canonicalize(&column(9)),
+        property_type: column(9),
+        land_area: column(10),
+        complete,
+        latitude: coordinate(0, 90.0),
+        longitude: coordinate(1, 180.0),
+        deleted: false,
+        raw,
+        updated_at: None,
+    };
+
+    options.rules.check(&property)?;
+
+    if options.sanity_check {
+        validation::sanity_check(&property)?;
+    }
+
+    Ok(Some(property))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str =
+        "prefecture,city,town,chome,banchi,go,building,price,nearest_station,property_type,land_area\n";
+
+    fn csv(rows: &[&str]) -> String {
+        let mut text = HEADER.to_string();
+        for row in rows {
+            text.push_str(row);
+            text.push('\n');
+        }
+        text
+    }
+
+    #[test]
+    fn parses_the_same_on_several_threads() {
+        let rows: Vec<String> = (0..5000)
+            .map(|i| match i % 100 {
+                0 => "bad,row".to_string(),
+                _ => format!("東京都,渋谷区,神南,1,2,{i},,{i}万円,渋谷,マンション,50"),
+            })
+            .collect();
+        let rows: Vec<&str> = rows.iter().map(String::as_str).collect();
+        let text = csv(&rows);
+
+        let serial = parse_csv(&text, &ImportOptions::default()).unwrap();
+        let options = ImportOptions {
+            parse_pool: parse_pool(4).unwrap().map(Arc::new),
+            ..Default::default()
+        };
+        let parallel = parse_csv(&text, &options).unwrap();
+
+        assert_eq!(parallel.properties.len(), serial.properties.len());
+        assert!(parallel
+            .properties
+            .iter()
+            .zip(&serial.properties)
+            .all(|(a, b)| a.id == b.id && a.go == b.go));
+        assert_eq!(parallel.skipped, serial.skipped);
+    }
+
+    #[test]
+    fn has_no_pool_for_one_thread() {
+        assert!(parse_pool(1).unwrap().is_none());
+        assert!(parse_pool(2).unwrap().is_some());
+    }
+
+    fn mapping(json: &str) -> ColumnMapping {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn reads_columns_through_a_mapping() {
+        let text = "価格,県,市,町,丁目,番地,号,建物,駅,種別,面積\n\
+                    1000万円,東京都,渋谷区,神南,1,2,3,,渋谷,土地,100\n";
+        let options = ImportOptions {
+            mapping: Some(mapping(
+                r#"{
+                    "prefecture": "県", "city": "市", "town": "町", "chome": 4, "banchi": 5,
+                    "go": 6, "building": 7, "price": "価格", "nearest_station": 8,
+                    "property_type": 9, "land_area": "面積"
+                }"#,
+            )),
+            ..Default::default()
+        };
+
+        let import = parse_csv(text, &options).unwrap();
+        let property = &import.properties[0];
+        assert_eq!(property.prefecture, "東京都");
+        assert_eq!(property.city, "渋谷区");
+        assert_eq!(property.chome, "1");
+        assert_eq!(property.price, "1000万円");
+        assert_eq!(property.land_area, "100");
+    }
+
+    #[test]
+    fn rejects_a_mapping_with_unknown_or_missing_fields() {
+        let text = csv(&[]);
+        let parse = |json: &str| {
+            let options = ImportOptions {
+                mapping: Some(mapping(json)),
+                ..Default::default()
+            };
+            parse_csv(&text, &options).unwrap_err()
+        };
+
+        assert_eq!(
+            parse(r#"{ "prefectur": 0 }"#),
+            ImportError::UnknownField("prefectur".to_string())
+        );
+        assert_eq!(
+            parse(r#"{ "prefecture": 0 }"#),
+            ImportError::UnmappedField("city")
+        );
+        assert_eq!(
+            parse(r#"{ "prefecture": "県" }"#),
+            ImportError::UnknownColumn("県".to_string())
+        );
+    }
+
+    #[test]
+    fn keeps_partial_rows_flagged_as_incomplete() {
+        let text = csv(&[
+            "東京都,渋谷区,神南",
+            "東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100",
+        ]);
+
+        let import = parse_csv(&text, &ImportOptions::default()).unwrap();
+        assert_eq!(import.properties.len(), 1);
+        assert_eq!(
+            import.skipped,
+            [SkippedRow {
+                row: 1,
+                reason: "expected at least 11 columns, found 3".to_string(),
+            }]
+        );
+
+        let options = ImportOptions {
+            keep_partial: true,
+            ..Default::default()
+        };
+        let import = parse_csv(&text, &options).unwrap();
+        let partial = &import.properties[0];
+        assert!(!partial.complete);
+        assert_eq!(partial.town, "神南");
+        assert_eq!(partial.price, "");
+        assert!(import.properties[1].complete);
+        assert_eq!(import.warnings[0].row, 1);
+    }
+
+    #[test]
+    fn rejects_files_over_the_row_limit() {
+        let row = "東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100";
+        let options = ImportOptions {
+            max_rows: Some(2),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            parse_csv(&csv(&[row, row]), &options)
+                .unwrap()
+                .properties
+                .len(),
+            2
+        );
+        assert_eq!(
+            parse_csv(&csv(&[row, row, row]), &options).unwrap_err(),
+            ImportError::TooManyRows(2)
+        );
+
+        // Rows from the files before this one count too
+        let options = ImportOptions {
+            previous_rows: 1,
+            ..options
+        };
+        assert_eq!(
+            parse_csv(&csv(&[row, row]), &options).unwrap_err(),
+            ImportError::TooManyRows(2)
+        );
+    }
+
+    #[test]
+    fn fails_a_strict_import_with_any_invalid_row() {
+        let text = csv(&[
+            "東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100",
+            "bad,row",
+        ]);
+        let options = ImportOptions {
+            strict: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            parse_csv(&text, &options).unwrap_err(),
+            ImportError::InvalidRows(vec![SkippedRow {
+                row: 2,
+                reason: "expected at least 11 columns, found 2".to_string(),
+            }])
+        );
+        assert_eq!(
+            parse_csv(
+                &csv(&["東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100"]),
+                &options
+            )
+            .unwrap()
+            .properties
+            .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn reads_the_header_from_a_later_row() {
+        let text = format!(
+            "物件一覧\n出力日,2024-06-01\n{}",
+            csv(&["東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100"])
+        );
+        let options = ImportOptions {
+            header_row: 2,
+            ..Default::default()
+        };
+
+        let import = parse_csv(&text, &options).unwrap();
+        assert_eq!(import.properties.len(), 1);
+        assert!(import.skipped.is_empty());
+        // Rows are still counted from the header
+        assert_eq!(import.properties[0].id, 1);
+        assert_eq!(import.rows, [1]);
+    }
+
+    #[test]
+    fn imports_the_last_row_without_a_trailing_newline() {
+        let row = "東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100";
+
+        for text in [
+            format!("{HEADER}{row}"),
+            format!("{HEADER}{row}\r"),
+            csv(&[row]).replace('\n', "\r\n").trim_end().to_string(),
+        ] {
+            let import = parse_csv(&text, &ImportOptions::default()).unwrap();
+
+            assert_eq!(import.properties.len(), 1);
+            assert!(import.skipped.is_empty());
+            assert_eq!(import.properties[0].land_area, "100");
+        }
+    }
+
+    #[test]
+    fn finds_columns_by_their_header_names() {
+        let text =
+            "価格,都道府県,市区町村,町名,丁目,番地,号,建物名,最寄駅,物件タイプ,敷地面積,緯度\n\
+                    1000万円,東京都,渋谷区,神南,1,2,3,,渋谷,土地,100,35.6\n";
+        let import = parse_csv(text, &ImportOptions::default()).unwrap();
+
+        let property = &import.properties[0];
+        assert_eq!(property.prefecture, "東京都");
+        assert_eq!(property.price, "1000万円");
+        assert_eq!(property.latitude, Some(35.6));
+        // Older files without the optional columns still import
+        assert_eq!(property.longitude, None);
+
+        // A header we don't recognize falls back to the documented order
+        let text =
+            csv(&["東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100"]).replacen("city", "市", 1);
+        let import = parse_csv(&text, &ImportOptions::default()).unwrap();
+        assert_eq!(import.properties[0].city, "渋谷区");
+    }
+
+    #[test]
+    fn skips_or_truncates_fields_over_the_length_limit() {
+        let text = csv(&[
+            "東京都,渋谷区,神南,1,2,3,渋谷スクランブルスクエア,1000万円,渋谷,土地,100",
+            "東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100",
+        ]);
+
+        let options = ImportOptions {
+            max_field_len: Some(6),
+            ..Default::default()
+        };
+        let import = parse_csv(&text, &options).unwrap();
+        assert_eq!(import.properties.len(), 1);
+        assert_eq!(
+            import.skipped,
+            vec![SkippedRow {
+                row: 1,
+                reason: "`building` is longer than the limit of 6 characters".to_string(),
+            }]
+        );
+
+        let options = ImportOptions {
+            oversized_fields: OversizedFields::Truncate,
+            ..options
+        };
+        let import = parse_csv(&text, &options).unwrap();
+        assert!(import.skipped.is_empty());
+        // The limit is in characters, not bytes
+        assert_eq!(import.properties[0].building, "渋谷スクラン");
+    }
+
+    #[test]
+    fn strips_or_rejects_control_characters() {
+        let text = csv(&[
+            "東京都,渋谷区,神\u{0}南,1,2,3,\"渋谷\tビル\",1000万円,渋谷,土地,100",
+            "大阪府,大阪市,梅田\u{b},2,3,4,,5000万円,梅田,マンション,80",
+        ]);
+
+        let import = parse_csv(&text, &ImportOptions::default()).unwrap();
+        assert!(import.skipped.is_empty());
+        assert_eq!(import.properties[0].town, "神南");
+        // Tabs can be part of a quoted field
+        assert_eq!(import.properties[0].building, "渋谷\tビル");
+        assert_eq!(import.properties[1].town, "梅田");
+
+        let options = ImportOptions {
+            control_characters: ControlCharacters::Reject,
+            ..Default::default()
+        };
+        let import = parse_csv(&text, &options).unwrap();
+        assert!(import.properties.is_empty());
+        assert_eq!(
+            import.skipped[0].reason,
+            "`town` contains the control character U+0000"
+        );
+        assert_eq!(
+            import.skipped[1].reason,
+            "`town` contains the control character U+000B"
+        );
+    }
+
+    #[test]
+    fn keeps_the_raw_row_only_when_asked() {
+        let row = "東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100";
+        let text = csv(&[row]);
+
+        let import = parse_csv(&text, &ImportOptions::default()).unwrap();
+        assert_eq!(import.properties[0].raw, None);
+
+        let options = ImportOptions {
+            keep_raw: true,
+            ..Default::default()
+        };
+        let import = parse_csv(&text, &options).unwrap();
+        assert_eq!(import.properties[0].raw.as_deref(), Some(row));
+    }
+
+    #[test]
+    fn holds_the_raw_row_to_the_field_limits() {
+        // The extra column isn't a field, but it's still part of the raw row
+        let text =
+            csv(&["東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100,,,\"とても\u{0}長いメモ\""]);
+        let options = ImportOptions {
+            keep_raw: true,
+            max_field_len: Some(4),
+            oversized_fields: OversizedFields::Truncate,
+            ..Default::default()
+        };
+
+        let import = parse_csv(&text, &options).unwrap();
+        assert_eq!(
+            import.properties[0].raw.as_deref(),
+            Some("東京都,渋谷区,神南,1,2,3,,1000,渋谷,土地,100,,,とても長")
+        );
+
+        let options = ImportOptions {
+            oversized_fields: OversizedFields::Skip,
+            max_field_len: Some(8),
+            control_characters: ControlCharacters::Reject,
+            ..options
+        };
+        let import = parse_csv(&text, &options).unwrap();
+        assert_eq!(
+            import.skipped[0].reason,
+            "column 14 contains the control character U+0000"
+        );
+    }
+}
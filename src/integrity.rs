@@ -0,0 +1,94 @@
+//! Checking the quality of the data we've imported
+
+use serde::Serialize;
+
+use crate::{prefecture, property::Property};
+
+/// Counts of the properties that have each kind of problem.
+/// A property with several problems is counted once for each of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct IntegrityReport {
+    pub total: usize,
+    pub unparseable_price: usize,
+    pub unparseable_land_area: usize,
+    /// Properties missing a prefecture, city, or town.
+    /// The block numbers and building are often legitimately empty,
+    /// so they aren't checked.
+    pub missing_address: usize,
+    /// Properties whose prefecture isn't one of the 47 we know about
+    pub unknown_prefecture: usize,
+}
+
+/// Scans the properties for problems
+pub fn check<'a>(properties: impl IntoIterator<Item = &'a Property>) -> IntegrityReport {
+    let mut report = IntegrityReport::default();
+
+    for property in properties {
+        report.total += 1;
+
+        if property.price_value().is_none() {
+            report.unparseable_price += 1;
+        }
+
+        if property.land_area_value().is_none() {
+            report.unparseable_land_area += 1;
+        }
+
+        if [&property.prefecture, &property.city, &property.town]
+            .iter()
+            .any(|field| field.trim().is_empty())
+        {
+            report.missing_address += 1;
+        }
+
+        if prefecture::find_by_name(&property.prefecture).is_none() {
+            report.unknown_prefecture += 1;
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    use super::*;
+
+    fn property(prefecture: &str, town: &str, price: &str, land_area: &str) -> Property {
+        let input = PropertyInput {
+            prefecture: prefecture.to_string(),
+            city: "渋谷区".to_string(),
+            town: town.to_string(),
+            price: price.to_string(),
+            land_area: land_area.to_string(),
+            ..Default::default()
+        };
+        Property::from_input(
+            1,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        )
+    }
+
+    #[test]
+    fn counts_each_kind_of_problem() {
+        let properties = [
+            property("東京都", "神南", "1000万円", "100"),
+            property("東京都", "", "未定", "100"),
+            property("東京", "神南", "1000万円", "広い"),
+        ];
+
+        assert_eq!(
+            check(&properties),
+            IntegrityReport {
+                total: 3,
+                unparseable_price: 1,
+                unparseable_land_area: 1,
+                missing_address: 1,
+                unknown_prefecture: 1,
+            }
+        );
+    }
+}
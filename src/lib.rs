@@ -1 +1,35 @@
+pub mod address;
+pub mod archive;
+pub mod compression;
+pub mod config;
+pub mod csv;
+pub mod db;
+pub mod diff;
+pub mod error;
+pub mod export;
+pub mod extract;
+pub mod fetch;
+pub mod filter;
+pub mod formatter;
+pub mod health;
+pub mod idempotency;
+pub mod import;
+pub mod integrity;
+pub mod logging;
+pub mod numbers;
+pub mod original;
+pub mod pagination;
+pub mod prefecture;
 pub mod property;
+pub mod property_type;
+pub mod range;
+pub mod response;
+pub mod response_cache;
+pub mod similar;
+pub mod snapshot;
+pub mod sort;
+pub mod station;
+pub mod stats;
+pub mod timestamp;
+pub mod validation;
+pub mod xlsx;
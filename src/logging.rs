@@ -0,0 +1,56 @@
+//! Setting up structured logging with `tracing`
+
+use std::str::FromStr;
+
+use tracing_subscriber::EnvFilter;
+
+/// How log lines are written out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines, for local development
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log aggregators
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "pretty" | "text" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format `{other}`")),
+        }
+    }
+}
+
+/// Installs the global tracing subscriber.
+///
+/// The log level can be set with `RUST_LOG`, and defaults to `info`.
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match format {
+        LogFormat::Pretty => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_log_format() {
+        assert_eq!(" JSON ".parse(), Ok(LogFormat::Json));
+        assert_eq!("pretty".parse(), Ok(LogFormat::Pretty));
+        assert_eq!("text".parse(), Ok(LogFormat::Pretty));
+        assert_eq!(
+            "xml".parse::<LogFormat>(),
+            Err("unknown log format `xml`".to_string())
+        );
+    }
+}
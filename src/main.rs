@@ -3,55 +3,363 @@
 use core::str;
 
 use axum::{
+    body::{Body, Bytes},
     debug_handler,
-    extract::{Json, Multipart, Path, State},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{FromRef, Multipart, RawQuery, Request, State},
+    http::{header, Extensions, HeaderMap, HeaderName, HeaderValue, StatusCode, Version},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
     routing::{get, post},
     Router,
 };
 
-use tokio::sync::RwLock;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, RwLock, RwLockWriteGuard};
+use tower_http::compression::{
+    predicate::{DefaultPredicate, Predicate},
+    CompressionLayer,
+};
+use tower_http::trace::TraceLayer;
 
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    sync::Arc,
+    time::Instant,
+};
 
-use japanese_properties_api::property::Property;
+use japanese_properties_api::{
+    address::{self, AddressParts},
+    archive,
+    compression::{self, Encoding},
+    config::Config,
+    csv::{self, Dialect, InvalidDialect},
+    db::{DbSnapshot, DbView, ShardedDb, Values},
+    diff,
+    error::ApiError,
+    export::{self, CsvWriter, ExportOptions, FixedWidthSpec, StreamMode},
+    extract::{Json, Path, Query},
+    fetch,
+    filter::PropertyFilter,
+    formatter::AddressFormat,
+    health::{self, MemoryUsage},
+    idempotency::{self, CachedResponse, IdempotencyCache},
+    import::{self, ColumnMapping, Import, ImportError, ImportOptions, SkippedRow},
+    integrity::{self, IntegrityReport},
+    logging,
+    original::OriginalFile,
+    pagination::{paginate, PageParams},
+    prefecture,
+    property::{Db, FormattedId, IdFormat, Property, PropertyInput, ViewOptions, MAX_ID},
+    property_type::TypeMapping,
+    range::{self, ByteRange},
+    response::{self, json_response, EmptyParams, EmptyResponse, CSV_CONTENT_TYPE},
+    response_cache::{self, ResponseCache},
+    similar, snapshot,
+    sort::{self, SortKey, SortParams},
+    station::StationAliases,
+    stats::{self, Bounds, Bucket, NumericField},
+    validation::{ValidationReport, ValidationRules},
+    xlsx,
+};
 
-/// Our app uses a HashMap as a lazy implementation
+/// Our app uses BTreeMaps as a lazy implementation
 /// of an in-memory database
-#[derive(Clone, Default)]
+#[derive(Default)]
 struct AppState {
-    db: HashMap<usize, Property>,
+    db: ShardedDb,
+    /// The SHA-256 checksum of the last successfully uploaded file,
+    /// as a lowercase hex string.
+    ///
+    /// This and the last modified time have their own locks, since edits
+    /// to a single property change them without locking the whole state.
+    last_upload_checksum: Mutex<Option<String>>,
+    /// The data as it was just before the last upload replaced it,
+    /// so that we can show what the upload changed
+    previous_db: Db,
+    /// Recent upload responses, so that retried uploads aren't imported twice
+    idempotency_cache: IdempotencyCache,
+    /// The files from the last upload, so that they can be downloaded again
+    original_files: Vec<OriginalFile>,
+    /// When the data last changed, through an upload or an edit
+    last_modified: Mutex<Option<DateTime<Utc>>>,
+    /// Held while writing a snapshot, so that snapshots are written in order
+    snapshot_lock: Mutex<()>,
+    /// Recent list responses, which are cleared whenever the data changes
+    list_cache: Mutex<ResponseCache>,
+}
+
+impl AppState {
+    /// Records that the data was just edited. The db no longer matches the
+    /// last uploaded file, so uploading that same file again needs to re-import it.
+    async fn mark_edited(&self) {
+        *self.last_upload_checksum.lock().await = None;
+        *self.last_modified.lock().await = Some(Utc::now());
+        self.list_cache.lock().await.clear();
+    }
 }
 
 // We need to wrap our state in a RwLock so that we can
 // allow an arbitrary number of readers, but lock when
 // we have a writer.
+// Changes to the whole db, like uploads, take the write lock. Edits to a
+// single property only need the read lock, and then lock their own shard.
 // We then wrap in an Arc to make it thread safe
 type SharedState = Arc<RwLock<AppState>>;
 
+/// Everything our handlers have access to.
+/// The config never changes, so it lives outside of the lock.
+#[derive(Clone, FromRef)]
+struct AppContext {
+    state: SharedState,
+    config: Arc<Config>,
+}
+
 #[tokio::main]
 async fn main() {
-    let state = SharedState::default();
+    // Logging is set up from the config, so there's nowhere to log this to yet
+    let mut config = match Config::from_env() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+    logging::init(config.log_format);
+
+    // Importing data without the rules the operator asked for could let bad
+    // data in, so we refuse to start if they can't be loaded
+    if let Some(path) = &config.validation_rules_path {
+        match ValidationRules::load(path) {
+            Ok(rules) => config.validation_rules = rules,
+            Err(error) => {
+                tracing::error!(%error, path = %path.display(), "failed to load validation rules");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = &config.station_aliases_path {
+        match StationAliases::load(path) {
+            Ok(aliases) => config.station_aliases = aliases,
+            Err(error) => {
+                tracing::error!(%error, path = %path.display(), "failed to load station aliases");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    match import::parse_pool(config.parse_threads) {
+        Ok(pool) => config.parse_pool = pool.map(Arc::new),
+        Err(error) => {
+            tracing::warn!(%error, "failed to start the parse threads, parsing on one instead");
+        }
+    }
+
+    if let Some(path) = &config.property_types_path {
+        match TypeMapping::load(path) {
+            Ok(mapping) => config.property_types = mapping,
+            Err(error) => {
+                tracing::error!(%error, path = %path.display(), "failed to load property types");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut db = Db::new();
+
+    if let Some(path) = &config.snapshot_path {
+        match snapshot::load(path).await {
+            Ok(Some(snapshot)) => {
+                tracing::info!(count = snapshot.len(), path = %path.display(), "loaded snapshot");
+                db = snapshot;
+
+                // The property types may have been mapped differently
+                // since the snapshot was saved
+                for property in db.values_mut() {
+                    property.canonical_type =
+                        config.property_types.canonicalize(&property.property_type);
+                }
+            }
+            Ok(None) => {}
+            Err(error) => {
+                tracing::error!(%error, path = %path.display(), "failed to load snapshot")
+            }
+        }
+    }
+
+    // A snapshot holds data that users have uploaded since the seed,
+    // so we only fall back to the seed file if there's no snapshot
+    let seed_file = config.seed_file.as_ref().filter(|_| db.is_empty());
+
+    if let Some(path) = seed_file {
+        match load_seed_file(path, &config).await {
+            Ok(properties) => {
+                tracing::info!(count = properties.len(), path = %path.display(), "seeded db");
+                db = properties
+                    .into_iter()
+                    .map(|property| (property.id, property))
+                    .collect();
+            }
+            Err(error) => tracing::error!(%error, path = %path.display(), "failed to seed db"),
+        }
+    }
+
+    let app_state = AppState {
+        db: db.into(),
+        ..Default::default()
+    };
+
+    let context = AppContext {
+        state: Arc::new(RwLock::new(app_state)),
+        config: Arc::new(config),
+    };
 
-    let app = Router::new()
+    let port = match std::env::var("PORT").map(|port| port.parse::<u16>()) {
+        Ok(Ok(port)) => port,
+        Err(_) => 3000,
+        Ok(Err(error)) => {
+            tracing::error!(%error, "invalid PORT");
+            std::process::exit(1);
+        }
+    };
+
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    let listener = tokio::net::TcpListener::bind(&address).await.unwrap();
+
+    tracing::info!("Listening on http://{}", address);
+    axum::serve(listener, app(context)).await.unwrap();
+}
+
+/// The routes of the API, with every layer, serving from this context
+fn app(context: AppContext) -> Router {
+    let compression = compression_layer(&context.config);
+
+    Router::new()
         .route("/up", get(up))
+        .route("/version", get(version))
+        .route("/health", get(health_check))
+        .route("/health/integrity", get(integrity_check))
         .route("/properties", get(list_properties))
         .route("/properties/upload", post(upload_csv))
-        .route("/properties/:id", get(get_property))
-        .with_state(state)
-        .fallback(not_found);
+        .route("/properties/upload/url", post(upload_from_url))
+        .route("/properties/upload/original", get(download_original))
+        .route("/properties/validate", post(validate_csv))
+        .route("/properties/preview", post(preview_csv))
+        .route("/properties/diff", get(diff_last_upload))
+        .route("/properties/export", get(export_csv).post(export_selected))
+        .route("/properties/export/json", get(export_json))
+        .route("/properties/export/fixed", get(export_fixed_width))
+        .route("/properties/stream", get(stream_msgpack))
+        .route("/properties/upsert", post(upsert_properties))
+        .route("/properties/compact", post(compact_ids))
+        .route("/properties/batch", post(create_properties))
+        .route("/properties/price_histogram", get(price_histogram))
+        .route("/properties/price_percentiles", get(price_percentiles))
+        .route("/properties/bounds", get(properties_bounds))
+        .route("/properties/by_station", get(aggregate_by_station))
+        .route("/properties/crosstab", get(crosstab))
+        .route("/properties/ids", get(list_ids))
+        .route("/properties/range/:field", get(field_range))
+        .route(
+            "/properties/prefectures/:prefecture/cities",
+            get(cities_in_prefecture),
+        )
+        .route(
+            "/properties/:id",
+            get(get_property)
+                .put(replace_property)
+                .delete(delete_property),
+        )
+        .route("/properties/:id/full_address", get(get_full_address))
+        .route("/properties/:id/similar", get(get_similar))
+        .route("/address/parse", post(parse_address))
+        .fallback(not_found)
+        .layer(compression)
+        .layer(TraceLayer::new_for_http())
+        // This has to come after the compression layer,
+        // so that it runs first and the compression layer sees our choice
+        .layer(middleware::map_request_with_state(
+            context.config.clone(),
+            choose_encoding,
+        ))
+        // This goes on last, so that the time covers all the other layers
+        .layer(middleware::from_fn(response_time))
+        .with_state(context)
+}
 
-    let port = std::env::var("PORT")
-        .ok()
-        .and_then(|port| port.parse().ok())
-        .unwrap_or(3000);
+/// Imports a CSV file from disk, the same way as if it had been uploaded
+async fn load_seed_file(
+    path: &std::path::Path,
+    config: &Config,
+) -> Result<Vec<Property>, Box<dyn std::error::Error>> {
+    let text = tokio::fs::read_to_string(path).await?;
+    let options = ImportOptions::from_config(config);
 
-    let address = SocketAddr::from(([0, 0, 0, 0], port));
-    let listener = tokio::net::TcpListener::bind(&address).await.unwrap();
+    Ok(import::parse_csv(&text, &options)?.properties)
+}
+
+/// Builds the layer that compresses responses, using only the algorithms
+/// the operator has enabled
+fn compression_layer(config: &Config) -> CompressionLayer<impl Predicate> {
+    let enabled = |encoding| config.compression_algorithms.contains(&encoding);
+
+    // Compressing part of a response would make the byte range meaningless
+    let not_partial = |status: StatusCode, _: Version, _: &HeaderMap, _: &Extensions| {
+        status != StatusCode::PARTIAL_CONTENT
+    };
+
+    CompressionLayer::new()
+        .br(enabled(Encoding::Br))
+        .gzip(enabled(Encoding::Gzip))
+        .deflate(enabled(Encoding::Deflate))
+        .quality(config.compression_quality)
+        .compress_when(DefaultPredicate::new().and(not_partial))
+}
 
-    println!("Listening on http://{}", address);
-    axum::serve(listener, app).await.unwrap();
+/// Narrows the client's `Accept-Encoding` down to our preferred encoding
+/// out of the ones they accept, so that the compression layer uses it
+async fn choose_encoding(State(config): State<Arc<Config>>, mut request: Request) -> Request {
+    let chosen = request
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| compression::choose_encoding(value, &config.compression_algorithms));
+
+    match chosen {
+        Some(encoding) => request.headers_mut().insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        ),
+        None => request.headers_mut().remove(header::ACCEPT_ENCODING),
+    };
+
+    request
+}
+
+/// The header we use to report how long the server spent on a request
+const RESPONSE_TIME_MS: HeaderName = HeaderName::from_static("x-response-time-ms");
+
+/// Adds how long we took to handle the request to the response, in milliseconds.
+///
+/// This is the time until the response was ready to send. For streamed
+/// responses, like exports, it doesn't include the time spent sending the body.
+async fn response_time(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let mut response = next.run(request).await;
+
+    let elapsed = format!("{:.3}", start.elapsed().as_secs_f64() * 1000.0);
+    if let Ok(value) = HeaderValue::from_str(&elapsed) {
+        response.headers_mut().insert(RESPONSE_TIME_MS, value);
+    }
+
+    response
 }
 
 /// A simple route just to check if we're up
@@ -59,110 +367,3873 @@ async fn up() -> &'static str {
     "200 OK"
 }
 
-/// The route to upload the CSV file
+#[derive(Serialize)]
+struct BuildInfo {
+    version: &'static str,
+    git_sha: &'static str,
+}
+
+/// This route reports which build of the server is running,
+/// so that we can check a deployment went out
+async fn version() -> Json<BuildInfo> {
+    Json(BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("GIT_SHA"),
+    })
+}
+
+/// The query parameters accepted by the health check
+#[derive(Deserialize)]
+struct HealthParams {
+    /// Whether to measure memory use, which means walking the whole db
+    #[serde(default)]
+    include_memory: bool,
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    properties: usize,
+    /// When the data last changed, in the configured timezone.
+    /// This is `null` until the first change after the server starts.
+    last_modified: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<MemoryUsage>,
+}
+
+/// This route reports on the state of the server, for operators
+#[debug_handler(state = AppContext)]
+async fn health_check(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<HealthParams>,
+) -> Json<Health> {
+    let state = state.read().await;
+    let db = state.db.read().await;
+    let last_modified = *state.last_modified.lock().await;
+
+    Json(Health {
+        status: "ok",
+        properties: db.len(),
+        last_modified: last_modified.map(|time| config.timezone.format(time)),
+        memory: params
+            .include_memory
+            .then(|| health::memory_usage(db.values())),
+    })
+}
+
+/// This route reports how many properties have data quality problems
 #[debug_handler]
+async fn integrity_check(State(state): State<SharedState>) -> Json<IntegrityReport> {
+    let state = state.read().await;
+    let db = state.db.read().await;
+
+    Json(integrity::check(db.values()))
+}
+
+/// The query parameters accepted when uploading a CSV file
+#[derive(Deserialize)]
+struct UploadParams {
+    #[serde(default)]
+    keep_partial: bool,
+    /// Reject the whole file if any row is invalid
+    #[serde(default)]
+    strict: bool,
+    /// Which worksheet to import, when uploading an Excel workbook
+    sheet: Option<String>,
+    /// Which row of the file is the header, counting from 0
+    #[serde(default)]
+    header_row: usize,
+    /// What to respond with if the upload leaves us with no properties
+    #[serde(default)]
+    if_empty: EmptyResponse,
+    /// Skip rows that fail the sanity checks, like negative prices
+    #[serde(default)]
+    validate: bool,
+    /// Respond with just the skipped rows, rather than every imported property
+    #[serde(default)]
+    only_errors: bool,
+    /// The character CSV fields are quoted with, `"` by default
+    quote: Option<String>,
+    /// The character that escapes a quote inside a quoted field.
+    /// Defaults to the quote, which means quotes are escaped by doubling them.
+    escape: Option<String>,
+}
+
+impl UploadParams {
+    /// The CSV dialect the client asked for, checking that the quote
+    /// and escape are each a single character
+    fn dialect(&self) -> Result<Dialect, ApiError> {
+        let invalid =
+            |error: InvalidDialect| ApiError::bad_request("invalid_dialect", error.to_string());
+
+        let mut dialect = Dialect::default();
+
+        if let Some(quote) = &self.quote {
+            dialect.quote = csv::single_char("quote", quote).map_err(invalid)?;
+            dialect.escape = dialect.quote;
+        }
+
+        if let Some(escape) = &self.escape {
+            dialect.escape = csv::single_char("escape", escape).map_err(invalid)?;
+        }
+
+        Ok(dialect)
+    }
+
+    /// The import options for an upload with these parameters
+    fn import_options(&self, config: &Config) -> Result<ImportOptions, ApiError> {
+        Ok(ImportOptions {
+            keep_partial: self.keep_partial,
+            sheet: self.sheet.clone(),
+            strict: self.strict,
+            header_row: self.header_row,
+            sanity_check: self.validate,
+            dialect: self.dialect()?,
+            ..ImportOptions::from_config(config)
+        })
+    }
+}
+
+/// The header clients can use to send the checksum of the file they're uploading
+const CONTENT_SHA256: HeaderName = HeaderName::from_static("x-content-sha256");
+
+/// The header we use to report how many invalid rows were skipped in an upload
+const SKIPPED_ROWS: HeaderName = HeaderName::from_static("x-skipped-rows");
+
+/// The route to upload the CSV file
+///
+/// Excel workbooks are accepted too, using the `sheet` parameter to pick
+/// which worksheet to import.
+///
+/// Along with the `file` field, or whatever `FIELD_NAME` is set to, an optional `mapping` field can hold a JSON
+/// object mapping each property field to a column name or index in the file.
+///
+/// Invalid rows are skipped, and counted in the `X-Skipped-Rows` header.
+/// With `strict=true`, any invalid row fails the whole upload instead,
+/// with `422 Unprocessable Entity` and a list of the invalid rows.
+///
+/// If the `X-Content-SHA256` header matches the checksum of the last
+/// successful upload, the file is unchanged, so we skip re-importing it
+/// and respond with `304 Not Modified`.
+///
+/// If the `Idempotency-Key` header matches a recent successful upload,
+/// this is a retry, so we send back the original response untouched.
+#[debug_handler(state = AppContext)]
 async fn upload_csv(
     State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<UploadParams>,
+    headers: HeaderMap,
     mut multipart: Multipart,
-) -> Json<Vec<Property>> {
-    let db = &mut state.write().await.db;
+) -> Result<Response, ApiError> {
+    let idempotency_key = idempotency_key(&headers);
+
+    if let Some(replayed) = replay(&state, idempotency_key.as_deref()).await {
+        return Ok(replayed);
+    }
+
+    let client_checksum = headers
+        .get(CONTENT_SHA256)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_ascii_lowercase());
+
+    // We check this before reading the body, so that we don't
+    // waste any time on a file we've already imported
+    if client_checksum.is_some()
+        && client_checksum == *state.read().await.last_upload_checksum.lock().await
+    {
+        return Ok(StatusCode::NOT_MODIFIED.into_response());
+    }
+
+    let mut options = params.import_options(&config)?;
+
+    let files = read_upload_form(&mut multipart, &config, &mut options).await?;
+
+    import_files(
+        &state,
+        &config,
+        &files,
+        &options,
+        params.if_empty,
+        params.only_errors,
+        idempotency_key,
+    )
+    .await
+    .map(IntoResponse::into_response)
+}
+
+/// Parses the uploaded files like [`parse_files`], on a thread that's allowed
+/// to block. Large files take a while to parse, and doing that on one of the
+/// runtime's threads would hold up every other request waiting on it.
+async fn parse_files_blocking(
+    files: &[Bytes],
+    options: &ImportOptions,
+) -> Result<Vec<Import>, ApiError> {
+    let files = files.to_vec();
+    let options = options.clone();
+
+    tokio::task::spawn_blocking(move || parse_files(&files, &options))
+        .await
+        .map_err(|error| {
+            ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "parse_failed",
+                format!("failed to parse the uploaded files: {error}"),
+            )
+        })?
+}
+
+/// Parses the uploaded files, which can be CSV text, Excel workbooks,
+/// or ZIP archives of CSV files. Every CSV file in an archive is imported.
+///
+/// The ids of each file carry on from the ones before it, so that
+/// properties from different files don't overwrite each other.
+/// Files with an id column can still use the same id as an earlier file,
+/// which is handled like a repeated id within one file: the last one wins,
+/// and the earlier row is skipped, or fails a strict import.
+fn parse_files(files: &[Bytes], options: &ImportOptions) -> Result<Vec<Import>, ApiError> {
+    let mut options = options.clone();
+    let mut imports: Vec<Import> = vec![];
+
+    // The file each id was last seen in, and where its property is in that file
+    let mut seen_ids: HashMap<usize, (usize, usize)> = HashMap::new();
+    let mut replaced: Vec<Vec<usize>> = vec![];
+
+    for data in files {
+        let entries = if archive::is_zip(data) && !xlsx::is_workbook(data) {
+            archive::csv_entries(data, options.max_archive_bytes.unwrap_or(usize::MAX))?
+                .into_iter()
+                .map(Cow::Owned)
+                .collect()
+        } else {
+            vec![Cow::Borrowed(data.as_ref())]
+        };
+
+        for entry in entries {
+            let parsed = parse_file(&entry, &options)?;
+
+            let last_id = parsed.properties.iter().map(|property| property.id).max();
+            options.id_offset = options.id_offset.max(last_id.unwrap_or_default());
+            options.previous_rows += parsed.row_count;
+
+            let file = imports.len();
+            for (position, property) in parsed.properties.iter().enumerate() {
+                let Some((earlier_file, earlier_position)) =
+                    seen_ids.insert(property.id, (file, position))
+                else {
+                    continue;
+                };
+
+                let earlier = &mut imports[earlier_file];
+                earlier.skipped.push(SkippedRow {
+                    row: earlier.rows[earlier_position],
+                    reason: format!(
+                        "id {} is used again in row {} of file {}",
+                        property.id,
+                        parsed.rows[position],
+                        file + 1
+                    ),
+                });
+                replaced[earlier_file].push(earlier_position);
+            }
+
+            imports.push(parsed);
+            replaced.push(vec![]);
+        }
+    }
+
+    for (import, replaced) in imports.iter_mut().zip(replaced) {
+        import.remove_replaced(replaced);
+    }
+
+    if options.strict && imports.iter().any(|import| !import.skipped.is_empty()) {
+        let skipped = imports.into_iter().flat_map(|import| import.skipped);
+        return Err(ImportError::InvalidRows(skipped.collect()).into());
+    }
+
+    Ok(imports)
+}
+
+/// Parses one file, which can be either CSV text or an Excel workbook
+fn parse_file(data: &[u8], options: &ImportOptions) -> Result<Import, ApiError> {
+    if xlsx::is_workbook(data) {
+        return Ok(xlsx::parse_xlsx(data, options)?);
+    }
+
+    let text = str::from_utf8(data).map_err(|_| {
+        ApiError::bad_request("invalid_encoding", "the file must be encoded as UTF-8")
+    })?;
+
+    Ok(import::parse_csv(text, options)?)
+}
+
+/// Imports the uploaded files, replacing all of the existing data,
+/// and builds the response with the new data
+///
+/// This is shared by every way of uploading files, so that they all
+/// parse the files and report the results in the same way.
+async fn import_files(
+    state: &SharedState,
+    config: &Config,
+    files: &[Bytes],
+    options: &ImportOptions,
+    if_empty: EmptyResponse,
+    only_errors: bool,
+    idempotency_key: Option<String>,
+) -> Result<CachedResponse, ApiError> {
+    let mut properties = vec![];
+    let mut skipped = vec![];
+    let mut hasher = Sha256::new();
+
+    for data in files {
+        hasher.update(data);
+    }
+
+    for parsed in parse_files_blocking(files, options).await? {
+        skipped.extend(parsed.skipped);
+        properties.push(parsed.properties);
+    }
+
+    let checksum = format!("{:x}", hasher.finalize());
+
+    // We compress the files before taking the lock, since it can take a while
+    let original_files = files
+        .iter()
+        .map(|data| OriginalFile::compress(data))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| {
+            ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "storage_failed",
+                format!("failed to store the uploaded file: {error}"),
+            )
+        })?;
+
+    let mut state = write_lock(state, config).await?;
+    *state.last_upload_checksum.get_mut() = Some(checksum.clone());
+    state.original_files = original_files;
+    let now = Utc::now();
+    *state.last_modified.get_mut() = Some(now);
+    state.list_cache.get_mut().clear();
 
     // The spec isn't completely clear about how long to preserve the property
     // data, so for now we wipe it out whenever a user uploads a new CSV file.
+    // We hold on to the old data so that we can show the differences.
     // TODO: We should be backing this data up somehow so that we can restore it
     // in the event that this update fails.
     // If we use a proper database, we can wrap these changes in a transaction
     // and simply drop it on error, or commit on success.
-    db.clear();
+    state.previous_db = state.db.take();
+    let mut db = Db::new();
 
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
+    properties.into_iter().flatten().for_each(|property| {
+        // Add each property into the db. The upload replaces all of them,
+        // so they all count as changed.
+        let property = Property {
+            updated_at: Some(now),
+            ..property
+        };
+        db.insert(property.id, property);
+    });
 
-        if name != "file" {
-            continue;
-        }
+    // We serialize the body ourselves, so that we can keep a copy of it
+    // for the idempotency cache. This can't fail, since properties are
+    // always valid JSON.
+    let view_options = ViewOptions::from_config(config);
+    // Clients that only want the errors get them even if nothing was imported
+    let (status, body) = match (db.is_empty(), if_empty) {
+        _ if only_errors => (
+            StatusCode::OK,
+            serde_json::to_vec(&SkippedReport { skipped: &skipped }).unwrap_or_default(),
+        ),
+        (true, EmptyResponse::NoContent) => (StatusCode::NO_CONTENT, vec![]),
+        _ => (
+            StatusCode::OK,
+            serde_json::to_vec(
+                &db.values()
+                    .map(|property| property.view(&view_options))
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap_or_default(),
+        ),
+    };
 
-        let data = field.bytes().await.unwrap();
-        let rows: Vec<&str> = str::from_utf8(&data).unwrap().lines().collect();
+    state.db = db.into();
+    save_snapshot(config, &state).await;
 
-        // We slice off the first row, because that's the header
-        rows[1..]
-            .iter()
-            // Split each row into columns
-            .map(|row| row.split(','))
-            .enumerate()
-            // Map those columns into properties
-            .flat_map(|(i, mut columns)| {
-                Some(Property {
-                    // We increment the index to start from 1.
-                    // This way, we can match the rows in the CSV file
-                    id: i + 1,
-                    // str::split returns an iterator, so we can pull each value
-                    // out one-by-one here and convert them all to owned strings.
-                    // If there should be an error, we return None.
-                    // None values get filtered out by the `flat_map` call.
-                    // NOTE: It's important that we do this in the order that matches the CSV:
-                    // prefecture, city, town, chome, banchi, go, building, price, nearest_station, property_type, land_area
-                    prefecture: columns.next()?.to_owned(),
-                    city: columns.next()?.to_owned(),
-                    town: columns.next()?.to_owned(),
-                    chome: columns.next()?.to_owned(),
-                    banchi: columns.next()?.to_owned(),
-                    go: columns.next()?.to_owned(),
-                    building: columns.next()?.to_owned(),
-                    price: columns.next()?.to_owned(),
-                    nearest_station: columns.next()?.to_owned(),
-                    property_type: columns.next()?.to_owned(),
-                    land_area: columns.next()?.to_owned(),
-                })
-            })
-            .for_each(|property| {
-                // Add each property into the db
-                db.insert(property.id, property);
-            });
+    let response = CachedResponse {
+        status,
+        headers: vec![
+            (CONTENT_SHA256, HeaderValue::from_str(&checksum).unwrap()),
+            (SKIPPED_ROWS, HeaderValue::from(skipped.len())),
+        ],
+        body: body.into(),
+    };
+
+    if let Some(key) = idempotency_key {
+        state.idempotency_cache.insert(key, response.clone());
     }
 
-    // TODO: report if there were any failed rows
+    Ok(response)
+}
 
-    match db.len() {
-        0 => Json(vec![]),
-        // Serde can stringify the whole list for us, but we need to
-        // collect the values into a vector first
-        _ => Json(db.values().cloned().collect()),
+/// The response to an upload with `only_errors=true`
+#[derive(Serialize)]
+struct SkippedReport<'a> {
+    skipped: &'a [SkippedRow],
+}
+
+/// The request body for importing a file from a URL
+#[derive(Deserialize)]
+struct UploadFromUrlRequest {
+    url: String,
+    /// Maps each property field to a column, like the upload form's `mapping`
+    mapping: Option<ColumnMapping>,
+}
+
+/// The route to import a CSV file or Excel workbook from a URL,
+/// for files that are already hosted somewhere
+///
+/// The file is downloaded and imported in the same way as an upload,
+/// with the same query parameters. Only http and https URLs are allowed,
+/// and the download is limited by `URL_UPLOAD_MAX_BYTES` and the upload timeout.
+#[debug_handler(state = AppContext)]
+async fn upload_from_url(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<UploadParams>,
+    headers: HeaderMap,
+    Json(request): Json<UploadFromUrlRequest>,
+) -> Result<Response, ApiError> {
+    let idempotency_key = idempotency_key(&headers);
+
+    if let Some(replayed) = replay(&state, idempotency_key.as_deref()).await {
+        return Ok(replayed);
     }
+
+    let options = ImportOptions {
+        mapping: request.mapping,
+        ..params.import_options(&config)?
+    };
+
+    let file = fetch::fetch(
+        &request.url,
+        config.url_upload_max_bytes,
+        config.upload_timeout,
+    )
+    .await?;
+
+    import_files(
+        &state,
+        &config,
+        &[file],
+        &options,
+        params.if_empty,
+        params.only_errors,
+        idempotency_key,
+    )
+    .await
+    .map(IntoResponse::into_response)
+}
+
+fn idempotency_key(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(idempotency::IDEMPOTENCY_KEY)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Finds the response to an earlier upload with the same idempotency key
+async fn replay(state: &SharedState, idempotency_key: Option<&str>) -> Option<Response> {
+    let state = state.read().await;
+    let cached = state.idempotency_cache.get(idempotency_key?)?;
+    let replayed = [(idempotency::IDEMPOTENT_REPLAYED, "true")];
+
+    Some((replayed, cached.clone()).into_response())
+}
+
+/// The query parameters accepted when downloading an uploaded file
+#[derive(Deserialize)]
+struct OriginalParams {
+    /// Which file to download, for uploads with more than one
+    #[serde(default)]
+    index: usize,
 }
 
-/// This route returns all the property data in JSON format
+/// This route downloads a file from the last upload, exactly as it was uploaded
 #[debug_handler]
-async fn list_properties(State(state): State<SharedState>) -> Json<Vec<Property>> {
-    let db = &state.read().await.db;
+async fn download_original(
+    State(state): State<SharedState>,
+    Query(params): Query<OriginalParams>,
+) -> Result<Response, ApiError> {
+    let state = state.read().await;
+
+    let file = state
+        .original_files
+        .get(params.index)
+        .ok_or_else(|| ApiError::not_found("No uploaded file found"))?;
+
+    let data = file.decompress().map_err(|error| {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "storage_failed",
+            format!("failed to read the uploaded file: {error}"),
+        )
+    })?;
+
+    let filename = file.filename(params.index);
+    let headers = [
+        (header::CONTENT_TYPE, file.content_type().to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            export::content_disposition(&filename, &filename),
+        ),
+    ];
+
+    Ok((headers, data).into_response())
+}
+
+/// This route checks a file the same way an upload would, without storing
+/// anything, and reports every problem it finds. Data teams can use it
+/// to lint their files before uploading them for real.
+///
+/// Rows that an upload would skip are reported as errors, including the ones
+/// that fail the sanity checks, which are always run here. Rows that would be
+/// imported, but have something odd about them, are reported as warnings.
+#[debug_handler(state = AppContext)]
+async fn validate_csv(
+    State(config): State<Arc<Config>>,
+    Query(params): Query<UploadParams>,
+    mut multipart: Multipart,
+) -> Result<Json<ValidationReport>, ApiError> {
+    // Every problem is reported, rather than failing on the first one
+    let mut options = ImportOptions {
+        strict: false,
+        sanity_check: true,
+        ..params.import_options(&config)?
+    };
+
+    let files = read_upload_form(&mut multipart, &config, &mut options).await?;
+
+    let imports = parse_files_blocking(&files, &options).await?;
+
+    Ok(Json(ValidationReport::new(&imports)))
+}
+
+/// How many rows a preview parses, unless the client asks for more or fewer
+const PREVIEW_ROWS: usize = 10;
+
+/// The query parameter for how many rows to preview
+#[derive(Deserialize)]
+struct PreviewParams {
+    rows: Option<usize>,
+}
+
+/// The route to preview how a file would be imported, without storing anything.
+///
+/// Only the first `rows` rows of the file are parsed, 10 by default, so
+/// clients can quickly check the column mapping of a large file.
+/// The upload parameters and form fields are the same as for an upload.
+#[debug_handler(state = AppContext)]
+async fn preview_csv(
+    State(config): State<Arc<Config>>,
+    Query(params): Query<UploadParams>,
+    Query(preview): Query<PreviewParams>,
+    Query(view_options): Query<ViewOptions>,
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    let view_options = view_options.with_config(&config);
+    let rows = preview.rows.unwrap_or(PREVIEW_ROWS);
+
+    // Only the first rows are parsed, so a file that's too long to import
+    // can still be previewed
+    let mut options = ImportOptions {
+        strict: false,
+        max_rows: None,
+        row_limit: Some(rows),
+        ..params.import_options(&config)?
+    };
+
+    let files = read_upload_form(&mut multipart, &config, &mut options).await?;
+
+    let imports = parse_files_blocking(&files, &options).await?;
+
+    // Each file is limited on its own, so several files together
+    // could still give us more rows than were asked for
+    let views: Vec<_> = imports
+        .iter()
+        .flat_map(|import| &import.properties)
+        .take(rows)
+        .map(|property| property.view(&view_options))
+        .collect();
+
+    Ok(Json(views).into_response())
+}
+
+/// Reads the fields of the upload form, returning the contents of any files.
+/// Files are read from the fields with the configured name, usually `file`.
+///
+/// A client that sends the body very slowly shouldn't be able to tie up
+/// the server forever, so this gives up after the upload timeout.
+/// We don't hold the lock while we wait for the body,
+/// so other requests can still get through in the meantime.
+async fn read_upload_form(
+    multipart: &mut Multipart,
+    config: &Config,
+    options: &mut ImportOptions,
+) -> Result<Vec<Bytes>, ApiError> {
+    tokio::time::timeout(
+        config.upload_timeout,
+        read_form_fields(multipart, &config.upload_field_name, options),
+    )
+    .await
+    .map_err(|_| {
+        ApiError::new(
+            StatusCode::REQUEST_TIMEOUT,
+            "upload_timeout",
+            "the upload took too long to receive",
+        )
+    })?
+}
+
+/// Reads the fields of the upload form, without a time limit.
+///
+/// The mapping might come after the file in the form data,
+/// so we need to collect all the fields before we parse anything.
+async fn read_form_fields(
+    multipart: &mut Multipart,
+    file_field: &str,
+    options: &mut ImportOptions,
+) -> Result<Vec<Bytes>, ApiError> {
+    let mut files = vec![];
+
+    while let Some(field) = multipart.next_field().await.map_err(invalid_multipart)? {
+        let name = field.name().unwrap_or_default().to_string();
+
+        match name.as_str() {
+            name if name == file_field => {
+                files.push(field.bytes().await.map_err(invalid_multipart)?)
+            }
+            "mapping" => {
+                let data = field.bytes().await.map_err(invalid_multipart)?;
+                let parsed = serde_json::from_slice(&data).map_err(|error| {
+                    ApiError::bad_request("invalid_mapping", format!("invalid mapping: {error}"))
+                })?;
+                options.mapping = Some(parsed);
+            }
+            _ => continue,
+        }
+    }
+
+    Ok(files)
+}
+
+fn invalid_multipart(error: axum::extract::multipart::MultipartError) -> ApiError {
+    ApiError::bad_request("invalid_multipart", error.body_text())
+}
+
+/// The header that holds the cursor for the next page, for CSV responses
+/// that have nowhere else to put it
+const NEXT_CURSOR: HeaderName = HeaderName::from_static("x-next-cursor");
+
+/// This route returns all the property data in JSON format,
+/// or as CSV if the client sends `Accept: text/csv`
+///
+/// If the client passes a `limit`, `offset`, or `after` cursor, only that page
+/// of properties is returned, wrapped in an object along with the cursor for
+/// the next page and any warnings.
+///
+/// Responses are cached for `LIST_CACHE_TTL_MS`, or until the data changes,
+/// so that the same query asked again doesn't need to be serialized again.
+#[debug_handler(state = AppContext)]
+#[allow(clippy::too_many_arguments)]
+async fn list_properties(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(options): Query<ViewOptions>,
+    Query(page): Query<PageParams>,
+    Query(filter): Query<PropertyFilter>,
+    Query(export_options): Query<ExportOptions>,
+    Query(empty): Query<EmptyParams>,
+    Query(sort_params): Query<SortParams>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+) -> Response {
+    let filter = filter.with_config(&config);
+    let options = options.with_config(&config);
+    let sort_keys = match sort_params.keys() {
+        Ok(keys) => keys,
+        Err(error) => {
+            return ApiError::bad_request("invalid_sort", error.to_string()).into_response()
+        }
+    };
+
+    let state = state.read().await;
+    let ttl = config.list_cache_ttl;
+    let key = response_cache::key(query.as_deref(), &headers);
+
+    if !ttl.is_zero() {
+        if let Some(cached) = state.list_cache.lock().await.get(&key, ttl) {
+            return cached.clone().into_response_with(true);
+        }
+    }
+
+    let db = state.db.read().await;
+    let list = ListRequest {
+        options,
+        page,
+        filter,
+        export_options,
+        empty,
+        sort_keys,
+    };
+    let response = list_response(&db, &config, list, &headers);
+
+    if ttl.is_zero() {
+        return response;
+    }
+
+    // We store the response while we still have the db locked,
+    // so that an edit can't clear the cache before this goes into it
+    match response_cache::CachedResponse::read(response).await {
+        Ok(cached) => {
+            state
+                .list_cache
+                .lock()
+                .await
+                .insert(key, cached.clone(), ttl);
+            cached.into_response_with(false)
+        }
+        Err(error) => ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            format!("failed to read the response: {error}"),
+        )
+        .into_response(),
+    }
+}
+
+/// What the client asked the list for
+struct ListRequest {
+    options: ViewOptions,
+    page: PageParams,
+    filter: PropertyFilter,
+    export_options: ExportOptions,
+    empty: EmptyParams,
+    sort_keys: Vec<SortKey>,
+}
+
+/// Builds the list's response out of the properties in the db
+fn list_response(db: &DbView, config: &Config, list: ListRequest, headers: &HeaderMap) -> Response {
+    let ListRequest {
+        options,
+        page,
+        filter,
+        export_options,
+        empty,
+        sort_keys,
+    } = list;
 
-    match db.len() {
-        0 => Json(vec![]),
+    // The db keeps the properties in id order,
+    // so pages are consistent between requests
+    let matches = filter.matcher();
+    let mut properties: Vec<&Property> = db.values().filter(|property| matches(property)).collect();
+    sort::sort(&mut properties, &sort_keys);
+
+    // The same URL can respond in either format, so caches need to know
+    // that the response depends on the Accept header
+    let vary = [(header::VARY, "accept")];
+
+    if properties.is_empty() && empty.if_empty == EmptyResponse::NoContent {
+        return (StatusCode::NO_CONTENT, vary).into_response();
+    }
+
+    if response::wants_csv(headers) {
+        let mut next_cursor = None;
+
+        if page.is_paginated() {
+            let page = paginate(properties, &page, config);
+            next_cursor = page.next_cursor;
+            properties = page.data;
+        }
+
+        let csv = match export::write_csv(properties, &export_options) {
+            Ok(csv) => csv,
+            Err(error) => return invalid_columns(error).into_response(),
+        };
+        let cursor_header = next_cursor.map(|cursor| [(NEXT_CURSOR, cursor)]);
+
+        return (
+            vary,
+            [(header::CONTENT_TYPE, CSV_CONTENT_TYPE)],
+            cursor_header,
+            csv,
+        )
+            .into_response();
+    }
+
+    let json = if page.is_paginated() {
+        let page = paginate(properties, &page, config);
+        json_response(headers, page.map(|property| property.view(&options)))
+    } else {
         // Serde can stringify the whole list for us, but we need to
         // collect the values into a vector first
-        _ => Json(db.values().cloned().collect()),
-    }
+        let views: Vec<_> = properties
+            .into_iter()
+            .map(|property| property.view(&options))
+            .collect();
+
+        json_response(headers, views)
+    };
+
+    (vary, json).into_response()
 }
 
-#[debug_handler]
-async fn get_property(
-    Path(id): Path<usize>,
+/// This route shows what changed between the data from before the last upload
+/// and the data we have now
+#[debug_handler(state = AppContext)]
+async fn diff_last_upload(
     State(state): State<SharedState>,
-) -> impl IntoResponse {
-    let db = &state.read().await.db;
+    State(config): State<Arc<Config>>,
+) -> Response {
+    let state = state.read().await;
+    let db = state.db.read().await;
+    let diff = diff::diff(state.previous_db.values(), db.values());
+
+    let options = ViewOptions::from_config(&config);
+    Json(diff.map(|property| property.view(&options))).into_response()
+}
+
+/// The query parameters for naming an exported file
+#[derive(Deserialize)]
+struct ExportFilenameParams {
+    /// A template for the filename, like `properties_{prefecture}_{date}.csv`
+    filename: Option<String>,
+}
+
+/// The smallest piece of a streamed export that we send at once, in bytes
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How many properties a stream reads from the db at a time
+const STREAM_BATCH: usize = 1000;
+
+/// Where a streamed export reads its properties from, as set by [`StreamMode`]
+#[derive(Clone)]
+enum StreamSource {
+    /// The db itself, locked for each batch
+    Live(SharedState),
+    /// The db as it was when the export started
+    Snapshot(DbSnapshot),
+}
 
-    match db.get(&id) {
-        Some(value) => Json(value.clone()).into_response(),
-        None => (StatusCode::NOT_FOUND, "Property not found").into_response(),
+impl StreamSource {
+    async fn new(state: SharedState, mode: StreamMode) -> Self {
+        match mode {
+            StreamMode::Live => StreamSource::Live(state),
+            StreamMode::Snapshot => StreamSource::Snapshot(state.read().await.db.snapshot().await),
+        }
     }
 }
 
-#[debug_handler]
-async fn not_found() -> impl IntoResponse {
-    (
-        StatusCode::NOT_FOUND,
-        "The page you're looking for doesn't exist",
-    )
-        .into_response()
+/// Reads the properties for a stream a batch at a time, in id order.
+/// A live stream only holds the lock while each batch is read, rather than
+/// for the whole time the client takes to download the export, and
+/// a snapshot doesn't need it at all.
+fn stream_batches(
+    source: StreamSource,
+    filter: PropertyFilter,
+) -> impl Stream<Item = Vec<Property>> {
+    fn batch(values: Values, matches: impl Fn(&Property) -> bool) -> Vec<Property> {
+        values
+            .filter(|property| matches(property))
+            .take(STREAM_BATCH)
+            .cloned()
+            .collect()
+    }
+
+    stream::unfold(Some(0), move |from| {
+        let source = source.clone();
+        let filter = filter.clone();
+
+        async move {
+            let from = from?;
+            let batch = match &source {
+                StreamSource::Live(state) => {
+                    let state = state.read().await;
+                    let db = state.db.read().await;
+                    batch(db.values_from(from), filter.matcher())
+                }
+                StreamSource::Snapshot(snapshot) => {
+                    batch(snapshot.values_from(from), filter.matcher())
+                }
+            };
+
+            // A batch that isn't full means we've reached the end,
+            // as does the highest id there can be
+            let last = batch.last()?.id;
+            let next = (batch.len() == STREAM_BATCH)
+                .then(|| last.checked_add(1))
+                .flatten();
+
+            Some((batch, next))
+        }
+    })
+}
+
+/// This route streams all the property data as a sequence of MessagePack maps,
+/// for clients that sync the whole dataset and want something more compact than JSON.
+/// The same filters as the list can be used to stream just some of it.
+///
+/// Each chunk is only encoded once the client is ready for it,
+/// so a slow client doesn't build up the export in memory.
+#[debug_handler(state = AppContext)]
+async fn stream_msgpack(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(options): Query<ViewOptions>,
+    Query(filter): Query<PropertyFilter>,
+) -> Response {
+    let filter = filter.with_config(&config);
+    let options = options.with_config(&config);
+    let content_type = [(header::CONTENT_TYPE, export::MSGPACK_CONTENT_TYPE)];
+
+    let source = StreamSource::new(state, config.stream_mode).await;
+    let chunks = stream_batches(source, filter).flat_map(move |batch| {
+        stream::iter(export::msgpack_chunks(
+            batch,
+            options.clone(),
+            EXPORT_CHUNK_SIZE,
+        ))
+    });
+    let body = Body::from_stream(chunks.map(Ok::<_, Infallible>));
+
+    (content_type, body).into_response()
+}
+
+/// This route downloads all the property data as a CSV file.
+/// The same filters as the list can be used to export just some of it.
+///
+/// The file is streamed a chunk at a time, so that large exports don't
+/// need to be built in memory first. It also supports `Range` requests,
+/// so that large downloads can be resumed. A range needs the length of the
+/// whole file, so those are built in memory instead.
+#[debug_handler(state = AppContext)]
+async fn export_csv(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(options): Query<ExportOptions>,
+    Query(filter): Query<PropertyFilter>,
+    Query(params): Query<ExportFilenameParams>,
+    headers: HeaderMap,
+) -> Response {
+    let filter = filter.with_config(&config);
+
+    // When exporting one region, the filename says which one it is
+    let filename_template = params
+        .filename
+        .as_deref()
+        .unwrap_or(match filter.prefecture {
+            Some(_) => "properties_{prefecture}.csv",
+            None => "properties.csv",
+        });
+
+    // Older clients only understand ASCII filenames,
+    // so the fallback uses the prefecture's romaji instead
+    let romaji = filter
+        .prefecture
+        .as_deref()
+        .map(|name| prefecture::find_by_name(name).map_or("", |prefecture| prefecture.romaji));
+
+    let date = config.timezone.date(Utc::now());
+    let disposition = export::content_disposition(
+        &export::render_filename(filename_template, filter.prefecture.as_deref(), &date),
+        &export::render_filename(filename_template, romaji, &date),
+    );
+
+    let csv_headers = [
+        (header::CONTENT_TYPE, CSV_CONTENT_TYPE.to_string()),
+        (header::CONTENT_DISPOSITION, disposition),
+        (header::ACCEPT_RANGES, "bytes".to_string()),
+    ];
+
+    if !headers.contains_key(header::RANGE) {
+        let writer = match CsvWriter::new(&options) {
+            Ok(writer) => writer,
+            Err(error) => return invalid_columns(error).into_response(),
+        };
+
+        let header = writer.header();
+        let end = writer.end();
+
+        // Each batch is already a good size to send at once
+        let source = StreamSource::new(state, config.stream_mode).await;
+        let rows = stream_batches(source, filter).map(move |batch| {
+            batch
+                .iter()
+                .map(|property| writer.row(property))
+                .collect::<String>()
+        });
+        let chunks = stream::once(async { header })
+            .chain(rows)
+            .chain(stream::iter(end));
+
+        let body = Body::from_stream(chunks.map(Ok::<_, Infallible>));
+
+        return (csv_headers, body).into_response();
+    }
+
+    let csv = {
+        let state = state.read().await;
+        let db = state.db.read().await;
+
+        let matches = filter.matcher();
+        let properties = db.values().filter(|property| matches(property));
+
+        match export::write_csv(properties, &options) {
+            Ok(csv) => csv,
+            Err(error) => return invalid_columns(error).into_response(),
+        }
+    };
+
+    let range_header = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+
+    match range::parse_range(range_header, csv.len()) {
+        ByteRange::Full => (csv_headers, csv).into_response(),
+        ByteRange::Partial(range) => {
+            let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, csv.len());
+
+            (
+                StatusCode::PARTIAL_CONTENT,
+                csv_headers,
+                [(header::CONTENT_RANGE, content_range)],
+                Bytes::from(csv).slice(range),
+            )
+                .into_response()
+        }
+        ByteRange::Unsatisfiable => (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{}", csv.len()))],
+        )
+            .into_response(),
+    }
+}
+
+/// This route downloads a hand-picked set of properties as a CSV file.
+///
+/// The body is a JSON array of ids, and the rows come out in the same order,
+/// so that the user gets them back the way they picked them. Ids that don't
+/// exist, or that were deleted, are quietly left out, as are repeats.
+///
+/// Like the other exports, the file is streamed from a snapshot of the db,
+/// so it's consistent without holding the lock while it's sent.
+#[debug_handler]
+async fn export_selected(
+    State(state): State<SharedState>,
+    Query(options): Query<ExportOptions>,
+    Json(ids): Json<Vec<usize>>,
+) -> Result<Response, ApiError> {
+    let writer = CsvWriter::new(&options).map_err(invalid_columns)?;
+    let snapshot = state.read().await.db.snapshot().await;
+
+    let mut seen = HashSet::new();
+    let ids: Vec<usize> = ids.into_iter().filter(|id| seen.insert(*id)).collect();
+    let batches: Vec<Vec<usize>> = ids.chunks(STREAM_BATCH).map(<[usize]>::to_vec).collect();
+
+    let header = writer.header();
+    let end = writer.end();
+    let rows = stream::iter(batches).map(move |batch| {
+        batch
+            .iter()
+            .filter_map(|id| snapshot.get(*id))
+            .filter(|property| !property.deleted)
+            .map(|property| writer.row(property))
+            .collect::<String>()
+    });
+    let chunks = stream::once(async { header })
+        .chain(rows)
+        .chain(stream::iter(end));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, CSV_CONTENT_TYPE.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                export::content_disposition("properties.csv", "properties.csv"),
+            ),
+        ],
+        Body::from_stream(chunks.map(Ok::<_, Infallible>)),
+    )
+        .into_response())
+}
+
+/// The query parameter for laying out a fixed-width export
+#[derive(Deserialize)]
+struct FixedWidthParams {
+    /// The columns and their widths, like `id:8,prefecture:4`
+    widths: Option<String>,
+    #[serde(default)]
+    mask_address: bool,
+}
+
+/// This route downloads the property data as fixed-width text, for older
+/// systems that read each field from the same position on every line.
+/// The same filters as the list can be used.
+#[debug_handler(state = AppContext)]
+async fn export_fixed_width(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<FixedWidthParams>,
+    Query(filter): Query<PropertyFilter>,
+) -> Result<Response, ApiError> {
+    let filter = filter.with_config(&config);
+    let spec: FixedWidthSpec = match &params.widths {
+        Some(widths) => widths.parse().map_err(|error: export::InvalidWidthSpec| {
+            ApiError::bad_request("invalid_widths", error.to_string())
+        })?,
+        None => FixedWidthSpec::default(),
+    };
+
+    let text = {
+        let state = state.read().await;
+        let db = state.db.read().await;
+
+        let matches = filter.matcher();
+        let properties = db
+            .values()
+            .filter(|property| matches(property))
+            .map(|property| property.masked_if(params.mask_address));
+
+        export::write_fixed_width(properties, &spec)
+    };
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/plain; charset=utf-8".to_string(),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                export::content_disposition("properties.txt", "properties.txt"),
+            ),
+        ],
+        text,
+    )
+        .into_response())
+}
+
+/// This route downloads all the property data as a JSON file,
+/// which is handy for keeping a backup.
+///
+/// The data is the same as the list's, with the same filters and view options,
+/// but it's sent as an attachment, so that browsers save it instead of showing it.
+#[debug_handler(state = AppContext)]
+async fn export_json(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(options): Query<ViewOptions>,
+    Query(filter): Query<PropertyFilter>,
+) -> Response {
+    let filter = filter.with_config(&config);
+    let options = options.with_config(&config);
+    let body = {
+        let state = state.read().await;
+        let db = state.db.read().await;
+
+        let matches = filter.matcher();
+        let views: Vec<_> = db
+            .values()
+            .filter(|property| matches(property))
+            .map(|property| property.view(&options))
+            .collect();
+
+        // This can't fail, since properties are always valid JSON
+        serde_json::to_vec(&views).unwrap_or_default()
+    };
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                export::content_disposition("properties.json", "properties.json"),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// The query parameters accepted by the price histogram
+#[derive(Deserialize)]
+struct HistogramParams {
+    /// The width of each bucket, in yen
+    bucket_size: Option<u64>,
+}
+
+/// This route counts how many properties fall into each price bucket,
+/// for drawing a chart of the price distribution
+#[debug_handler(state = AppContext)]
+async fn price_histogram(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<HistogramParams>,
+    Query(filter): Query<PropertyFilter>,
+) -> Result<Json<Vec<Bucket>>, ApiError> {
+    let filter = filter.with_config(&config);
+    let bucket_size = params.bucket_size.unwrap_or(stats::DEFAULT_BUCKET_SIZE);
+
+    if bucket_size == 0 {
+        return Err(ApiError::bad_request(
+            "invalid_bucket_size",
+            "bucket_size must be greater than zero",
+        ));
+    }
+
+    let state = state.read().await;
+    let db = state.db.read().await;
+    let matches = filter.matcher();
+    let properties = db.values().filter(|property| matches(property));
+
+    stats::price_histogram(properties, bucket_size)
+        .map(Json)
+        .map_err(|error| ApiError::bad_request("too_many_buckets", error.to_string()))
+}
+
+/// The query parameters accepted by the price percentiles
+#[derive(Deserialize)]
+struct PercentileParams {
+    /// A comma-separated list of percentiles, like `25,50,75`
+    percentiles: Option<String>,
+}
+
+/// This route finds the price at each percentile in each prefecture,
+/// such as the median, which is a better guide to typical prices than
+/// the average, since a few very expensive listings don't throw it off
+#[debug_handler(state = AppContext)]
+async fn price_percentiles(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<PercentileParams>,
+    Query(groups): Query<GroupParams>,
+    Query(filter): Query<PropertyFilter>,
+) -> Result<Response, ApiError> {
+    let filter = filter.with_config(&config);
+    let percentiles = match &params.percentiles {
+        Some(list) => stats::parse_percentiles(list)
+            .map_err(|error| ApiError::bad_request("invalid_percentile", error.to_string()))?,
+        None => stats::DEFAULT_PERCENTILES.to_vec(),
+    };
+
+    let state = state.read().await;
+    let db = state.db.read().await;
+    let matches = filter.matcher();
+    let properties = db.values().filter(|property| matches(property));
+
+    let percentiles = stats::price_percentiles(properties, &percentiles);
+
+    Ok(groups_response(percentiles, &groups, &config))
+}
+
+/// This route finds the bounding box around every property with coordinates,
+/// so that a map can be zoomed to fit them.
+/// If none of the properties have coordinates, the response is `null`.
+#[debug_handler(state = AppContext)]
+async fn properties_bounds(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(filter): Query<PropertyFilter>,
+) -> Json<Option<Bounds>> {
+    let filter = filter.with_config(&config);
+    let state = state.read().await;
+    let db = state.db.read().await;
+    let matches = filter.matcher();
+
+    Json(stats::bounds(
+        db.values().filter(|property| matches(property)),
+    ))
+}
+
+/// The query parameter for capping how many groups an aggregation sends
+#[derive(Deserialize)]
+struct GroupParams {
+    limit: Option<usize>,
+}
+
+/// The header that says how many groups an aggregation has in all,
+/// when it's sent without a limit
+const TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+
+/// Sends the groups as they are, like before there were limits,
+/// or wrapped up with how many there are if the client passed a limit.
+/// Without a limit, only the default number of groups are sent, and
+/// the `X-Total-Count` header says how many there are in all.
+fn groups_response<C>(groups: C, params: &GroupParams, config: &Config) -> Response
+where
+    C: Serialize + IntoIterator + FromIterator<C::Item>,
+    C::IntoIter: ExactSizeIterator,
+{
+    match params.limit {
+        Some(limit) => Json(stats::limit_groups(groups, limit, config.max_groups)).into_response(),
+        None => {
+            let groups = stats::limit_groups(groups, config.default_groups, config.max_groups);
+            ([(TOTAL_COUNT, groups.total.to_string())], Json(groups.data)).into_response()
+        }
+    }
+}
+
+/// This route counts the listings near each station, along with their average
+/// price, to show which stations have the most listings
+#[debug_handler(state = AppContext)]
+async fn aggregate_by_station(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(filter): Query<PropertyFilter>,
+    Query(params): Query<GroupParams>,
+) -> Response {
+    let filter = filter.with_config(&config);
+    let state = state.read().await;
+    let db = state.db.read().await;
+    let matches = filter.matcher();
+
+    let stations = stats::aggregate_by_station(db.values().filter(|property| matches(property)));
+
+    groups_response(stations, &params, &config)
+}
+
+/// This route lists just the ids of the properties, in order, for clients
+/// that want to see which properties exist without downloading them all.
+/// The same filters as the list can be used.
+#[debug_handler(state = AppContext)]
+async fn list_ids(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(filter): Query<PropertyFilter>,
+    Query(params): Query<IdParams>,
+) -> Json<Vec<FormattedId>> {
+    let filter = filter.with_config(&config);
+    let state = state.read().await;
+    let db = state.db.read().await;
+    let matches = filter.matcher();
+
+    // The db is ordered by id, so these come out sorted
+    Json(
+        db.values()
+            .filter(|property| matches(property))
+            .map(|property| params.id_format.id(property.id))
+            .collect(),
+    )
+}
+
+/// This route finds the smallest and largest values of a numeric field,
+/// like the price, for setting up the ends of a range slider.
+/// The same filters as the list can be used.
+///
+/// Values that can't be parsed are left out, and if none of the properties
+/// have a value, the response is `null`.
+#[debug_handler(state = AppContext)]
+async fn field_range(
+    Path(field): Path<String>,
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(filter): Query<PropertyFilter>,
+) -> Result<Response, ApiError> {
+    let filter = filter.with_config(&config);
+    let field: NumericField = field.parse().map_err(|error: stats::UnknownNumericField| {
+        ApiError::bad_request("unknown_field", error.to_string())
+    })?;
+
+    let state = state.read().await;
+    let db = state.db.read().await;
+    let matches = filter.matcher();
+    let properties = db.values().filter(|property| matches(property));
+
+    // Prices are whole numbers of yen, so they stay integers in the response
+    Ok(match field {
+        NumericField::Price => {
+            Json(stats::range(properties.filter_map(Property::price_value))).into_response()
+        }
+        NumericField::LandArea => Json(stats::range(
+            properties.filter_map(Property::land_area_value),
+        ))
+        .into_response(),
+        NumericField::PricePerSqm => {
+            Json(stats::range(properties.filter_map(Property::price_per_sqm))).into_response()
+        }
+    })
+}
+
+/// This route counts the listings in each prefecture by property type,
+/// for looking at what the market is made of in each region
+#[debug_handler(state = AppContext)]
+async fn crosstab(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(filter): Query<PropertyFilter>,
+    Query(params): Query<GroupParams>,
+) -> Response {
+    let filter = filter.with_config(&config);
+    let state = state.read().await;
+    let db = state.db.read().await;
+    let matches = filter.matcher();
+
+    let crosstab = stats::crosstab(db.values().filter(|property| matches(property)));
+
+    groups_response(crosstab, &params, &config)
+}
+
+/// This route lists the cities in a prefecture that have listings, for
+/// filling in a city dropdown once the user has picked a prefecture.
+///
+/// The prefecture can be given by its name or in romaji.
+#[debug_handler(state = AppContext)]
+async fn cities_in_prefecture(
+    Path(name): Path<String>,
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<GroupParams>,
+) -> Response {
+    let name = prefecture::find_by_name(&name)
+        .or_else(|| prefecture::find_by_romaji(&name))
+        .map_or(name.as_str(), |prefecture| prefecture.name);
+
+    let state = state.read().await;
+    let db = state.db.read().await;
+
+    let cities = stats::cities_in(db.values().filter(|property| !property.deleted), name);
+
+    groups_response(cities, &params, &config)
+}
+
+fn invalid_columns(error: export::InvalidColumns) -> ApiError {
+    let code = match error {
+        export::InvalidColumns::Unknown(_) => "unknown_column",
+        export::InvalidColumns::Empty => "no_columns",
+    };
+    ApiError::bad_request(code, error.to_string())
+}
+
+/// The query parameter for looking up a property that was soft deleted
+#[derive(Deserialize)]
+struct DeletedParams {
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+impl DeletedParams {
+    /// Looks up a property, treating soft deleted ones as missing
+    /// unless the client asked for them
+    fn get<'a>(&self, db: &'a DbView, id: usize) -> Option<&'a Property> {
+        db.get(id)
+            .filter(|property| self.include_deleted || !property.deleted)
+    }
+}
+
+#[debug_handler(state = AppContext)]
+async fn get_property(
+    Path(id): Path<usize>,
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(options): Query<ViewOptions>,
+    Query(deleted): Query<DeletedParams>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let options = options.with_config(&config);
+    let state = state.read().await;
+    let db = state.db.read().await;
+
+    match deleted.get(&db, id) {
+        Some(value) => json_response(&headers, value.view(&options)),
+        None => ApiError::not_found("Property not found").into_response(),
+    }
+}
+
+/// The query parameters accepted when fetching a formatted address
+#[derive(Deserialize)]
+struct FullAddressParams {
+    #[serde(default)]
+    full_address_format: AddressFormat,
+    #[serde(default)]
+    strip_leading_zeros: bool,
+    #[serde(default)]
+    mask_address: bool,
+}
+
+/// This route returns just the formatted address of a property as plain text,
+/// which is handy for printing labels
+#[debug_handler]
+async fn get_full_address(
+    Path(id): Path<usize>,
+    State(state): State<SharedState>,
+    Query(params): Query<FullAddressParams>,
+    Query(deleted): Query<DeletedParams>,
+) -> Result<String, ApiError> {
+    let state = state.read().await;
+    let db = state.db.read().await;
+
+    deleted
+        .get(&db, id)
+        .map(|property| {
+            let property = property.masked_if(params.mask_address);
+
+            if params.strip_leading_zeros {
+                property
+                    .without_leading_zeros()
+                    .full_address_with(params.full_address_format)
+            } else {
+                property.full_address_with(params.full_address_format)
+            }
+        })
+        .ok_or_else(|| ApiError::not_found("Property not found"))
+}
+
+/// The query parameters accepted when finding similar properties
+#[derive(Deserialize)]
+struct SimilarParams {
+    limit: Option<usize>,
+}
+
+/// This route recommends properties like the given one, with the same type,
+/// in the same city, and at a similar price, ordered by how close the price is
+#[debug_handler(state = AppContext)]
+async fn get_similar(
+    Path(id): Path<usize>,
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<SimilarParams>,
+    Query(options): Query<ViewOptions>,
+    Query(deleted): Query<DeletedParams>,
+    headers: HeaderMap,
+) -> Response {
+    let options = options.with_config(&config);
+    let state = state.read().await;
+    let db = state.db.read().await;
+
+    let Some(target) = deleted.get(&db, id) else {
+        return ApiError::not_found("Property not found").into_response();
+    };
+
+    let limit = params
+        .limit
+        .unwrap_or(similar::DEFAULT_LIMIT)
+        .min(config.max_page_size);
+
+    // Deleted properties aren't for sale anymore, so we never recommend them
+    let candidates = db.values().filter(|property| !property.deleted);
+
+    let views: Vec<_> = similar::find_similar(target, candidates, limit)
+        .into_iter()
+        .map(|property| property.view(&options))
+        .collect();
+
+    json_response(&headers, views)
+}
+
+/// This route fully replaces a property's data with the JSON body.
+/// Unlike a partial update, any fields missing from the body are cleared,
+/// so sending the same body twice always leaves the property in the same state.
+#[debug_handler(state = AppContext)]
+async fn replace_property(
+    Path(id): Path<usize>,
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Json(input): Json<PropertyInput>,
+) -> Result<Response, ApiError> {
+    let state = wait_for_lock(&config, state.read()).await?;
+    let mut shard = wait_for_lock(&config, state.db.write(id)).await?;
+
+    let Some(property) = shard.get_mut(&id) else {
+        return Err(ApiError::not_found("Property not found"));
+    };
+
+    *property = Property::from_input(id, input, &config.property_types, &config.station_aliases);
+    let response = json_response(&headers, property.view(&ViewOptions::from_config(&config)));
+
+    // The snapshot needs to read every shard, including this one
+    drop(shard);
+    state.mark_edited().await;
+    save_snapshot(&config, &state).await;
+
+    Ok(response)
+}
+
+/// This route deletes a property.
+///
+/// With soft deletes turned on, the property is only marked as deleted.
+/// It's left out of lists, but can still be fetched with `include_deleted=true`,
+/// and replacing it with a PUT brings it back.
+#[debug_handler(state = AppContext)]
+async fn delete_property(
+    Path(id): Path<usize>,
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+) -> Result<StatusCode, ApiError> {
+    let state = wait_for_lock(&config, state.read()).await?;
+    let mut shard = wait_for_lock(&config, state.db.write(id)).await?;
+
+    let deleted = if config.soft_delete {
+        shard
+            .get_mut(&id)
+            .filter(|property| !property.deleted)
+            .map(|property| {
+                property.deleted = true;
+                property.updated_at = Some(Utc::now());
+            })
+            .is_some()
+    } else {
+        shard.remove(&id).is_some()
+    };
+
+    drop(shard);
+
+    if !deleted {
+        return Err(ApiError::not_found("Property not found"));
+    }
+
+    state.mark_edited().await;
+    save_snapshot(&config, &state).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The number of properties an upsert added and changed
+#[derive(Serialize)]
+struct UpsertCounts {
+    inserted: usize,
+    updated: usize,
+}
+
+/// This route merges a JSON array of properties into the db, without
+/// wiping out the existing data like an upload does.
+///
+/// Properties that match an existing one by their content key are updated
+/// in place, keeping their id. Everything else is added with a new id.
+#[debug_handler(state = AppContext)]
+async fn upsert_properties(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Json(inputs): Json<Vec<PropertyInput>>,
+) -> Result<Json<UpsertCounts>, ApiError> {
+    let mut state = write_lock(&state, &config).await?;
+    let db = state.db.read().await;
+
+    // We need owned keys here, since we'll be changing the db afterwards
+    let mut ids_by_key: HashMap<[String; 11], usize> = db
+        .values()
+        .map(|property| (property.content_key().map(String::from), property.id))
+        .collect();
+
+    let mut last_id = db.last_id();
+    drop(db);
+
+    let mut counts = UpsertCounts {
+        inserted: 0,
+        updated: 0,
+    };
+
+    // Nothing is changed until every property has an id,
+    // so running out of them partway through doesn't leave half of them in
+    let mut properties = Vec::with_capacity(inputs.len());
+
+    for input in inputs {
+        let property =
+            Property::from_input(0, input, &config.property_types, &config.station_aliases);
+        let key = property.content_key().map(String::from);
+
+        let id = match ids_by_key.get(&key) {
+            Some(id) => {
+                counts.updated += 1;
+                *id
+            }
+            None => {
+                let id = id_after(last_id)?;
+                last_id = Some(id);
+                counts.inserted += 1;
+
+                // Repeats later in the same request count as updates
+                ids_by_key.insert(key, id);
+                id
+            }
+        };
+
+        properties.push(Property { id, ..property });
+    }
+
+    for property in properties {
+        state.db.insert(property);
+    }
+
+    state.mark_edited().await;
+    save_snapshot(&config, &state).await;
+
+    Ok(Json(counts))
+}
+
+/// The query parameter for how ids are written,
+/// for responses that have ids without their properties
+#[derive(Deserialize)]
+struct IdParams {
+    #[serde(default)]
+    id_format: IdFormat,
+}
+
+/// Each old id and the new one it was renumbered to,
+/// written in the format the client asked for
+struct RenumberedIds {
+    ids: BTreeMap<usize, usize>,
+    format: IdFormat,
+}
+
+impl Serialize for RenumberedIds {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            self.ids
+                .iter()
+                .map(|(old, new)| (self.format.id(*old), self.format.id(*new))),
+        )
+    }
+}
+
+/// This route adds each property in a JSON array to the db with a new id,
+/// responding with the ids in the same order as the array.
+///
+/// Unlike an upsert, nothing is matched up with the existing data,
+/// so sending the same array twice adds everything twice.
+#[debug_handler(state = AppContext)]
+async fn create_properties(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<IdParams>,
+    Json(inputs): Json<Vec<PropertyInput>>,
+) -> Result<(StatusCode, Json<Vec<FormattedId>>), ApiError> {
+    let mut state = write_lock(&state, &config).await?;
+
+    let mut last_id = state.db.read().await.last_id();
+    let ids = inputs
+        .iter()
+        .map(|_| {
+            let id = id_after(last_id)?;
+            last_id = Some(id);
+            Ok(id)
+        })
+        .collect::<Result<Vec<usize>, ApiError>>()?;
+
+    for (id, input) in ids.iter().zip(inputs) {
+        state.db.insert(Property::from_input(
+            *id,
+            input,
+            &config.property_types,
+            &config.station_aliases,
+        ));
+    }
+
+    state.mark_edited().await;
+    save_snapshot(&config, &state).await;
+
+    let ids = ids.into_iter().map(|id| params.id_format.id(id)).collect();
+    Ok((StatusCode::CREATED, Json(ids)))
+}
+
+/// The id for a new property after the one with `last_id`, or the first id
+/// for an empty db. This fails once the ids reach [`MAX_ID`], since they're
+/// never reused, but compacting them frees up the ones in the gaps.
+fn id_after(last_id: Option<usize>) -> Result<usize, ApiError> {
+    match last_id {
+        None => Ok(1),
+        Some(id) if id < MAX_ID => Ok(id + 1),
+        Some(_) => Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "ids_exhausted",
+            format!("there are no ids left after {MAX_ID}, compact the ids to free some up"),
+        )),
+    }
+}
+
+/// This route renumbers the properties from 1 with no gaps, in id order,
+/// responding with a map of each old id to its new one.
+///
+/// Ids are row numbers, so they can have gaps from skipped rows or changes.
+/// This is a maintenance operation, since it breaks any stored references.
+#[debug_handler(state = AppContext)]
+async fn compact_ids(
+    State(state): State<SharedState>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<IdParams>,
+) -> Result<Json<RenumberedIds>, ApiError> {
+    let mut state = write_lock(&state, &config).await?;
+
+    let properties = state.db.take().into_values();
+    let mut ids = BTreeMap::new();
+    let now = Utc::now();
+
+    for (i, property) in properties.enumerate() {
+        let id = i + 1;
+        ids.insert(property.id, id);
+
+        // Clients syncing changes need to know about the new ids
+        let updated_at = if id == property.id {
+            property.updated_at
+        } else {
+            Some(now)
+        };
+
+        state.db.insert(Property {
+            id,
+            updated_at,
+            ..property
+        });
+    }
+
+    state.mark_edited().await;
+    save_snapshot(&config, &state).await;
+
+    Ok(Json(RenumberedIds {
+        ids,
+        format: params.id_format,
+    }))
+}
+
+/// Takes the write lock, giving up with `503 Service Unavailable` if it
+/// can't be taken within the configured timeout.
+///
+/// Mutating handlers use this instead of waiting forever, so that a long
+/// upload holding the lock doesn't leave other clients hanging.
+async fn write_lock<'a>(
+    state: &'a SharedState,
+    config: &Config,
+) -> Result<RwLockWriteGuard<'a, AppState>, ApiError> {
+    wait_for_lock(config, state.write()).await
+}
+
+/// Waits for any lock, with the same timeout as [`write_lock`]
+async fn wait_for_lock<T>(config: &Config, lock: impl Future<Output = T>) -> Result<T, ApiError> {
+    tokio::time::timeout(config.lock_timeout, lock)
+        .await
+        .map_err(|_| {
+            ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "busy",
+                "the server is busy with another change, please try again",
+            )
+        })
+}
+
+/// Writes the db out to the snapshot file, if one is configured.
+///
+/// This is called after every change to the db. Edits to different shards
+/// can finish at the same time, so we hold the snapshot lock from reading
+/// the db until the file is written, so that snapshots are always written
+/// in order, and the last one has every change.
+async fn save_snapshot(config: &Config, state: &AppState) {
+    if let Some(path) = &config.snapshot_path {
+        let _snapshot_lock = state.snapshot_lock.lock().await;
+        let db = state.db.read().await;
+
+        if let Err(error) = snapshot::save(path, db.values()).await {
+            tracing::error!(%error, path = %path.display(), "failed to save snapshot");
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ParseAddressRequest {
+    address: String,
+}
+
+/// This route splits a raw address string into the same components
+/// that we store for each property
+#[debug_handler]
+async fn parse_address(
+    Json(request): Json<ParseAddressRequest>,
+) -> Result<Json<AddressParts>, ApiError> {
+    address::parse_address(&request.address)
+        .map(Json)
+        .map_err(|error| {
+            ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_address",
+                error.to_string(),
+            )
+        })
+}
+
+/// Browsers that land on a missing page get a small HTML page,
+/// and everything else gets the usual JSON error
+#[debug_handler]
+async fn not_found(headers: HeaderMap) -> Response {
+    let vary = [(header::VARY, "accept")];
+
+    if response::wants_html(&headers) {
+        return (StatusCode::NOT_FOUND, vary, Html(response::NOT_FOUND_PAGE)).into_response();
+    }
+
+    let error = ApiError::not_found("The page you're looking for doesn't exist");
+    (vary, error).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use axum::http::{Method, Request};
+    use japanese_properties_api::property_type::CanonicalType;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+
+    /// Two properties, with ids 1 and 2
+    const SAMPLE: &str = "prefecture,city,town,chome,banchi,go,building,price,nearest_station,property_type,land_area
+東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100
+大阪府,大阪市,梅田,2,3,4,梅田ビル,5000万円,梅田,マンション,80
+";
+
+    fn server(config: Config) -> Router {
+        app(AppContext {
+            state: SharedState::default(),
+            config: Arc::new(config),
+        })
+    }
+
+    /// A server with the sample data uploaded
+    async fn sample_server(config: Config) -> Router {
+        let app = server(config);
+        let response = send(&app, upload("/properties/upload", &[SAMPLE.as_bytes()])).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        app
+    }
+
+    async fn send(app: &Router, request: Request<Body>) -> Response {
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn body(response: Response) -> Bytes {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    async fn text(response: Response) -> String {
+        String::from_utf8(body(response).await.to_vec()).unwrap()
+    }
+
+    async fn json(response: Response) -> Value {
+        serde_json::from_slice(&body(response).await).unwrap()
+    }
+
+    /// Percent-encodes anything in the uri that isn't ASCII, like Japanese
+    /// in the query string, so that it can be sent
+    fn encode(uri: &str) -> String {
+        uri.bytes()
+            .map(|byte| match byte {
+                0x80.. => format!("%{byte:02X}"),
+                _ => (byte as char).to_string(),
+            })
+            .collect()
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(encode(uri)).body(Body::empty()).unwrap()
+    }
+
+    fn with_json(method: Method, uri: &str, value: Value) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(value.to_string()))
+            .unwrap()
+    }
+
+    const BOUNDARY: &str = "test-boundary";
+
+    /// A multipart form with each of the files in a `file` field
+    fn upload(uri: &str, files: &[&[u8]]) -> Request<Body> {
+        let fields: Vec<(&str, &[u8])> = files.iter().map(|file| ("file", *file)).collect();
+        form(uri, &fields)
+    }
+
+    /// A multipart form with the given fields, in order
+    fn form(uri: &str, fields: &[(&str, &[u8])]) -> Request<Body> {
+        let mut body = vec![];
+
+        for (name, data) in fields {
+            body.extend_from_slice(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"; filename=\"{name}\"\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+
+        Request::post(uri)
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    /// The JSON for a property, with every field empty except these ones
+    fn input(fields: Value) -> Value {
+        let mut input = json!({
+            "prefecture": "", "city": "", "town": "", "chome": "", "banchi": "", "go": "",
+            "building": "", "price": "", "nearest_station": "", "property_type": "", "land_area": "",
+        });
+        input
+            .as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        input
+    }
+
+    #[tokio::test]
+    async fn replaces_a_property() {
+        let app = sample_server(Config::default()).await;
+
+        // Only some of the fields are sent
+        let replacement = json!({ "prefecture": "京都府", "city": "京都市" });
+        let response = send(&app, with_json(Method::PUT, "/properties/1", replacement)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let property = json(send(&app, get("/properties/1")).await).await;
+        assert_eq!(property["prefecture"], "京都府");
+        assert_eq!(property["city"], "京都市");
+        // Fields that weren't sent are cleared, rather than kept
+        for field in [
+            "town",
+            "chome",
+            "banchi",
+            "go",
+            "price",
+            "nearest_station",
+            "property_type",
+        ] {
+            assert_eq!(property[field], "", "{field}");
+        }
+    }
+
+    #[tokio::test]
+    async fn replacing_a_missing_property_is_not_found() {
+        let app = sample_server(Config::default()).await;
+
+        let response = send(
+            &app,
+            with_json(Method::PUT, "/properties/99", input(json!({}))),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(json(response).await["error"]["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn wraps_responses_in_the_envelope_when_asked() {
+        let app = sample_server(Config::default()).await;
+
+        let request = Request::get("/properties/1")
+            .header(header::ACCEPT, response::V1_MEDIA_TYPE)
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&app, request).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            response::V1_MEDIA_TYPE
+        );
+
+        let envelope = json(response).await;
+        assert_eq!(envelope["api_version"], "1");
+        assert_eq!(envelope["data"]["id"], 1);
+
+        // Without the header, the property is sent bare
+        let property = json(send(&app, get("/properties/1")).await).await;
+        assert_eq!(property["id"], 1);
+        assert!(property.get("api_version").is_none());
+    }
+
+    #[tokio::test]
+    async fn parses_an_address() {
+        let app = server(Config::default());
+
+        let request = json!({ "address": "東京都渋谷区神南1丁目2番3号 渋谷ビル" });
+        let response = send(&app, with_json(Method::POST, "/address/parse", request)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let parts = json(response).await;
+        assert_eq!(parts["prefecture"], "東京都");
+        assert_eq!(parts["city"], "渋谷区");
+        assert_eq!(parts["town"], "神南");
+        assert_eq!(parts["chome"], "1");
+        assert_eq!(parts["banchi"], "2");
+        assert_eq!(parts["go"], "3");
+        assert_eq!(parts["building"], "渋谷ビル");
+    }
+
+    #[tokio::test]
+    async fn rejects_an_address_it_cannot_parse() {
+        let app = server(Config::default());
+
+        let request = json!({ "address": "渋谷区神南1丁目2番3号" });
+        let response = send(&app, with_json(Method::POST, "/address/parse", request)).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json(response).await["error"]["code"], "invalid_address");
+    }
+
+    #[tokio::test]
+    async fn uploads_with_a_column_mapping() {
+        let app = server(Config::default());
+
+        let file = "価格,県,市,町,丁目,番地,号,建物,駅,種別,面積\n\
+                    1000万円,東京都,渋谷区,神南,1,2,3,,渋谷,土地,100\n";
+        let mapping = r#"{
+            "prefecture": "県", "city": "市", "town": "町", "chome": "丁目", "banchi": "番地",
+            "go": "号", "building": "建物", "price": "価格", "nearest_station": "駅",
+            "property_type": "種別", "land_area": "面積"
+        }"#;
+        let request = form(
+            "/properties/upload",
+            &[("mapping", mapping.as_bytes()), ("file", file.as_bytes())],
+        );
+        assert_eq!(send(&app, request).await.status(), StatusCode::OK);
+
+        let property = json(send(&app, get("/properties/1")).await).await;
+        assert_eq!(property["prefecture"], "東京都");
+        assert_eq!(property["price"], "1000万円");
+    }
+
+    #[tokio::test]
+    async fn rejects_an_invalid_column_mapping() {
+        let app = server(Config::default());
+
+        let request = form(
+            "/properties/upload",
+            &[("mapping", b"{ not json"), ("file", SAMPLE.as_bytes())],
+        );
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(response).await["error"]["code"], "invalid_mapping");
+
+        let request = form(
+            "/properties/upload",
+            &[
+                ("mapping", br#"{ "prefecture": 0 }"#),
+                ("file", SAMPLE.as_bytes()),
+            ],
+        );
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(response).await["error"]["code"], "invalid_csv");
+    }
+
+    #[tokio::test]
+    async fn saves_a_snapshot_after_each_change() {
+        let path =
+            std::env::temp_dir().join(format!("snapshot-{}-upload.json", std::process::id()));
+        let config = Config {
+            snapshot_path: Some(path.clone()),
+            ..Default::default()
+        };
+        let app = sample_server(config).await;
+
+        let saved = snapshot::load(&path).await.unwrap().unwrap();
+        assert_eq!(saved.keys().copied().collect::<Vec<_>>(), [1, 2]);
+
+        assert!(send(&app, delete("/properties/2"))
+            .await
+            .status()
+            .is_success());
+
+        let saved = snapshot::load(&path).await.unwrap().unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert_eq!(saved.keys().copied().collect::<Vec<_>>(), [1]);
+    }
+
+    #[tokio::test]
+    async fn flags_partial_rows_instead_of_skipping_them() {
+        let app = server(Config::default());
+        let file = format!("{SAMPLE}京都府,京都市\n");
+
+        let response = send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+        assert_eq!(response.headers()["x-skipped-rows"], "1");
+
+        let request = upload("/properties/upload?keep_partial=true", &[file.as_bytes()]);
+        let response = send(&app, request).await;
+        assert_eq!(response.headers()["x-skipped-rows"], "0");
+
+        let partial = json(send(&app, get("/properties/3")).await).await;
+        assert_eq!(partial["city"], "京都市");
+        assert_eq!(partial["complete"], false);
+        // Complete properties aren't flagged at all
+        let complete = json(send(&app, get("/properties/1")).await).await;
+        assert!(complete.get("complete").is_none());
+    }
+
+    #[tokio::test]
+    async fn leaves_out_the_full_address_when_asked() {
+        let app = sample_server(Config::default()).await;
+
+        let property = json(send(&app, get("/properties/1")).await).await;
+        assert_eq!(property["full_address"], "東京都渋谷区神南1丁目2番地3号");
+
+        let property =
+            json(send(&app, get("/properties/1?include_full_address=false")).await).await;
+        assert!(property.get("full_address").is_none());
+
+        let list = json(send(&app, get("/properties?include_full_address=false")).await).await;
+        assert!(list
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|property| property.get("full_address").is_none()));
+    }
+
+    #[tokio::test]
+    async fn skips_an_upload_with_the_same_checksum() {
+        let app = server(Config::default());
+
+        let response = send(&app, upload("/properties/upload", &[SAMPLE.as_bytes()])).await;
+        let checksum = response.headers()["x-content-sha256"].clone();
+        assert_eq!(checksum, format!("{:x}", Sha256::digest(SAMPLE)).as_str());
+
+        let with_checksum = |checksum| {
+            let mut request = upload("/properties/upload", &[SAMPLE.as_bytes()]);
+            request.headers_mut().insert("x-content-sha256", checksum);
+            request
+        };
+
+        let response = send(&app, with_checksum(checksum)).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = send(&app, with_checksum(HeaderValue::from_static("abc123"))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn sends_every_error_in_the_same_format() {
+        let app = sample_server(Config::default()).await;
+
+        for (uri, status, code) in [
+            ("/properties/abc", StatusCode::BAD_REQUEST, "invalid_path"),
+            ("/properties/99", StatusCode::NOT_FOUND, "not_found"),
+            (
+                "/properties?limit=abc",
+                StatusCode::BAD_REQUEST,
+                "invalid_query",
+            ),
+            ("/nowhere", StatusCode::NOT_FOUND, "not_found"),
+        ] {
+            let response = send(&app, get(uri)).await;
+            assert_eq!(response.status(), status, "{uri}");
+
+            let error = json(response).await;
+            assert_eq!(error["error"]["code"], code, "{uri}");
+            assert!(error["error"]["message"].is_string(), "{uri}");
+        }
+
+        let request = Request::put("/properties/1")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("{"))
+            .unwrap();
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(response).await["error"]["code"], "invalid_json");
+    }
+
+    #[tokio::test]
+    async fn pages_through_the_list() {
+        let config = Config {
+            default_page_size: 1,
+            ..Default::default()
+        };
+        let app = sample_server(config).await;
+
+        let page = json(send(&app, get("/properties?offset=0")).await).await;
+        assert_eq!(page["data"].as_array().unwrap().len(), 1);
+        assert_eq!(page["data"][0]["id"], 1);
+        assert_eq!(page["pagination"]["total"], 2);
+        assert_eq!(page["pagination"]["has_more"], true);
+
+        // Lists are only paginated when the client asks for it
+        let list = json(send(&app, get("/properties")).await).await;
+        assert_eq!(list.as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn diffs_the_last_upload() {
+        let app = sample_server(Config::default()).await;
+
+        let file = SAMPLE.replace("5000万円", "4500万円");
+        let response = send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let diff = json(send(&app, get("/properties/diff")).await).await;
+        assert_eq!(diff["added"], json!([]));
+        assert_eq!(diff["removed"], json!([]));
+        assert_eq!(diff["changed"][0]["before"]["price"], "5000万円");
+        assert_eq!(diff["changed"][0]["after"]["price"], "4500万円");
+    }
+
+    #[tokio::test]
+    async fn rejects_uploads_over_the_row_limit() {
+        let config = Config {
+            max_rows: Some(1),
+            ..Default::default()
+        };
+        let app = server(config);
+
+        let response = send(&app, upload("/properties/upload", &[SAMPLE.as_bytes()])).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(json(response).await["error"]["code"], "too_many_rows");
+
+        let list = json(send(&app, get("/properties")).await).await;
+        assert_eq!(list, json!([]));
+    }
+
+    #[tokio::test]
+    async fn counts_the_row_limit_across_every_file() {
+        let config = Config {
+            max_rows: Some(3),
+            ..Default::default()
+        };
+        let app = server(config);
+
+        let files: &[&[u8]] = &[SAMPLE.as_bytes(), SAMPLE.as_bytes()];
+        let response = send(&app, upload("/properties/upload", files)).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(json(response).await["error"]["code"], "too_many_rows");
+    }
+
+    #[tokio::test]
+    async fn filters_by_romaji_prefecture() {
+        let app = sample_server(Config::default()).await;
+
+        let list = json(send(&app, get("/properties?romaji=Osaka-fu")).await).await;
+        assert_eq!(list.as_array().unwrap().len(), 1);
+        assert_eq!(list[0]["prefecture"], "大阪府");
+
+        // An unknown name matches nothing, rather than being an error
+        let list = json(send(&app, get("/properties?romaji=atlantis")).await).await;
+        assert_eq!(list, json!([]));
+    }
+
+    fn with_range(uri: &str, range: &str) -> Request<Body> {
+        Request::get(uri)
+            .header(header::RANGE, range)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_part_of_the_export_for_a_range() {
+        let app = sample_server(Config::default()).await;
+        let full = body(send(&app, get("/properties/export")).await).await;
+
+        let response = send(&app, with_range("/properties/export", "bytes=0-9")).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            format!("bytes 0-9/{}", full.len()).as_str()
+        );
+        assert_eq!(body(response).await, full.slice(0..10));
+
+        let response = send(&app, with_range("/properties/export", "bytes=-5")).await;
+        assert_eq!(body(response).await, full.slice(full.len() - 5..));
+
+        let range = format!("bytes={}-", full.len());
+        let response = send(&app, with_range("/properties/export", &range)).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            format!("bytes */{}", full.len()).as_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_off_the_trailing_newline_when_asked() {
+        let app = sample_server(Config::default()).await;
+
+        for stream_mode in [StreamMode::Live, StreamMode::Snapshot] {
+            let config = Config {
+                stream_mode,
+                ..Default::default()
+            };
+            let app = sample_server(config).await;
+
+            let csv = text(send(&app, get("/properties/export?columns=id")).await).await;
+            assert_eq!(csv, "id\n1\n2\n");
+
+            let uri = "/properties/export?columns=id&trailing_newline=false";
+            let csv = text(send(&app, get(uri)).await).await;
+            assert_eq!(csv, "id\n1\n2");
+        }
+
+        let request = with_range(
+            "/properties/export?columns=id&trailing_newline=false",
+            "bytes=-2",
+        );
+        assert_eq!(text(send(&app, request).await).await, "\n2");
+    }
+
+    #[tokio::test]
+    async fn reports_data_integrity() {
+        let app = server(Config::default());
+        let file = format!("{SAMPLE}京都府,京都市,,1,2,3,,応相談,京都,土地,100\n");
+        send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+
+        let report = json(send(&app, get("/health/integrity")).await).await;
+        assert_eq!(
+            report,
+            json!({
+                "total": 3,
+                "unparseable_price": 1,
+                "unparseable_land_area": 0,
+                "missing_address": 1,
+                "unknown_prefecture": 0,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn pages_through_the_list_with_a_cursor() {
+        let app = sample_server(Config::default()).await;
+
+        let page = json(send(&app, get("/properties?limit=1")).await).await;
+        assert_eq!(page["data"][0]["id"], 1);
+        let cursor = page["next_cursor"].as_str().unwrap().to_string();
+
+        // Removing a property before the cursor doesn't shift the next page
+        assert!(send(&app, delete("/properties/1"))
+            .await
+            .status()
+            .is_success());
+
+        let uri = format!("/properties?limit=1&after={cursor}");
+        let page = json(send(&app, get(&uri)).await).await;
+        assert_eq!(page["data"][0]["id"], 2);
+        assert!(page.get("next_cursor").is_none());
+    }
+
+    #[tokio::test]
+    async fn escapes_formulas_in_the_export() {
+        let app = server(Config::default());
+        let file = SAMPLE.replace("神南", "=1+1");
+        send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+
+        let csv = text(send(&app, get("/properties/export?columns=town")).await).await;
+        assert_eq!(csv, "town\n'=1+1\n梅田\n");
+
+        let uri = "/properties/export?columns=town&formula_escape=none";
+        let csv = text(send(&app, get(uri)).await).await;
+        assert_eq!(csv, "town\n=1+1\n梅田\n");
+    }
+
+    #[tokio::test]
+    async fn finds_properties_by_part_of_the_address() {
+        let app = sample_server(Config::default()).await;
+
+        let uri = "/properties?full_address_contains=梅田2丁目";
+        let list = json(send(&app, get(uri)).await).await;
+        assert_eq!(list.as_array().unwrap().len(), 1);
+        assert_eq!(list[0]["id"], 2);
+
+        // It's matched against the formal address, whatever format is asked for
+        let uri = "/properties?full_address_contains=神南1丁目&full_address_format=hyphenated";
+        let list = json(send(&app, get(uri)).await).await;
+        assert_eq!(list[0]["id"], 1);
+
+        let list = json(send(&app, get("/properties?full_address_contains=福岡")).await).await;
+        assert_eq!(list, json!([]));
+    }
+
+    #[tokio::test]
+    async fn compresses_with_the_preferred_encoding() {
+        let config = Config {
+            compression_algorithms: vec![Encoding::Gzip, Encoding::Br],
+            ..Default::default()
+        };
+        let app = sample_server(config).await;
+
+        let with_encoding = |accept_encoding| {
+            Request::get("/properties")
+                .header(header::ACCEPT_ENCODING, accept_encoding)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = send(&app, with_encoding("br, gzip")).await;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+
+        let response = send(&app, with_encoding("gzip;q=0, br")).await;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "br");
+
+        // Deflate isn't enabled, so the response is sent as it is
+        let response = send(&app, with_encoding("deflate")).await;
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn sends_the_full_address_as_text() {
+        let app = sample_server(Config::default()).await;
+
+        let response = send(&app, get("/properties/2/full_address")).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            text(response).await,
+            "大阪府大阪市梅田2丁目3番地4号梅田ビル"
+        );
+
+        let uri = "/properties/2/full_address?full_address_format=postal";
+        let address = text(send(&app, get(uri)).await).await;
+        assert_eq!(address, "大阪府大阪市梅田2-3-4 梅田ビル");
+
+        let response = send(&app, get("/properties/99/full_address")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn seeds_from_the_bundled_sample() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("sample")
+            .join("japanese_properties.csv");
+        let properties = load_seed_file(&path, &Config::default()).await.unwrap();
+
+        assert_eq!(properties.len(), 5000);
+        assert_eq!(properties[0].id, 1);
+        assert_eq!(properties[0].prefecture, "神奈川県");
+        assert_eq!(properties[0].canonical_type, CanonicalType::House);
+
+        let missing = path.with_file_name("missing.csv");
+        assert!(load_seed_file(&missing, &Config::default()).await.is_err());
+    }
+
+    /// The sample property with id 1, as it would be sent in a request
+    fn shibuya() -> Value {
+        input(json!({
+            "prefecture": "東京都", "city": "渋谷区", "town": "神南", "chome": "1", "banchi": "2",
+            "go": "3", "price": "1000万円", "nearest_station": "渋谷", "property_type": "土地",
+            "land_area": "100",
+        }))
+    }
+
+    #[tokio::test]
+    async fn upserts_properties_by_content() {
+        let app = sample_server(Config::default()).await;
+
+        let kyoto = input(json!({ "prefecture": "京都府", "city": "京都市" }));
+        let body = json!([shibuya(), kyoto, kyoto]);
+        let response = send(&app, with_json(Method::POST, "/properties/upsert", body)).await;
+        assert_eq!(json(response).await, json!({ "inserted": 1, "updated": 2 }));
+
+        let list = json(send(&app, get("/properties")).await).await;
+        let ids: Vec<_> = list
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["id"].clone())
+            .collect();
+        assert_eq!(ids, [1, 2, 3]);
+        assert_eq!(list[2]["prefecture"], "京都府");
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_uploads_that_are_too_slow() {
+        let config = Config {
+            upload_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let app = server(config);
+
+        // The start of the form arrives, but the rest never does
+        let start = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"file\"\r\n\r\n{SAMPLE}"
+        );
+        let body = stream::once(async { Ok::<_, Infallible>(start) }).chain(stream::pending());
+        let request = Request::post("/properties/upload")
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(Body::from_stream(body))
+            .unwrap();
+
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(json(response).await["error"]["code"], "upload_timeout");
+    }
+
+    #[tokio::test]
+    async fn exports_one_prefecture() {
+        let app = sample_server(Config::default()).await;
+
+        let response = send(&app, get("/properties/export?prefecture=大阪府&columns=id")).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"properties_Osaka.csv\"; \
+             filename*=UTF-8''properties_%E5%A4%A7%E9%98%AA%E5%BA%9C.csv"
+        );
+        assert_eq!(text(response).await, "id\n2\n");
+
+        let response = send(&app, get("/properties/export")).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"properties.csv\""
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_properties_by_price_bucket() {
+        let app = sample_server(Config::default()).await;
+
+        let uri = "/properties/price_histogram?bucket_size=20000000";
+        let buckets = json(send(&app, get(uri)).await).await;
+        assert_eq!(
+            buckets,
+            json!([
+                { "min": 0, "max": 20000000, "count": 1 },
+                { "min": 20000000, "max": 40000000, "count": 0 },
+                { "min": 40000000, "max": 60000000, "count": 1 },
+            ])
+        );
+
+        let uri = "/properties/price_histogram?bucket_size=0";
+        let response = send(&app, get(uri)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(response).await["error"]["code"], "invalid_bucket_size");
+
+        let uri = "/properties/price_histogram?bucket_size=1";
+        let response = send(&app, get(uri)).await;
+        assert_eq!(json(response).await["error"]["code"], "too_many_buckets");
+    }
+
+    fn with_accept(uri: &str, accept: &str) -> Request<Body> {
+        Request::get(encode(uri))
+            .header(header::ACCEPT, accept)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn lists_as_csv_when_asked() {
+        let app = sample_server(Config::default()).await;
+
+        let request = with_accept("/properties?columns=id,town", "text/csv;q=0.9");
+        let response = send(&app, request).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], CSV_CONTENT_TYPE);
+        assert_eq!(response.headers()[header::VARY], "accept");
+        assert_eq!(text(response).await, "id,town\n1,神南\n2,梅田\n");
+
+        let request = with_accept("/properties", "application/json");
+        let response = send(&app, request).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_export_columns() {
+        let app = sample_server(Config::default()).await;
+
+        let response = send(&app, get("/properties/export?columns=id,rent")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(response).await["error"]["code"], "unknown_column");
+    }
+
+    #[tokio::test]
+    async fn rejects_exports_without_any_columns() {
+        let app = sample_server(Config::default()).await;
+
+        let response = send(&app, get("/properties/export?columns=,")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(response).await["error"]["code"], "no_columns");
+    }
+
+    #[tokio::test]
+    async fn recommends_similar_properties() {
+        let app = server(Config::default());
+        let file = format!(
+            "{SAMPLE}大阪府,大阪市,梅田,1,1,1,,5500万円,梅田,マンション,70\n\
+             大阪府,大阪市,梅田,1,1,2,,9000万円,梅田,マンション,70\n"
+        );
+        send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+
+        let similar = json(send(&app, get("/properties/2/similar")).await).await;
+        assert_eq!(similar.as_array().unwrap().len(), 1);
+        assert_eq!(similar[0]["id"], 3);
+
+        let response = send(&app, get("/properties/99/similar")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn fails_a_strict_upload_with_any_invalid_row() {
+        let app = sample_server(Config::default()).await;
+        let file = format!("{SAMPLE}bad,row\n");
+
+        let response = send(
+            &app,
+            upload("/properties/upload?strict=true", &[file.as_bytes()]),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let error = json(response).await;
+        assert_eq!(error["error"]["code"], "invalid_rows");
+        assert_eq!(error["error"]["details"][0]["row"], 3);
+
+        // The data from before is left alone
+        let list = json(send(&app, get("/properties")).await).await;
+        assert_eq!(list.as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn finds_the_bounding_box() {
+        let app = server(Config::default());
+        assert_eq!(
+            json(send(&app, get("/properties/bounds")).await).await,
+            Value::Null
+        );
+
+        let file = "prefecture,city,town,chome,banchi,go,building,price,nearest_station,property_type,land_area,latitude,longitude
+東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100,35.66,139.7
+大阪府,大阪市,梅田,2,3,4,,5000万円,梅田,マンション,80,34.7,135.49
+";
+        send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+
+        let bounds = json(send(&app, get("/properties/bounds")).await).await;
+        assert_eq!(
+            bounds,
+            json!({
+                "min_latitude": 34.7,
+                "max_latitude": 35.66,
+                "min_longitude": 135.49,
+                "max_longitude": 139.7,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn replays_an_upload_with_the_same_idempotency_key() {
+        let app = server(Config::default());
+
+        let with_key = |file: &str| {
+            let mut request = upload("/properties/upload", &[file.as_bytes()]);
+            request
+                .headers_mut()
+                .insert("idempotency-key", HeaderValue::from_static("upload-1"));
+            request
+        };
+
+        let first = send(&app, with_key(SAMPLE)).await;
+        assert!(!first.headers().contains_key("idempotent-replayed"));
+        let first = json(first).await;
+
+        // The retry isn't imported again, even if the file changed
+        let file = SAMPLE.replace("5000万円", "4500万円");
+        let retry = send(&app, with_key(&file)).await;
+        assert_eq!(retry.headers()["idempotent-replayed"], "true");
+        assert_eq!(json(retry).await, first);
+
+        let property = json(send(&app, get("/properties/2")).await).await;
+        assert_eq!(property["price"], "5000万円");
+    }
+
+    #[tokio::test]
+    async fn reports_memory_use_when_asked() {
+        let app = sample_server(Config::default()).await;
+
+        let health = json(send(&app, get("/health")).await).await;
+        assert_eq!(health["status"], "ok");
+        assert_eq!(health["properties"], 2);
+        assert!(health.get("memory").is_none());
+
+        let health = json(send(&app, get("/health?include_memory=true")).await).await;
+        assert!(health["memory"]["db_bytes"].as_u64().unwrap() > 0);
+        // The resident size can only be read on Linux
+        if cfg!(target_os = "linux") {
+            assert!(health["memory"]["resident_bytes"].as_u64().unwrap() > 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_to_import_from_local_urls() {
+        let app = server(Config::default());
+
+        let body = json!({ "url": "http://127.0.0.1:1/sample.csv" });
+        let response = send(
+            &app,
+            with_json(Method::POST, "/properties/upload/url", body),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(response).await["error"]["code"], "forbidden_url");
+
+        let body = json!({ "url": "not a url" });
+        let response = send(
+            &app,
+            with_json(Method::POST, "/properties/upload/url", body),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(response).await["error"]["code"], "invalid_url");
+    }
+
+    #[tokio::test]
+    async fn downloads_the_original_upload() {
+        let app = server(Config::default());
+
+        let response = send(&app, get("/properties/upload/original")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let other = SAMPLE.replace("5000万円", "4500万円");
+        let request = upload("/properties/upload", &[SAMPLE.as_bytes(), other.as_bytes()]);
+        send(&app, request).await;
+
+        let response = send(&app, get("/properties/upload/original")).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], CSV_CONTENT_TYPE);
+        assert_eq!(text(response).await, SAMPLE);
+
+        let response = send(&app, get("/properties/upload/original?index=1")).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"upload_1.csv\""
+        );
+        assert_eq!(text(response).await, other);
+    }
+
+    #[tokio::test]
+    async fn sends_string_ids_when_asked() {
+        let app = sample_server(Config::default()).await;
+
+        let property = json(send(&app, get("/properties/2?id_format=string")).await).await;
+        assert_eq!(property["id"], "000002");
+
+        // Padded ids work in paths too, since they're still numbers
+        let property = json(send(&app, get("/properties/000002")).await).await;
+        assert_eq!(property["id"], 2);
+    }
+
+    #[tokio::test]
+    async fn streams_large_exports_in_full() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("sample")
+            .join("japanese_properties.csv");
+        let file = std::fs::read(path).unwrap();
+
+        for stream_mode in [StreamMode::Live, StreamMode::Snapshot] {
+            let config = Config {
+                stream_mode,
+                ..Default::default()
+            };
+            let app = server(config);
+            send(&app, upload("/properties/upload", &[&file])).await;
+
+            // This spans several batches of a live stream, and several chunks
+            let csv = text(send(&app, get("/properties/export?columns=id")).await).await;
+            let expected: String = std::iter::once("id\n".to_string())
+                .chain((1..=5000).map(|id| format!("{id}\n")))
+                .collect();
+            assert_eq!(csv, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_rows_that_break_the_validation_rules() {
+        let rules = r#"{ "building": { "required": true } }"#;
+        let config = Config {
+            validation_rules: ValidationRules::from_json(rules).unwrap(),
+            ..Default::default()
+        };
+        let app = server(config);
+
+        let response = send(&app, upload("/properties/upload", &[SAMPLE.as_bytes()])).await;
+        assert_eq!(response.headers()["x-skipped-rows"], "1");
+
+        let list = json(send(&app, get("/properties")).await).await;
+        assert_eq!(list.as_array().unwrap().len(), 1);
+        assert_eq!(list[0]["building"], "梅田ビル");
+    }
+
+    /// The ids of the properties in a list response, in order
+    fn ids(list: &Value) -> Vec<Value> {
+        list.as_array()
+            .unwrap()
+            .iter()
+            .map(|property| property["id"].clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn compacts_the_ids() {
+        let app = server(Config::default());
+        // The bad row in the middle leaves a gap in the ids
+        let file = SAMPLE.replacen("\n大阪府", "\nbad,row\n大阪府", 1);
+        send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+        assert_eq!(
+            ids(&json(send(&app, get("/properties")).await).await),
+            [1, 3]
+        );
+
+        let request = Request::post("/properties/compact")
+            .body(Body::empty())
+            .unwrap();
+        let renumbered = json(send(&app, request).await).await;
+        assert_eq!(renumbered, json!({ "1": 1, "3": 2 }));
+
+        let list = json(send(&app, get("/properties")).await).await;
+        assert_eq!(ids(&list), [1, 2]);
+        assert_eq!(list[1]["prefecture"], "大阪府");
+    }
+
+    #[tokio::test]
+    async fn writes_the_compacted_ids_in_the_format_asked_for() {
+        let app = server(Config::default());
+        let file = SAMPLE.replacen("\n大阪府", "\nbad,row\n大阪府", 1);
+        send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+
+        let request = Request::post("/properties/compact?id_format=string")
+            .body(Body::empty())
+            .unwrap();
+        let renumbered = json(send(&app, request).await).await;
+        assert_eq!(
+            renumbered,
+            json!({ "000001": "000001", "000003": "000002" })
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_the_header_from_the_row_asked_for() {
+        let app = server(Config::default());
+        let file = format!("物件一覧\n{SAMPLE}");
+
+        let request = upload("/properties/upload?header_row=1", &[file.as_bytes()]);
+        let response = send(&app, request).await;
+        assert_eq!(response.headers()["x-skipped-rows"], "0");
+        assert_eq!(ids(&json(response).await), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn responds_with_no_content_for_an_empty_db_when_asked() {
+        let app = server(Config::default());
+
+        let list = json(send(&app, get("/properties")).await).await;
+        assert_eq!(list, json!([]));
+
+        let response = send(&app, get("/properties?if_empty=no_content")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(body(response).await.is_empty());
+
+        // Filtering everything out counts as empty too
+        let app = sample_server(Config::default()).await;
+        let response = send(
+            &app,
+            get("/properties?prefecture=福岡県&if_empty=no_content"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let file = "prefecture,city,town,chome,banchi,go,building,price,nearest_station,property_type,land_area\n";
+        let request = upload("/properties/upload?if_empty=no_content", &[file.as_bytes()]);
+        assert_eq!(send(&app, request).await.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_held_write_lock() {
+        let state = SharedState::default();
+        let app = app(AppContext {
+            state: state.clone(),
+            config: Arc::new(Config {
+                lock_timeout: Duration::from_millis(50),
+                ..Config::default()
+            }),
+        });
+
+        let guard = state.read().await;
+        let request = with_json(Method::POST, "/properties/upsert", json!([]));
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(json(response).await["error"]["code"], "busy");
+
+        drop(guard);
+        let request = with_json(Method::POST, "/properties/upsert", json!([]));
+        assert_eq!(send(&app, request).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn creates_a_batch_of_properties() {
+        let app = sample_server(Config::default()).await;
+        let batch = json!([
+            input(json!({ "prefecture": "福岡県", "city": "福岡市" })),
+            input(json!({ "prefecture": "北海道", "city": "札幌市" })),
+        ]);
+
+        let response = send(
+            &app,
+            with_json(Method::POST, "/properties/batch", batch.clone()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(json(response).await, json!([3, 4]));
+
+        let property = json(send(&app, get("/properties/4")).await).await;
+        assert_eq!(property["city"], "札幌市");
+
+        // The same batch again is added again, rather than matched up
+        let response = send(
+            &app,
+            with_json(Method::POST, "/properties/batch", batch.clone()),
+        )
+        .await;
+        assert_eq!(json(response).await, json!([5, 6]));
+
+        let request = with_json(Method::POST, "/properties/batch?id_format=string", batch);
+        let response = send(&app, request).await;
+        assert_eq!(json(response).await, json!(["000007", "000008"]));
+    }
+
+    #[tokio::test]
+    async fn lists_properties_in_id_order() {
+        let app = sample_server(Config::default()).await;
+        let batch = Value::Array((0..10).map(|_| input(json!({}))).collect());
+        send(&app, with_json(Method::POST, "/properties/batch", batch)).await;
+        send(&app, delete("/properties/2")).await;
+
+        let list = json(send(&app, get("/properties")).await).await;
+        let expected: Vec<Value> = [1].into_iter().chain(3..=12).map(Value::from).collect();
+        assert_eq!(ids(&list), expected);
+    }
+
+    #[tokio::test]
+    async fn skips_rows_that_fail_the_sanity_checks_when_asked() {
+        let app = server(Config::default());
+        let file =
+            format!("{SAMPLE}京都府,京都市,,,,,,-100,,戸建,50\n北海道,札幌市,,,,,,100,,売地,0\n");
+
+        let response = send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+        assert_eq!(response.headers()["x-skipped-rows"], "0");
+
+        let request = upload("/properties/upload?validate=true", &[file.as_bytes()]);
+        let response = send(&app, request).await;
+        assert_eq!(response.headers()["x-skipped-rows"], "2");
+        assert_eq!(ids(&json(response).await), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn lists_the_cities_in_a_prefecture() {
+        let app = sample_server(Config::default()).await;
+        let batch = json!([
+            input(json!({ "prefecture": "東京都", "city": "新宿区" })),
+            input(json!({ "prefecture": "東京都", "city": "渋谷区" })),
+        ]);
+        send(&app, with_json(Method::POST, "/properties/batch", batch)).await;
+
+        let expected = json!([
+            { "city": "新宿区", "count": 1 },
+            { "city": "渋谷区", "count": 2 },
+        ]);
+        let cities = send(&app, get("/properties/prefectures/東京都/cities")).await;
+        assert_eq!(json(cities).await, expected);
+        let cities = send(&app, get("/properties/prefectures/tokyo/cities")).await;
+        assert_eq!(json(cities).await, expected);
+
+        let cities = send(&app, get("/properties/prefectures/福岡県/cities")).await;
+        assert_eq!(json(cities).await, json!([]));
+    }
+
+    #[tokio::test]
+    async fn uploads_a_file_without_a_trailing_newline() {
+        let app = server(Config::default());
+        let file = SAMPLE.trim_end().replace('\n', "\r\n");
+
+        let response = send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+        assert_eq!(response.headers()["x-skipped-rows"], "0");
+
+        let property = json(send(&app, get("/properties/2")).await).await;
+        assert_eq!(property["land_area"], "80");
+    }
+
+    #[tokio::test]
+    async fn reports_the_build() {
+        let app = server(Config::default());
+
+        let build = json(send(&app, get("/version")).await).await;
+        assert_eq!(build["version"], env!("CARGO_PKG_VERSION"));
+        assert!(!build["git_sha"].as_str().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn streams_the_properties_as_messagepack() {
+        let app = sample_server(Config::default()).await;
+
+        let response = send(&app, get("/properties/stream?prefecture=大阪府")).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            export::MSGPACK_CONTENT_TYPE
+        );
+
+        let bytes = body(response).await;
+        let property: Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(property["id"], 2);
+        assert_eq!(property["city"], "大阪市");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_last_row_for_a_repeated_id() {
+        let app = server(Config::default());
+        let file = "id,prefecture,city,town,chome,banchi,go,building,price,nearest_station,property_type,land_area\n\
+                    7,東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100\n\
+                    9,大阪府,大阪市,梅田,2,3,4,梅田ビル,5000万円,梅田,マンション,80\n\
+                    7,京都府,京都市,祇園,,,,,,,,\n";
+
+        let response = send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+        assert_eq!(response.headers()["x-skipped-rows"], "1");
+        assert_eq!(ids(&json(response).await), [7, 9]);
+
+        let property = json(send(&app, get("/properties/7")).await).await;
+        assert_eq!(property["city"], "京都市");
+
+        let request = upload("/properties/upload?strict=true", &[file.as_bytes()]);
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let error = json(response).await;
+        assert_eq!(error["error"]["code"], "invalid_rows");
+        assert_eq!(error["error"]["details"][0]["row"], 1);
+    }
+
+    #[tokio::test]
+    async fn stops_adding_properties_once_the_ids_run_out() {
+        let app = server(Config::default());
+        let file = format!(
+            "id,prefecture,city,town,chome,banchi,go,building,price,nearest_station,property_type,land_area\n\
+             {MAX_ID},東京都,渋谷区,神南,1,2,3,,1000万円,渋谷,土地,100\n\
+             {},大阪府,大阪市,梅田,2,3,4,梅田ビル,5000万円,梅田,マンション,80\n",
+            MAX_ID + 1
+        );
+
+        let response = send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+        assert_eq!(response.headers()["x-skipped-rows"], "1");
+        assert_eq!(ids(&json(response).await), [MAX_ID]);
+
+        let batch = json!([input(json!({ "prefecture": "福岡県" }))]);
+        for uri in ["/properties/batch", "/properties/upsert"] {
+            let response = send(&app, with_json(Method::POST, uri, batch.clone())).await;
+            assert_eq!(response.status(), StatusCode::CONFLICT);
+            assert_eq!(json(response).await["error"]["code"], "ids_exhausted");
+        }
+
+        let list = json(send(&app, get("/properties")).await).await;
+        assert_eq!(ids(&list), [MAX_ID]);
+    }
+
+    #[tokio::test]
+    async fn validates_a_file_without_storing_it() {
+        let app = server(Config::default());
+        let file = format!(
+            "{SAMPLE}京都府,京都市,,,,,,応相談,,戸建,50\n北海道,札幌市,,,,,,-100,,戸建,50\n"
+        );
+
+        let report = send(&app, upload("/properties/validate", &[file.as_bytes()])).await;
+        assert_eq!(
+            json(report).await,
+            json!({
+                "valid": false,
+                "rows": 4,
+                "errors": [{ "row": 4, "reason": "`price` is negative" }],
+                "warnings": [{ "row": 3, "reason": "`price` isn't a number we understand: `応相談`" }],
+            })
+        );
+
+        let list = json(send(&app, get("/properties")).await).await;
+        assert_eq!(list, json!([]));
+    }
+
+    #[tokio::test]
+    async fn canonicalizes_station_names_on_upload() {
+        let app = server(Config {
+            station_aliases: StationAliases::from_json(r#"{ "Shibuya": "渋谷" }"#).unwrap(),
+            ..Config::default()
+        });
+        let file = SAMPLE.replace(",渋谷,", ",shibuya,");
+
+        send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+
+        let property = json(send(&app, get("/properties/1")).await).await;
+        assert_eq!(property["nearest_station"], "渋谷");
+    }
+
+    #[tokio::test]
+    async fn reports_how_long_each_request_took() {
+        let app = server(Config::default());
+
+        for uri in ["/", "/properties", "/nowhere"] {
+            let response = send(&app, get(uri)).await;
+            let elapsed = response.headers()["x-response-time-ms"].to_str().unwrap();
+            assert!(elapsed.parse::<f64>().unwrap() >= 0.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_browsers_a_not_found_page() {
+        let app = server(Config::default());
+
+        let request = with_accept("/nowhere", "text/html,application/xhtml+xml,*/*;q=0.8");
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::VARY], "accept");
+        assert!(response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        assert_eq!(text(response).await, response::NOT_FOUND_PAGE);
+
+        let response = send(&app, get("/nowhere")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(json(response).await["error"]["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn counts_properties_by_prefecture_and_type() {
+        let app = sample_server(Config::default()).await;
+        let batch = json!([input(
+            json!({ "prefecture": "東京都", "property_type": "土地" })
+        )]);
+        send(&app, with_json(Method::POST, "/properties/batch", batch)).await;
+
+        let crosstab = json(send(&app, get("/properties/crosstab")).await).await;
+        assert_eq!(
+            crosstab,
+            json!({ "大阪府": { "マンション": 1 }, "東京都": { "土地": 2 } })
+        );
+
+        let crosstab = json(send(&app, get("/properties/crosstab?prefecture=大阪府")).await).await;
+        assert_eq!(crosstab, json!({ "大阪府": { "マンション": 1 } }));
+    }
+
+    #[tokio::test]
+    async fn reads_files_from_the_configured_field() {
+        let app = server(Config {
+            upload_field_name: "csv".to_string(),
+            ..Config::default()
+        });
+
+        // Fields with other names are ignored, including the default one
+        let request = form(
+            "/properties/upload",
+            &[
+                ("file", SAMPLE.as_bytes()),
+                ("csv", SAMPLE.lines().next().unwrap().as_bytes()),
+            ],
+        );
+        assert_eq!(json(send(&app, request).await).await, json!([]));
+
+        let request = form("/properties/upload", &[("csv", SAMPLE.as_bytes())]);
+        assert_eq!(ids(&json(send(&app, request).await).await), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn imports_each_csv_file_in_a_zip() {
+        let mut writer = ZipWriter::new(std::io::Cursor::new(vec![]));
+        let mut lines = SAMPLE.lines();
+        let header = lines.next().unwrap();
+        for (name, row) in ["tokyo.csv", "osaka.csv"].into_iter().zip(lines) {
+            writer
+                .start_file(name, SimpleFileOptions::default())
+                .unwrap();
+            writeln!(writer, "{header}\n{row}").unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+
+        let app = server(Config::default());
+        let response = send(&app, upload("/properties/upload", &[&data])).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // The ids carry on from one file to the next
+        assert_eq!(ids(&json(response).await), [1, 2]);
+
+        let app = server(Config {
+            max_archive_bytes: 100,
+            ..Config::default()
+        });
+        let response = send(&app, upload("/properties/upload", &[&data])).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(json(response).await["error"]["code"], "archive_too_large");
+    }
+
+    #[tokio::test]
+    async fn uploads_the_same_with_a_parse_pool() {
+        let rows: String = (1..=2000)
+            .map(|i| format!("東京都,渋谷区,神南,1,2,{i},,{i}万円,渋谷,マンション,50\n"))
+            .collect();
+        let file = &format!("{}\n{rows}", SAMPLE.lines().next().unwrap());
+
+        // Only the upload times can differ between the two
+        let uploaded = |app: Router| async move {
+            let response = send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+            let mut list = json(response).await;
+            for property in list.as_array_mut().unwrap() {
+                property.as_object_mut().unwrap().remove("updated_at");
+            }
+            list
+        };
+
+        let parallel = server(Config {
+            parse_threads: 4,
+            parse_pool: import::parse_pool(4).unwrap().map(Arc::new),
+            ..Config::default()
+        });
+        assert_eq!(
+            uploaded(parallel).await,
+            uploaded(server(Config::default())).await
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_just_the_ids() {
+        let app = sample_server(Config::default()).await;
+
+        let list = json(send(&app, get("/properties/ids")).await).await;
+        assert_eq!(list, json!([1, 2]));
+
+        let list = json(send(&app, get("/properties/ids?prefecture=大阪府")).await).await;
+        assert_eq!(list, json!([2]));
+
+        let list = json(send(&app, get("/properties/ids?id_format=string")).await).await;
+        assert_eq!(list, json!(["000001", "000002"]));
+    }
+
+    #[tokio::test]
+    async fn includes_the_raw_row_when_asked() {
+        // Raw rows aren't kept unless the operator turns them on
+        let app = sample_server(Config::default()).await;
+        let property = json(send(&app, get("/properties/2?include_raw=true")).await).await;
+        assert!(property["raw"].is_null());
+
+        let config = Config {
+            store_raw_rows: true,
+            ..Default::default()
+        };
+        let app = sample_server(config).await;
+
+        let property = json(send(&app, get("/properties/2")).await).await;
+        assert!(property.get("raw").is_none());
+
+        let property = json(send(&app, get("/properties/2?include_raw=true")).await).await;
+        assert_eq!(property["raw"], SAMPLE.lines().nth(2).unwrap());
+
+        // Properties that weren't imported from a file have no raw row
+        let batch = json!([input(json!({}))]);
+        send(&app, with_json(Method::POST, "/properties/batch", batch)).await;
+        let property = json(send(&app, get("/properties/3?include_raw=true")).await).await;
+        assert!(property["raw"].is_null());
+    }
+
+    fn delete(uri: &str) -> Request<Body> {
+        Request::delete(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn deletes_a_property() {
+        let app = sample_server(Config::default()).await;
+
+        assert_eq!(
+            send(&app, delete("/properties/1")).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            send(&app, get("/properties/1")).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let response = send(&app, get("/properties/1?include_deleted=true")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        assert_eq!(
+            send(&app, delete("/properties/1")).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn soft_deletes_a_property_when_configured() {
+        let app = sample_server(Config {
+            soft_delete: true,
+            ..Config::default()
+        })
+        .await;
+
+        assert_eq!(
+            send(&app, delete("/properties/1")).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            send(&app, get("/properties/1")).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(ids(&json(send(&app, get("/properties")).await).await), [2]);
+        // Deleting it again finds nothing to delete
+        assert_eq!(
+            send(&app, delete("/properties/1")).await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let property = json(send(&app, get("/properties/1?include_deleted=true")).await).await;
+        assert_eq!(property["city"], "渋谷区");
+        let list = json(send(&app, get("/properties?include_deleted=true")).await).await;
+        assert_eq!(ids(&list), [1, 2]);
+
+        // Replacing it brings it back
+        send(&app, with_json(Method::PUT, "/properties/1", shibuya())).await;
+        assert_eq!(
+            send(&app, get("/properties/1")).await.status(),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn uploads_with_another_quote_character() {
+        let app = server(Config::default());
+        let file = SAMPLE.replace(",梅田ビル,", ",'梅田ビル, 南館',");
+
+        let request = upload("/properties/upload?quote='", &[file.as_bytes()]);
+        send(&app, request).await;
+        let property = json(send(&app, get("/properties/2")).await).await;
+        assert_eq!(property["building"], "梅田ビル, 南館");
+
+        let request = upload("/properties/upload?quote=''", &[file.as_bytes()]);
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(response).await["error"]["code"], "invalid_dialect");
+    }
+
+    #[tokio::test]
+    async fn previews_the_first_rows_of_a_file() {
+        let app = server(Config {
+            max_rows: Some(5),
+            ..Config::default()
+        });
+        let rows: String = (1..=20)
+            .map(|i| format!("東京都,渋谷区,神南,1,2,{i},,{i}万円,渋谷,マンション,50\n"))
+            .collect();
+        let file = format!("{}\n{rows}", SAMPLE.lines().next().unwrap());
+
+        // A file too long to upload can still be previewed
+        let response = send(&app, upload("/properties/preview", &[file.as_bytes()])).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json(response).await.as_array().unwrap().len(), 10);
+
+        let request = upload("/properties/preview?rows=3", &[file.as_bytes()]);
+        let preview = json(send(&app, request).await).await;
+        assert_eq!(ids(&preview), [1, 2, 3]);
+        assert_eq!(preview[2]["go"], "3");
+
+        // Nothing was stored
+        assert_eq!(json(send(&app, get("/properties")).await).await, json!([]));
+    }
+
+    #[tokio::test]
+    async fn reports_when_the_data_last_changed() {
+        let app = server(Config {
+            timezone: "+09:00".parse().unwrap(),
+            ..Config::default()
+        });
+
+        let health = json(send(&app, get("/health")).await).await;
+        assert!(health["last_modified"].is_null());
+
+        send(&app, upload("/properties/upload", &[SAMPLE.as_bytes()])).await;
+        let health = json(send(&app, get("/health")).await).await;
+        assert!(health["last_modified"]
+            .as_str()
+            .unwrap()
+            .ends_with("+09:00"));
+    }
+
+    #[tokio::test]
+    async fn writes_every_timestamp_in_the_configured_timezone() {
+        let app = server(Config {
+            timezone: "+09:00".parse().unwrap(),
+            ..Config::default()
+        });
+        let in_japan = |time: &Value| time.as_str().unwrap().ends_with("+09:00");
+
+        let response = send(&app, upload("/properties/upload", &[SAMPLE.as_bytes()])).await;
+        assert!(in_japan(&json(response).await[0]["updated_at"]));
+
+        let response = send(&app, with_json(Method::PUT, "/properties/1", shibuya())).await;
+        assert!(in_japan(&json(response).await["updated_at"]));
+
+        let response = send(&app, get("/properties/stream")).await;
+        let property: Value = rmp_serde::from_slice(&body(response).await).unwrap();
+        assert!(in_japan(&property["updated_at"]));
+
+        let file = SAMPLE.replace("5000万円", "4500万円");
+        send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+        let diff = json(send(&app, get("/properties/diff")).await).await;
+        assert!(in_japan(&diff["changed"][0]["after"]["updated_at"]));
+    }
+
+    #[tokio::test]
+    async fn describes_where_a_page_is_in_the_list() {
+        let app = sample_server(Config::default()).await;
+
+        let page = json(send(&app, get("/properties?limit=1&offset=1")).await).await;
+        assert_eq!(ids(&page["data"]), [2]);
+        assert_eq!(
+            page["pagination"],
+            json!({ "total": 2, "limit": 1, "offset": 1, "has_more": false })
+        );
+        assert!(page.get("next_cursor").is_none());
+    }
+
+    #[tokio::test]
+    async fn uploads_a_tab_separated_file() {
+        let app = server(Config::default());
+        let file = SAMPLE.replace(',', "\t");
+
+        let response = send(&app, upload("/properties/upload", &[file.as_bytes()])).await;
+        assert_eq!(response.headers()["x-skipped-rows"], "0");
+
+        let property = json(send(&app, get("/properties/2")).await).await;
+        assert_eq!(property["building"], "梅田ビル");
+    }
+
+    #[tokio::test]
+    async fn finds_the_range_of_a_field() {
+        let app = sample_server(Config::default()).await;
+
+        let range = json(send(&app, get("/properties/range/price")).await).await;
+        assert_eq!(range, json!({ "min": 10_000_000, "max": 50_000_000 }));
+
+        let range = send(&app, get("/properties/range/land_area?prefecture=大阪府")).await;
+        assert_eq!(json(range).await, json!({ "min": 80.0, "max": 80.0 }));
+
+        let range = send(&app, get("/properties/range/price?prefecture=福岡県")).await;
+        assert_eq!(json(range).await, Value::Null);
+
+        let response = send(&app, get("/properties/range/rent")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(response).await["error"]["code"], "unknown_field");
+    }
+
+    #[tokio::test]
+    async fn masks_the_address_on_every_output() {
+        let app = sample_server(Config::default()).await;
+        let masked = "2,*,*,*";
+
+        let uri = "/properties?mask_address=true&columns=id,banchi,go,building";
+        let csv = text(send(&app, with_accept(uri, "text/csv")).await).await;
+        assert!(csv.contains(masked), "{csv}");
+
+        let uri = "/properties/export?mask_address=true&columns=id,banchi,go,building";
+        let csv = text(send(&app, get(uri)).await).await;
+        assert!(csv.contains(masked), "{csv}");
+
+        let request = with_json(Method::POST, uri, json!([2]));
+        let csv = text(send(&app, request).await).await;
+        assert!(csv.contains(masked), "{csv}");
+
+        let uri = "/properties/2/full_address?mask_address=true";
+        let address = text(send(&app, get(uri)).await).await;
+        assert_eq!(address, "大阪府大阪市梅田2丁目*番地*号*");
+
+        let uri = "/properties/stream?prefecture=大阪府&mask_address=true";
+        let bytes = body(send(&app, get(uri)).await).await;
+        let property: Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(property["banchi"], "*");
+        assert_eq!(property["building"], "*");
+    }
+
+    #[tokio::test]
+    async fn downloads_the_properties_as_json() {
+        let app = sample_server(Config::default()).await;
+
+        let response = send(&app, get("/properties/export/json?prefecture=大阪府")).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"properties.json\""
+        );
+
+        let download = json(response).await;
+        let list = json(send(&app, get("/properties?prefecture=大阪府")).await).await;
+        assert_eq!(download, list);
+    }
+
+    #[tokio::test]
+    async fn sees_changes_made_while_streaming_only_when_live() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("sample")
+            .join("japanese_properties.csv");
+        let file = std::fs::read(path).unwrap();
+
+        for (stream_mode, kept) in [(StreamMode::Live, false), (StreamMode::Snapshot, true)] {
+            let config = Config {
+                stream_mode,
+                ..Default::default()
+            };
+            let app = server(config);
+            send(&app, upload("/properties/upload", &[&file])).await;
+
+            // The property is deleted after the export started, before it's read
+            let response = send(&app, get("/properties/export?columns=id")).await;
+            send(&app, delete("/properties/4000")).await;
+
+            let csv = text(response).await;
+            assert_eq!(csv.lines().any(|line| line == "4000"), kept);
+            assert!(csv.lines().any(|line| line == "4001"));
+        }
+    }
+
+    #[tokio::test]
+    async fn responds_with_just_the_skipped_rows_when_asked() {
+        let app = server(Config::default());
+        let file = format!("{SAMPLE}京都府,京都市\n");
+
+        let request = upload("/properties/upload?only_errors=true", &[file.as_bytes()]);
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            json(response).await,
+            json!({ "skipped": [{ "row": 3, "reason": "expected at least 11 columns, found 2" }] })
+        );
+
+        // The rest of the file was still imported
+        assert_eq!(
+            ids(&json(send(&app, get("/properties")).await).await),
+            [1, 2]
+        );
+
+        // Even with nothing to import
+        let request = upload(
+            "/properties/upload?only_errors=true&if_empty=no_content",
+            &[b"prefecture\n"],
+        );
+        let report = json(send(&app, request).await).await;
+        assert_eq!(report, json!({ "skipped": [] }));
+    }
+
+    #[tokio::test]
+    async fn filters_by_the_canonical_type() {
+        let app = sample_server(Config::default()).await;
+
+        let list = json(send(&app, get("/properties?canonical_type=condo")).await).await;
+        assert_eq!(ids(&list), [2]);
+
+        let response = send(&app, get("/properties?canonical_type=castle")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn exports_the_selected_properties_in_order() {
+        let app = sample_server(Config::default()).await;
+
+        let request = with_json(
+            Method::POST,
+            "/properties/export?columns=id,city",
+            json!([2, 9, 1, 2]),
+        );
+        let response = send(&app, request).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], CSV_CONTENT_TYPE);
+        assert_eq!(text(response).await, "id,city\n2,大阪市\n1,渋谷区\n");
+
+        let request = with_json(Method::POST, "/properties/export?columns=rent", json!([1]));
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(response).await["error"]["code"], "unknown_column");
+    }
+
+    #[tokio::test]
+    async fn exports_the_selected_properties_as_they_were_when_asked_for() {
+        let app = sample_server(Config::default()).await;
+
+        let request = with_json(Method::POST, "/properties/export?columns=id", json!([2, 1]));
+        let response = send(&app, request).await;
+        send(&app, delete("/properties/2")).await;
+
+        assert_eq!(text(response).await, "id\n2\n1\n");
+    }
+
+    #[tokio::test]
+    async fn caches_lists_until_the_data_changes() {
+        let app = sample_server(Config {
+            list_cache_ttl: Duration::from_secs(60),
+            ..Config::default()
+        })
+        .await;
+
+        let response = send(&app, get("/properties?prefecture=東京都")).await;
+        assert_eq!(response.headers()["x-cache"], "MISS");
+        let response = send(&app, get("/properties?prefecture=東京都")).await;
+        assert_eq!(response.headers()["x-cache"], "HIT");
+        assert_eq!(ids(&json(response).await), [1]);
+
+        // A different format is cached on its own
+        let response = send(
+            &app,
+            with_accept("/properties?prefecture=東京都", "text/csv"),
+        )
+        .await;
+        assert_eq!(response.headers()["x-cache"], "MISS");
+
+        send(&app, delete("/properties/1")).await;
+        let response = send(&app, get("/properties?prefecture=東京都")).await;
+        assert_eq!(response.headers()["x-cache"], "MISS");
+        assert_eq!(json(response).await, json!([]));
+    }
+
+    #[tokio::test]
+    async fn finds_the_price_percentiles() {
+        let app = sample_server(Config::default()).await;
+
+        let uri = "/properties/price_percentiles?percentiles=50";
+        let percentiles = json(send(&app, get(uri)).await).await;
+        assert_eq!(
+            percentiles,
+            json!({
+                "大阪府": { "count": 1, "p50": 50_000_000 },
+                "東京都": { "count": 1, "p50": 10_000_000 },
+            })
+        );
+
+        let response = send(&app, get("/properties/price_percentiles?percentiles=abc")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(response).await["error"]["code"], "invalid_percentile");
+    }
+
+    #[tokio::test]
+    async fn lists_just_the_properties_changed_since_a_time() {
+        let app = sample_server(Config::default()).await;
+        // Anything that changes after this time is listed
+        let since = Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        let replacement = input(json!({}));
+        send(&app, with_json(Method::PUT, "/properties/2", replacement)).await;
+
+        let uri = format!("/properties/ids?updated_since={since}");
+        assert_eq!(json(send(&app, get(&uri)).await).await, json!([2]));
+
+        let response = send(&app, get("/properties?updated_since=yesterday")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn limits_the_number_of_groups() {
+        let app = sample_server(Config {
+            max_groups: 1,
+            ..Config::default()
+        })
+        .await;
+
+        // Even without a limit, there are never more than the maximum
+        let response = send(&app, get("/properties/by_station")).await;
+        assert_eq!(response.headers()["x-total-count"], "2");
+        assert_eq!(json(response).await.as_array().unwrap().len(), 1);
+
+        let limited = json(send(&app, get("/properties/by_station?limit=1")).await).await;
+        assert_eq!(limited["data"].as_array().unwrap().len(), 1);
+        assert_eq!(limited["total"], 2);
+        assert_eq!(limited["has_more"], true);
+        assert!(limited.get("warning").is_none());
+
+        let clamped = json(send(&app, get("/properties/by_station?limit=5")).await).await;
+        assert_eq!(clamped["data"].as_array().unwrap().len(), 1);
+        assert!(clamped["warning"]
+            .as_str()
+            .unwrap()
+            .contains("clamped to 1"));
+    }
+
+    #[tokio::test]
+    async fn caps_every_aggregation_even_without_a_limit() {
+        let app = sample_server(Config {
+            default_groups: 1,
+            ..Config::default()
+        })
+        .await;
+
+        let response = send(&app, get("/properties/by_station")).await;
+        assert_eq!(response.headers()["x-total-count"], "2");
+        assert_eq!(json(response).await.as_array().unwrap().len(), 1);
+
+        for uri in ["/properties/crosstab", "/properties/price_percentiles"] {
+            let response = send(&app, get(uri)).await;
+            assert_eq!(response.headers()["x-total-count"], "2");
+            let groups = json(response).await;
+            assert_eq!(groups.as_object().unwrap().len(), 1, "{uri}");
+            assert!(groups.get("大阪府").is_some(), "{uri}");
+
+            let limited = json(send(&app, get(&format!("{uri}?limit=2"))).await).await;
+            assert_eq!(limited["data"].as_object().unwrap().len(), 2, "{uri}");
+            assert_eq!(limited["has_more"], false, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn exports_fixed_width_text() {
+        let app = sample_server(Config::default()).await;
+
+        let response = send(
+            &app,
+            get("/properties/export/fixed?widths=id:2,prefecture:3"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(text(response).await, "1 東京都\n2 大阪府\n");
+
+        let uri = "/properties/export/fixed?widths=id:2,banchi:2,building:2&mask_address=true";
+        assert_eq!(text(send(&app, get(uri)).await).await, "1 *   \n2 * * \n");
+
+        for widths in ["id:0", ""] {
+            let uri = format!("/properties/export/fixed?widths={widths}");
+            let response = send(&app, get(&uri)).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(json(response).await["error"]["code"], "invalid_widths");
+        }
+    }
+
+    #[tokio::test]
+    async fn hides_the_hidden_types_from_the_list() {
+        let app = sample_server(Config {
+            hidden_types: vec![CanonicalType::Land],
+            ..Config::default()
+        })
+        .await;
+
+        let list = json(send(&app, get("/properties")).await).await;
+        assert_eq!(ids(&list), vec![2]);
+
+        let list = json(send(&app, get("/properties?include_hidden_types=true")).await).await;
+        assert_eq!(ids(&list), vec![1, 2]);
+
+        let list = json(send(&app, get("/properties?canonical_type=land")).await).await;
+        assert_eq!(ids(&list), vec![1]);
+
+        // So do the ids, exports, and statistics, with the same override
+        assert_eq!(
+            json(send(&app, get("/properties/ids")).await).await,
+            json!([2])
+        );
+        let csv = text(send(&app, get("/properties/export?columns=id")).await).await;
+        assert_eq!(csv, "id\n2\n");
+        let crosstab = json(send(&app, get("/properties/crosstab")).await).await;
+        assert_eq!(crosstab, json!({ "大阪府": { "マンション": 1 } }));
+
+        let uri = "/properties/ids?include_hidden_types=true";
+        assert_eq!(json(send(&app, get(uri)).await).await, json!([1, 2]));
+        let uri = "/properties/export?columns=id&include_hidden_types=true";
+        assert_eq!(text(send(&app, get(uri)).await).await, "id\n1\n2\n");
+
+        // Looking a property up by its id still finds it
+        let response = send(&app, get("/properties/1")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
 }
@@ -0,0 +1,205 @@
+//! Parsing the numeric fields, which we store as the strings we were given,
+//! and formatting them back out for display
+
+use serde::Deserialize;
+
+/// Parses a price in yen.
+///
+/// Commas, whitespace, and a trailing `円` are allowed,
+/// so `12,345,678円` parses the same as `12345678`.
+///
+/// Listings usually write prices with the 万 (10,000) and 億 (100,000,000)
+/// units, such as `5,480万円` or `1億2000万`, so those are understood too.
+pub fn parse_price(price: &str) -> Option<u64> {
+    let price = price.trim();
+    let price = price.strip_suffix('円').unwrap_or(price);
+
+    let digits: String = price
+        .chars()
+        .filter(|c| *c != ',' && !c.is_whitespace())
+        .collect();
+
+    let mut rest = digits.as_str();
+    let mut total: u64 = 0;
+    let mut has_units = false;
+
+    // The units always go from largest to smallest, like 1億2000万500
+    for (unit, multiplier) in [('億', 100_000_000), ('万', 10_000)] {
+        if let Some((amount, remainder)) = rest.split_once(unit) {
+            total = total.checked_add(parse_scaled(amount, multiplier)?)?;
+            rest = remainder;
+            has_units = true;
+        }
+    }
+
+    if rest.is_empty() && has_units {
+        return Some(total);
+    }
+
+    total.checked_add(rest.parse().ok()?)
+}
+
+/// Parses an amount of some unit, such as the `5480` in `5480万`.
+/// Decimals like `1.5億` are allowed, as long as they come out to a whole number of yen.
+fn parse_scaled(amount: &str, multiplier: u64) -> Option<u64> {
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+
+    if whole.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut value = whole.parse::<u64>().ok()?.checked_mul(multiplier)?;
+
+    if !fraction.is_empty() {
+        let scale = 10u64.checked_pow(fraction.len() as u32)?;
+        let fraction = fraction.parse::<u64>().ok()?.checked_mul(multiplier)?;
+
+        if fraction % scale != 0 {
+            return None;
+        }
+
+        value = value.checked_add(fraction / scale)?;
+    }
+
+    Some(value)
+}
+
+/// Parses an area in square meters.
+///
+/// Commas, whitespace, and a trailing unit of `㎡`, `m²`, or `m2` are allowed.
+///
+/// Listings often follow the area with its size in tsubo (坪), the traditional
+/// unit, like `120.5㎡（36.4坪）`. The tsubo are only there for reference,
+/// so we read the square meters and ignore the rest.
+pub fn parse_area(area: &str) -> Option<f64> {
+    let area = strip_tsubo(area.trim()).trim_end();
+    let area = ["㎡", "m²", "m2"]
+        .iter()
+        .find_map(|unit| area.strip_suffix(unit))
+        .unwrap_or(area);
+
+    let digits: String = area
+        .chars()
+        .filter(|c| *c != ',' && !c.is_whitespace())
+        .collect();
+
+    digits.parse().ok().filter(|area: &f64| area.is_finite())
+}
+
+/// Removes a trailing size in tsubo, in full-width or ASCII parentheses
+fn strip_tsubo(area: &str) -> &str {
+    [('（', '）'), ('(', ')')]
+        .iter()
+        .find_map(|&(open, close)| {
+            let (before, inside) = area.strip_suffix(close)?.rsplit_once(open)?;
+            inside.trim().ends_with('坪').then_some(before)
+        })
+        .unwrap_or(area)
+}
+
+/// How to group the digits of a formatted price
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PriceFormat {
+    /// In thousands, like `¥12,345,678`
+    Western,
+    /// With the 億 and 万 units, the way listings are written, like `¥1,234万5,678`
+    Japanese,
+}
+
+/// Formats a price in yen for display, such as `¥12,345,678`
+pub fn format_price(price: u64, format: PriceFormat) -> String {
+    match format {
+        PriceFormat::Western => format!("¥{}", group_thousands(price)),
+        PriceFormat::Japanese => {
+            let parts = [
+                (price / 100_000_000, "億"),
+                (price / 10_000 % 10_000, "万"),
+                (price % 10_000, ""),
+            ];
+
+            // Units with nothing in them are left out, so 5000万 isn't 5000万0
+            let formatted: String = parts
+                .iter()
+                .filter(|(amount, _)| *amount > 0)
+                .map(|(amount, unit)| format!("{}{unit}", group_thousands(*amount)))
+                .collect();
+
+            if formatted.is_empty() {
+                "¥0".to_string()
+            } else {
+                format!("¥{formatted}")
+            }
+        }
+    }
+}
+
+/// Writes a number with commas between each group of three digits
+fn group_thousands(number: u64) -> String {
+    let digits = number.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_prices_in_yen() {
+        assert_eq!(parse_price("12345678"), Some(12_345_678));
+        assert_eq!(parse_price(" 12,345,678円 "), Some(12_345_678));
+        assert_eq!(parse_price("応相談"), None);
+        assert_eq!(parse_price(""), None);
+    }
+
+    #[test]
+    fn parses_prices_with_units() {
+        assert_eq!(parse_price("5,480万円"), Some(54_800_000));
+        assert_eq!(parse_price("1億2000万"), Some(120_000_000));
+        assert_eq!(parse_price("1億2000万500円"), Some(120_000_500));
+        assert_eq!(parse_price("3億"), Some(300_000_000));
+        assert_eq!(parse_price("1.5億"), Some(150_000_000));
+        assert_eq!(parse_price("0.00001万"), None);
+        assert_eq!(parse_price("万"), None);
+        assert_eq!(parse_price("2000万1億"), None);
+        assert_eq!(parse_price("99999999999999億"), None);
+    }
+
+    #[test]
+    fn parses_areas_and_ignores_their_size_in_tsubo() {
+        assert_eq!(parse_area(" 1,200.5 ㎡"), Some(1200.5));
+        assert_eq!(parse_area("80m2"), Some(80.0));
+        assert_eq!(parse_area("120.5㎡（36.4坪）"), Some(120.5));
+        assert_eq!(parse_area("120.5m² (36.4 坪)"), Some(120.5));
+        // Anything else in parentheses isn't ours to ignore
+        assert_eq!(parse_area("120.5㎡（私道含む）"), None);
+        assert_eq!(parse_area("36.4坪"), None);
+    }
+
+    #[test]
+    fn formats_prices_for_display() {
+        assert_eq!(
+            format_price(12_345_678, PriceFormat::Western),
+            "¥12,345,678"
+        );
+        assert_eq!(format_price(0, PriceFormat::Western), "¥0");
+        assert_eq!(format_price(999, PriceFormat::Western), "¥999");
+
+        assert_eq!(
+            format_price(12_345_678, PriceFormat::Japanese),
+            "¥1,234万5,678"
+        );
+        assert_eq!(format_price(50_000_000, PriceFormat::Japanese), "¥5,000万");
+        assert_eq!(format_price(100_000_500, PriceFormat::Japanese), "¥1億500");
+        assert_eq!(format_price(0, PriceFormat::Japanese), "¥0");
+    }
+}
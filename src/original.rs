@@ -0,0 +1,141 @@
+//! Keeping the files from the last upload, so that they can be downloaded again
+
+use std::io::{self, Read, Write};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use crate::{archive, xlsx};
+
+/// The kinds of file that can be uploaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Csv,
+    Xlsx,
+    /// A ZIP archive of CSV files
+    Zip,
+}
+
+impl FileKind {
+    /// Works out the kind of file from its contents,
+    /// the same way the upload decides how to parse it
+    pub fn detect(data: &[u8]) -> Self {
+        if xlsx::is_workbook(data) {
+            FileKind::Xlsx
+        } else if archive::is_zip(data) {
+            FileKind::Zip
+        } else {
+            FileKind::Csv
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            FileKind::Csv => "text/csv; charset=utf-8",
+            FileKind::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            FileKind::Zip => "application/zip",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            FileKind::Csv => "csv",
+            FileKind::Xlsx => "xlsx",
+            FileKind::Zip => "zip",
+        }
+    }
+}
+
+/// An uploaded file, stored gzip-compressed to save memory.
+/// CSV files compress very well, so this is usually a fraction of the size.
+#[derive(Debug, Clone)]
+pub struct OriginalFile {
+    compressed: Vec<u8>,
+    kind: FileKind,
+}
+
+impl OriginalFile {
+    /// Compresses the file for storage
+    pub fn compress(data: &[u8]) -> io::Result<Self> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+
+        Ok(OriginalFile {
+            compressed: encoder.finish()?,
+            kind: FileKind::detect(data),
+        })
+    }
+
+    /// Decompresses the file, giving back exactly the bytes that were uploaded
+    pub fn decompress(&self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        GzDecoder::new(self.compressed.as_slice()).read_to_end(&mut data)?;
+
+        Ok(data)
+    }
+
+    pub fn kind(&self) -> FileKind {
+        self.kind
+    }
+
+    /// The media type of the file, based on what kind of file was uploaded
+    pub fn content_type(&self) -> &'static str {
+        self.kind.content_type()
+    }
+
+    /// A name to download the file with
+    pub fn filename(&self, index: usize) -> String {
+        let extension = self.kind.extension();
+
+        match index {
+            0 => format!("upload.{extension}"),
+            _ => format!("upload_{index}.{extension}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+
+    fn zip(name: &str) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        writer
+            .start_file(name, SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"a,b\n").unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn keeps_the_bytes_of_the_upload() {
+        let data = "id,prefecture\n1,東京都\n".as_bytes();
+        let original = OriginalFile::compress(data).unwrap();
+
+        assert_eq!(original.decompress().unwrap(), data);
+        assert_eq!(original.kind(), FileKind::Csv);
+        assert_eq!(original.content_type(), "text/csv; charset=utf-8");
+        assert_eq!(original.filename(0), "upload.csv");
+        assert_eq!(original.filename(2), "upload_2.csv");
+    }
+
+    #[test]
+    fn names_an_archive_as_a_zip() {
+        let original = OriginalFile::compress(&zip("tokyo.csv")).unwrap();
+
+        assert_eq!(original.kind(), FileKind::Zip);
+        assert_eq!(original.content_type(), "application/zip");
+        assert_eq!(original.filename(1), "upload_1.zip");
+    }
+
+    #[test]
+    fn names_a_workbook_as_xlsx() {
+        let original = OriginalFile::compress(&zip("xl/workbook.xml")).unwrap();
+
+        assert_eq!(original.kind(), FileKind::Xlsx);
+        assert_eq!(original.filename(0), "upload.xlsx");
+    }
+}
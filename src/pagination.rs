@@ -0,0 +1,287 @@
+//! Splitting long lists of properties into pages
+//!
+//! Clients can page through the list in two ways: with an `offset`, or with
+//! an `after` cursor. Offsets are simple, but if properties are added or
+//! removed between requests, the pages shift and results get skipped or
+//! repeated. Cursors avoid that, since each page picks up right after the
+//! last property of the previous page, no matter what changed in between.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, property::Property};
+
+/// The pagination parameters a client can send in the query string
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PageParams {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    /// The cursor from the previous page's `next_cursor`
+    pub after: Option<String>,
+}
+
+impl PageParams {
+    /// Lists are only paginated if the client asks for it,
+    /// so that existing clients keep getting the full list
+    pub fn is_paginated(&self) -> bool {
+        self.limit.is_some() || self.offset.is_some() || self.after.is_some()
+    }
+}
+
+/// A single page of results
+#[derive(Debug, Clone, Serialize)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub pagination: PageInfo,
+    /// Pass this as `after` to get the next page.
+    /// This is left out on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// Lets the client know if we had to adjust their request,
+    /// such as clamping a limit that was too large
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// Where a page is in the whole list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PageInfo {
+    /// How many items there are across every page
+    pub total: usize,
+    /// The most items this page could have, after clamping
+    pub limit: usize,
+    /// How many items in the list come before this page.
+    /// For cursors, this counts everything up to the cursor too.
+    pub offset: usize,
+    /// Whether there's another page after this one
+    pub has_more: bool,
+}
+
+impl<T> Page<T> {
+    /// Converts the items in the page, keeping everything else
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            data: self.data.into_iter().map(f).collect(),
+            pagination: self.pagination,
+            next_cursor: self.next_cursor,
+            warning: self.warning,
+        }
+    }
+}
+
+/// Takes one page out of the properties, using the configured page sizes.
+///
+/// The properties can be in any order. If the property that a cursor
+/// points to is removed, the cursor only still works for lists sorted by id.
+pub fn paginate<'a>(
+    properties: impl IntoIterator<Item = &'a Property>,
+    params: &PageParams,
+    config: &Config,
+) -> Page<&'a Property> {
+    let requested_limit = params.limit.unwrap_or(config.default_page_size);
+    // An empty page would say there's more without a cursor to get to it
+    let limit = requested_limit.min(config.max_page_size).max(1);
+
+    let mut warnings = vec![];
+
+    if limit < requested_limit {
+        warnings.push(format!(
+            "limit of {requested_limit} exceeds the maximum page size, so it was clamped to {limit}"
+        ));
+    } else if limit > requested_limit {
+        warnings.push(format!(
+            "limit of {requested_limit} is below the minimum page size, so it was raised to {limit}"
+        ));
+    }
+
+    // The cursor is just the id of the last property on the previous page,
+    // but clients shouldn't rely on that
+    let after = match params.after.as_deref().map(str::parse::<usize>) {
+        Some(Ok(after)) => Some(after),
+        Some(Err(_)) => {
+            warnings.push("the `after` cursor is invalid, so it was ignored".to_string());
+            None
+        }
+        None => None,
+    };
+
+    // When the list is sorted by id, everything up to the cursor is skipped,
+    // even if the property it points to has since been removed.
+    // In any other order, the page picks up right after that property.
+    let properties: Vec<&Property> = properties.into_iter().collect();
+    let start = match after {
+        Some(after) => properties
+            .iter()
+            .position(|property| property.id == after)
+            .map_or_else(
+                || properties.partition_point(|property| property.id <= after),
+                |position| position + 1,
+            ),
+        None => 0,
+    };
+
+    let total = properties.len();
+    let offset = start.saturating_add(params.offset.unwrap_or(0));
+
+    let mut remaining = properties.into_iter().skip(offset).peekable();
+
+    let data: Vec<_> = remaining.by_ref().take(limit).collect();
+    let has_more = remaining.peek().is_some();
+
+    let next_cursor = match (has_more, data.last()) {
+        (true, Some(last)) => Some(last.id.to_string()),
+        _ => None,
+    };
+
+    Page {
+        data,
+        pagination: PageInfo {
+            total,
+            limit,
+            offset,
+            has_more,
+        },
+        next_cursor,
+        warning: (!warnings.is_empty()).then(|| warnings.join("; ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    use super::*;
+
+    fn properties(ids: impl IntoIterator<Item = usize>) -> Vec<Property> {
+        ids.into_iter()
+            .map(|id| {
+                Property::from_input(
+                    id,
+                    PropertyInput::default(),
+                    &TypeMapping::default(),
+                    &StationAliases::default(),
+                )
+            })
+            .collect()
+    }
+
+    fn ids(page: &Page<&Property>) -> Vec<usize> {
+        page.data.iter().map(|property| property.id).collect()
+    }
+
+    fn config(default_page_size: usize, max_page_size: usize) -> Config {
+        Config {
+            default_page_size,
+            max_page_size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn uses_the_default_page_size_without_a_limit() {
+        let properties = properties(1..=10);
+        let params = PageParams {
+            offset: Some(2),
+            ..Default::default()
+        };
+
+        let page = paginate(&properties, &params, &config(3, 5));
+        assert_eq!(ids(&page), [3, 4, 5]);
+        assert_eq!(
+            page.pagination,
+            PageInfo {
+                total: 10,
+                limit: 3,
+                offset: 2,
+                has_more: true,
+            }
+        );
+        assert!(page.warning.is_none());
+    }
+
+    #[test]
+    fn clamps_limits_over_the_max_page_size() {
+        let properties = properties(1..=10);
+        let params = PageParams {
+            limit: Some(8),
+            ..Default::default()
+        };
+
+        let page = paginate(&properties, &params, &config(3, 5));
+        assert_eq!(ids(&page), [1, 2, 3, 4, 5]);
+        assert_eq!(page.pagination.limit, 5);
+        assert_eq!(
+            page.warning.as_deref(),
+            Some("limit of 8 exceeds the maximum page size, so it was clamped to 5")
+        );
+    }
+
+    #[test]
+    fn picks_up_after_the_cursor() {
+        let properties = properties([1, 2, 3, 5, 6]);
+        let config = config(2, 5);
+
+        let first = paginate(&properties, &PageParams::default(), &config);
+        assert_eq!(ids(&first), [1, 2]);
+        assert_eq!(first.next_cursor.as_deref(), Some("2"));
+
+        let after = |cursor: &str| PageParams {
+            after: Some(cursor.to_string()),
+            ..Default::default()
+        };
+
+        let second = paginate(&properties, &after("2"), &config);
+        assert_eq!(ids(&second), [3, 5]);
+        assert_eq!(second.pagination.offset, 2);
+
+        let last = paginate(&properties, &after("5"), &config);
+        assert_eq!(ids(&last), [6]);
+        assert!(!last.pagination.has_more);
+        assert!(last.next_cursor.is_none());
+    }
+
+    #[test]
+    fn still_works_when_the_cursor_property_is_gone() {
+        // Property 4 was on the end of the last page, but has since been removed
+        let properties = properties([1, 2, 3, 5, 6]);
+
+        let params = PageParams {
+            after: Some("4".to_string()),
+            ..Default::default()
+        };
+        let page = paginate(&properties, &params, &config(2, 5));
+        assert_eq!(ids(&page), [5, 6]);
+    }
+
+    #[test]
+    fn ignores_an_invalid_cursor() {
+        let properties = properties(1..=3);
+        let params = PageParams {
+            after: Some("abc".to_string()),
+            ..Default::default()
+        };
+
+        let page = paginate(&properties, &params, &config(2, 5));
+        assert_eq!(ids(&page), [1, 2]);
+        assert_eq!(
+            page.warning.as_deref(),
+            Some("the `after` cursor is invalid, so it was ignored")
+        );
+    }
+
+    #[test]
+    fn raises_a_limit_of_zero() {
+        let properties = properties(1..=3);
+        let params = PageParams {
+            limit: Some(0),
+            ..Default::default()
+        };
+
+        let page = paginate(&properties, &params, &config(3, 5));
+        assert_eq!(ids(&page), [1]);
+        assert_eq!(page.next_cursor.as_deref(), Some("1"));
+        assert_eq!(
+            page.warning.as_deref(),
+            Some("limit of 0 is below the minimum page size, so it was raised to 1")
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! A static table of the 47 prefectures of Japan
+
+/// A prefecture, along with its name written in romaji
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prefecture {
+    pub name: &'static str,
+    /// The romaji name, without the 都/府/県 suffix
+    pub romaji: &'static str,
+}
+
+/// All 47 prefectures, in the standard order used by the
+/// Japanese government (north to south)
+pub const PREFECTURES: [Prefecture; 47] = [
+    Prefecture {
+        name: "北海道",
+        romaji: "Hokkaido",
+    },
+    Prefecture {
+        name: "青森県",
+        romaji: "Aomori",
+    },
+    Prefecture {
+        name: "岩手県",
+        romaji: "Iwate",
+    },
+    Prefecture {
+        name: "宮城県",
+        romaji: "Miyagi",
+    },
+    Prefecture {
+        name: "秋田県",
+        romaji: "Akita",
+    },
+    Prefecture {
+        name: "山形県",
+        romaji: "Yamagata",
+    },
+    Prefecture {
+        name: "福島県",
+        romaji: "Fukushima",
+    },
+    Prefecture {
+        name: "茨城県",
+        romaji: "Ibaraki",
+    },
+    Prefecture {
+        name: "栃木県",
+        romaji: "Tochigi",
+    },
+    Prefecture {
+        name: "群馬県",
+        romaji: "Gunma",
+    },
+    Prefecture {
+        name: "埼玉県",
+        romaji: "Saitama",
+    },
+    Prefecture {
+        name: "千葉県",
+        romaji: "Chiba",
+    },
+    Prefecture {
+        name: "東京都",
+        romaji: "Tokyo",
+    },
+    Prefecture {
+        name: "神奈川県",
+        romaji: "Kanagawa",
+    },
+    Prefecture {
+        name: "新潟県",
+        romaji: "Niigata",
+    },
+    Prefecture {
+        name: "富山県",
+        romaji: "Toyama",
+    },
+    Prefecture {
+        name: "石川県",
+        romaji: "Ishikawa",
+    },
+    Prefecture {
+        name: "福井県",
+        romaji: "Fukui",
+    },
+    Prefecture {
+        name: "山梨県",
+        romaji: "Yamanashi",
+    },
+    Prefecture {
+        name: "長野県",
+        romaji: "Nagano",
+    },
+    Prefecture {
+        name: "岐阜県",
+        romaji: "Gifu",
+    },
+    Prefecture {
+        name: "静岡県",
+        romaji: "Shizuoka",
+    },
+    Prefecture {
+        name: "愛知県",
+        romaji: "Aichi",
+    },
+    Prefecture {
+        name: "三重県",
+        romaji: "Mie",
+    },
+    Prefecture {
+        name: "滋賀県",
+        romaji: "Shiga",
+    },
+    Prefecture {
+        name: "京都府",
+        romaji: "Kyoto",
+    },
+    Prefecture {
+        name: "大阪府",
+        romaji: "Osaka",
+    },
+    Prefecture {
+        name: "兵庫県",
+        romaji: "Hyogo",
+    },
+    Prefecture {
+        name: "奈良県",
+        romaji: "Nara",
+    },
+    Prefecture {
+        name: "和歌山県",
+        romaji: "Wakayama",
+    },
+    Prefecture {
+        name: "鳥取県",
+        romaji: "Tottori",
+    },
+    Prefecture {
+        name: "島根県",
+        romaji: "Shimane",
+    },
+    Prefecture {
+        name: "岡山県",
+        romaji: "Okayama",
+    },
+    Prefecture {
+        name: "広島県",
+        romaji: "Hiroshima",
+    },
+    Prefecture {
+        name: "山口県",
+        romaji: "Yamaguchi",
+    },
+    Prefecture {
+        name: "徳島県",
+        romaji: "Tokushima",
+    },
+    Prefecture {
+        name: "香川県",
+        romaji: "Kagawa",
+    },
+    Prefecture {
+        name: "愛媛県",
+        romaji: "Ehime",
+    },
+    Prefecture {
+        name: "高知県",
+        romaji: "Kochi",
+    },
+    Prefecture {
+        name: "福岡県",
+        romaji: "Fukuoka",
+    },
+    Prefecture {
+        name: "佐賀県",
+        romaji: "Saga",
+    },
+    Prefecture {
+        name: "長崎県",
+        romaji: "Nagasaki",
+    },
+    Prefecture {
+        name: "熊本県",
+        romaji: "Kumamoto",
+    },
+    Prefecture {
+        name: "大分県",
+        romaji: "Oita",
+    },
+    Prefecture {
+        name: "宮崎県",
+        romaji: "Miyazaki",
+    },
+    Prefecture {
+        name: "鹿児島県",
+        romaji: "Kagoshima",
+    },
+    Prefecture {
+        name: "沖縄県",
+        romaji: "Okinawa",
+    },
+];
+
+impl Prefecture {
+    /// The romaji for the prefecture's suffix, such as `ken` for 県.
+    /// Hokkaido's 道 is already part of its name, so it has none.
+    pub fn romaji_suffix(&self) -> &'static str {
+        match self.name.chars().last() {
+            Some('都') => "to",
+            Some('府') => "fu",
+            Some('県') => "ken",
+            _ => "",
+        }
+    }
+}
+
+/// Finds the prefecture that the given string starts with, if any
+pub fn strip_prefecture(s: &str) -> Option<(&'static str, &str)> {
+    PREFECTURES
+        .iter()
+        .find_map(|prefecture| Some((prefecture.name, s.strip_prefix(prefecture.name)?)))
+}
+
+/// Looks up a prefecture by its name, such as 東京都
+pub fn find_by_name(name: &str) -> Option<&'static Prefecture> {
+    PREFECTURES
+        .iter()
+        .find(|prefecture| prefecture.name == name)
+}
+
+/// Looks up a prefecture by its romaji name.
+///
+/// This is forgiving about how the name is written, so `tokyo`, `Tokyo`,
+/// `tokyo-to`, and `Tokyo To` all find 東京都.
+pub fn find_by_romaji(romaji: &str) -> Option<&'static Prefecture> {
+    let query: String = romaji
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    PREFECTURES.iter().find(|prefecture| {
+        let name = prefecture.romaji.to_ascii_lowercase();
+        query == name || query == format!("{name}{}", prefecture.romaji_suffix())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_prefectures_by_romaji() {
+        for romaji in ["tokyo", "Tokyo", "tokyo-to", "Tokyo To", "TOKYOTO"] {
+            assert_eq!(
+                find_by_romaji(romaji).map(|p| p.name),
+                Some("東京都"),
+                "{romaji}"
+            );
+        }
+        assert_eq!(find_by_romaji("osaka fu").map(|p| p.name), Some("大阪府"));
+        assert_eq!(find_by_romaji("hokkaido").map(|p| p.name), Some("北海道"));
+        assert!(find_by_romaji("tokyo-ken").is_none());
+        assert!(find_by_romaji("edo").is_none());
+    }
+}
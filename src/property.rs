@@ -1,13 +1,39 @@
 //! A data type to represent Japanese real estate properties
 
-use serde::{ser::SerializeStruct, Serialize};
+use std::{borrow::Cow, collections::BTreeMap};
+
+use chrono::{DateTime, Utc};
+use serde::{ser::SerializeStruct, Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    formatter::{self, AddressFormat},
+    numbers::{self, PriceFormat},
+    prefecture,
+    property_type::{CanonicalType, TypeMapping},
+    station::StationAliases,
+    timestamp::Timezone,
+};
 
 // TODO: using Strings is pretty safe, and avoids plenty of issues when
 // we're only worried about converting between CSV and JSON data.
 // However, it's likely using more memory than really necessary, so we
 // should consider downsizing a bit, such as by using raw Bytes.
 
-#[derive(Debug, Clone)]
+/// Stores properties by their id.
+///
+/// A BTreeMap keeps the properties in id order, so lists and exports
+/// come out in a consistent order without having to sort them every time.
+pub type Db = BTreeMap<usize, Property>;
+
+/// The largest id a property can have. Ids can come from uploaded files,
+/// so they're kept well below `usize::MAX`, where counting up to the next
+/// id would overflow.
+pub const MAX_ID: usize = u32::MAX as usize;
+
+// Deserialize is derived so that we can read our own JSON output back in.
+// The computed full_address field is simply ignored.
+#[derive(Debug, Clone, Deserialize)]
 pub struct Property {
     pub id: usize,
     pub prefecture: String,
@@ -20,50 +46,825 @@ pub struct Property {
     pub price: String,
     pub nearest_station: String,
     pub property_type: String,
+    /// The kind of property that `property_type` was mapped to on import
+    #[serde(default)]
+    pub canonical_type: CanonicalType,
     pub land_area: String,
+    /// False if the row this came from was missing some columns,
+    /// in which case those fields are left empty
+    #[serde(default = "default_true")]
+    pub complete: bool,
+    /// The coordinates of the property, if it has been geocoded
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    /// The row of the file this was imported from, as it was written,
+    /// for tracking down why a row was parsed the way it was
+    #[serde(default)]
+    pub raw: Option<String>,
+    /// Set when the property was deleted with soft deletes turned on.
+    /// It stays in the db, but is left out of lists unless asked for.
+    #[serde(default)]
+    pub deleted: bool,
+    /// When the property was last uploaded, added, or changed, so that
+    /// clients can sync just the changes. Properties from the seed file
+    /// don't have one, since they were there from the start.
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// The user-editable fields of a property, as received in a request body.
+/// Any field that's missing from the body defaults to an empty string.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PropertyInput {
+    pub prefecture: String,
+    pub city: String,
+    pub town: String,
+    pub chome: String,
+    pub banchi: String,
+    pub go: String,
+    pub building: String,
+    pub price: String,
+    pub nearest_station: String,
+    pub property_type: String,
+    pub land_area: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+impl Property {
+    /// Builds a property with the given id out of the user-supplied fields,
+    /// mapping its type with `types` and its station with `aliases`,
+    /// the same way as for an imported row
+    pub fn from_input(
+        id: usize,
+        input: PropertyInput,
+        types: &TypeMapping,
+        aliases: &StationAliases,
+    ) -> Self {
+        Property {
+            id,
+            prefecture: input.prefecture,
+            city: input.city,
+            town: input.town,
+            chome: input.chome,
+            banchi: input.banchi,
+            go: input.go,
+            building: input.building,
+            price: input.price,
+            nearest_station: aliases.canonicalize(&input.nearest_station).to_string(),
+            canonical_type: types.canonicalize(&input.property_type),
+            property_type: input.property_type,
+            land_area: input.land_area,
+            complete: true,
+            latitude: input.latitude,
+            longitude: input.longitude,
+            raw: None,
+            deleted: false,
+            updated_at: Some(Utc::now()),
+        }
+    }
+
+    /// The price in yen, if it can be parsed
+    pub fn price_value(&self) -> Option<u64> {
+        numbers::parse_price(&self.price)
+    }
+
+    /// The land area in square meters, if it can be parsed
+    pub fn land_area_value(&self) -> Option<f64> {
+        numbers::parse_area(&self.land_area)
+    }
+
+    /// The price per square meter of land, if both can be parsed
+    /// and the area isn't zero
+    pub fn price_per_sqm(&self) -> Option<f64> {
+        let area = self.land_area_value().filter(|area| *area > 0.0)?;
+        Some(self.price_value()? as f64 / area)
+    }
+
+    /// The latitude and longitude, if the property has both
+    pub fn coordinates(&self) -> Option<(f64, f64)> {
+        self.latitude.zip(self.longitude)
+    }
+
+    /// The address fields, which together identify a property
+    /// regardless of its id
+    pub fn address_key(&self) -> [&str; 7] {
+        [
+            &self.prefecture,
+            &self.city,
+            &self.town,
+            &self.chome,
+            &self.banchi,
+            &self.go,
+            &self.building,
+        ]
+    }
+
+    /// All of the data fields, leaving out the id.
+    ///
+    /// Ids are just row numbers, so two properties with the same content key
+    /// describe the same listing, even if they came from different uploads.
+    /// Features that compare properties by value should use this,
+    /// so that they all agree on what counts as the same property.
+    pub fn content_key(&self) -> [&str; 11] {
+        [
+            &self.prefecture,
+            &self.city,
+            &self.town,
+            &self.chome,
+            &self.banchi,
+            &self.go,
+            &self.building,
+            &self.price,
+            &self.nearest_station,
+            &self.property_type,
+            &self.land_area,
+        ]
+    }
+
+    /// Formats the address fields into a single string.
+    ///
+    /// This is the formal way to display Japanese addresses, though
+    /// there are several other formats that could have been used.
+    /// See [`Property::full_address_with`] for those.
+    pub fn full_address(&self) -> String {
+        self.full_address_with(AddressFormat::FORMAL)
+    }
+
+    /// Formats the address fields into a single string,
+    /// using one of the registered address formats
+    pub fn full_address_with(&self, format: AddressFormat) -> String {
+        format.format(self)
+    }
+
+    /// Formats the address in Western order, from the most specific part
+    /// to the least, such as `国立競技場, 4-16-12 日本橋, 中央区, Tokyo`.
+    ///
+    /// The prefecture is written in romaji. We don't have romaji for the
+    /// other parts yet, so they're left as they are.
+    pub fn full_address_en(&self) -> String {
+        let block = formatter::block_numbers(self)
+            .into_iter()
+            .filter(|number| !number.is_empty())
+            .collect::<Vec<_>>()
+            .join("-");
+
+        let street = [block.as_str(), &self.town]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let prefecture = prefecture::find_by_name(&self.prefecture)
+            .map(|prefecture| prefecture.romaji)
+            .unwrap_or(&self.prefecture);
+
+        [&self.building, street.as_str(), &self.city, prefecture]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Options for how a property is serialized in a response.
+/// These can be deserialized directly from a request's query string.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ViewOptions {
+    /// Whether to include the computed full_address field
+    #[serde(default = "default_true")]
+    pub include_full_address: bool,
+    /// How to write the block numbers in the full_address field
+    #[serde(default)]
+    pub full_address_format: AddressFormat,
+    /// Whether to include the address in Western order, for international
+    /// listings. This is off by default to keep responses small.
+    #[serde(default)]
+    pub include_full_address_en: bool,
+    /// Whether to send the id as a number or as a padded string
+    #[serde(default)]
+    pub id_format: IdFormat,
+    /// What to send for the numeric `*_value` fields when the
+    /// price or land area can't be parsed
+    #[serde(default)]
+    pub missing_values: MissingValues,
+    /// Whether field names are written in snake_case or camelCase
+    #[serde(default)]
+    pub field_naming: FieldNaming,
+    /// How to write the price for display, in the price_formatted field.
+    /// The field is left out unless this is set.
+    pub price_format: Option<PriceFormat>,
+    /// Whether to include the raw row each property was imported from
+    #[serde(default)]
+    pub include_raw: bool,
+    /// Whether to hide the parts of the address that pinpoint the property,
+    /// for public views of privacy-sensitive listings
+    #[serde(default)]
+    pub mask_address: bool,
+    /// Whether to take leading zeros off of the chome, banchi, and go,
+    /// like `03` to `3`. They're kept as they were imported by default.
+    #[serde(default)]
+    pub strip_leading_zeros: bool,
+    /// The timezone that updated_at is written in.
+    /// This comes from the config, rather than the query.
+    #[serde(skip)]
+    pub timezone: Timezone,
+}
+
+impl ViewOptions {
+    /// The options the operator configured, for responses that
+    /// don't take any from the query string
+    pub fn from_config(config: &Config) -> Self {
+        ViewOptions::default().with_config(config)
+    }
+
+    /// Fills in the options that come from the config, rather than the query
+    pub fn with_config(self, config: &Config) -> Self {
+        ViewOptions {
+            timezone: config.timezone,
+            ..self
+        }
+    }
+}
+
+impl Default for ViewOptions {
+    fn default() -> Self {
+        ViewOptions {
+            include_full_address: true,
+            full_address_format: AddressFormat::default(),
+            include_full_address_en: false,
+            id_format: IdFormat::default(),
+            missing_values: MissingValues::default(),
+            field_naming: FieldNaming::default(),
+            price_format: None,
+            include_raw: false,
+            mask_address: false,
+            strip_leading_zeros: false,
+            timezone: Timezone::UTC,
+        }
+    }
+}
+
+/// How the id of a property is serialized.
+/// Either way, ids are stored as numbers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdFormat {
+    /// A plain number, like `1`
+    #[default]
+    Integer,
+    /// A zero-padded string, like `"000001"`, for clients that
+    /// expect ids to be opaque
+    String,
+}
+
+/// The number of digits that string ids are padded to
+pub const ID_WIDTH: usize = 6;
+
+impl IdFormat {
+    /// The id, ready to be serialized in this format
+    pub fn id(self, id: usize) -> FormattedId {
+        FormattedId { id, format: self }
+    }
+}
+
+/// An id that's serialized in the format the client asked for,
+/// for responses that have ids without the rest of their properties
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormattedId {
+    pub id: usize,
+    pub format: IdFormat,
+}
+
+impl Serialize for FormattedId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.format {
+            IdFormat::Integer => self.id.serialize(serializer),
+            IdFormat::String => {
+                serializer.collect_str(&format_args!("{:0width$}", self.id, width = ID_WIDTH))
+            }
+        }
+    }
+}
+
+/// How the numeric `price_value` and `land_area_value` fields are
+/// serialized when the original string can't be parsed as a number
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingValues {
+    /// Send the field as `null`
+    #[default]
+    Null,
+    /// Leave the field out entirely
+    Omit,
+    /// Send the field as `0`, for clients that can't handle nulls.
+    /// Beware that this can't be told apart from a real zero.
+    Zero,
+}
+
+/// How the field names of a property are written.
+/// This only covers the property's own fields, and not the rest of the
+/// response around it, like a page's `next_cursor`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldNaming {
+    /// Like `nearest_station`
+    #[default]
+    Snake,
+    /// Like `nearestStation`, which JavaScript clients tend to prefer
+    Camel,
+}
+
+impl FieldNaming {
+    /// Converts one of our snake_case field names to this naming.
+    /// Serialized field names need to be static, so the camelCase
+    /// names are listed out rather than computed.
+    pub fn name(self, field: &'static str) -> &'static str {
+        if self == FieldNaming::Snake {
+            return field;
+        }
+
+        match field {
+            "full_address" => "fullAddress",
+            "full_address_en" => "fullAddressEn",
+            "nearest_station" => "nearestStation",
+            "property_type" => "propertyType",
+            "canonical_type" => "canonicalType",
+            "land_area" => "landArea",
+            "price_value" => "priceValue",
+            "price_formatted" => "priceFormatted",
+            "land_area_value" => "landAreaValue",
+            "updated_at" => "updatedAt",
+            _ => field,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A property along with the options to serialize it with
+#[derive(Debug, Clone, Copy)]
+pub struct PropertyView<'a> {
+    pub property: &'a Property,
+    pub options: &'a ViewOptions,
+}
+
+impl Property {
+    /// Pairs the property with options for serializing it
+    pub fn view<'a>(&'a self, options: &'a ViewOptions) -> PropertyView<'a> {
+        PropertyView {
+            property: self,
+            options,
+        }
+    }
+}
+
+// By default, a property is serialized with all of its fields
+impl Serialize for Property {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.view(&ViewOptions::default()).serialize(serializer)
+    }
+}
+
+/// What masked address parts are replaced with
+pub const MASK: &str = "*";
+
+impl Property {
+    /// A copy of the property with the banchi, go, and building masked,
+    /// so that only the area it's in can be seen. The coordinates and raw row
+    /// would give the exact location away too, so they're left out.
+    pub fn masked(&self) -> Property {
+        // Empty parts stay empty, so they don't look like they were set
+        let mask = |part: &str| {
+            if part.is_empty() {
+                String::new()
+            } else {
+                MASK.to_string()
+            }
+        };
+
+        Property {
+            banchi: mask(&self.banchi),
+            go: mask(&self.go),
+            building: mask(&self.building),
+            latitude: None,
+            longitude: None,
+            raw: None,
+            ..self.clone()
+        }
+    }
+
+    /// The property masked like [`Property::masked`] if `mask` is set,
+    /// or as it is otherwise
+    pub fn masked_if(&self, mask: bool) -> Cow<'_, Property> {
+        if mask {
+            Cow::Owned(self.masked())
+        } else {
+            Cow::Borrowed(self)
+        }
+    }
+
+    /// A copy of the property with the leading zeros taken off of the
+    /// chome, banchi, and go
+    pub fn without_leading_zeros(&self) -> Property {
+        let strip = |number: &str| formatter::strip_leading_zeros(number).to_string();
+
+        Property {
+            chome: strip(&self.chome),
+            banchi: strip(&self.banchi),
+            go: strip(&self.go),
+            ..self.clone()
+        }
+    }
+}
+
+impl PropertyView<'_> {
+    /// How many fields will be serialized, after the skipped ones are left out.
+    /// JSON doesn't care, but formats like MessagePack write this count
+    /// before the fields, so it needs to be exact.
+    fn field_count(&self, property: &Property) -> usize {
+        let omit_missing = self.options.missing_values == MissingValues::Omit;
+
+        // The id, the address parts, and the rest of the original columns
+        let always = 13;
+
+        let optional = [
+            self.options.include_full_address,
+            self.options.include_full_address_en,
+            !(omit_missing && property.price_value().is_none()),
+            !(omit_missing && property.land_area_value().is_none()),
+            self.options.price_format.is_some()
+                && !(omit_missing && property.price_value().is_none()),
+            property.latitude.is_some(),
+            property.longitude.is_some(),
+            !property.complete,
+            self.options.include_raw,
+            property.deleted,
+            property.updated_at.is_some(),
+        ];
+
+        always + optional.into_iter().filter(|&included| included).count()
+    }
 }
 
 // We add a custom implementation of Serialize so that we
 // can add the full_address property to the JSON
-impl Serialize for Property {
+impl Serialize for PropertyView<'_> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        let mut s = serializer.serialize_struct("Property", 13)?;
-        s.serialize_field("id", &self.id)?;
+        let mut property = self.property.masked_if(self.options.mask_address);
+
+        if self.options.strip_leading_zeros {
+            property = Cow::Owned(property.without_leading_zeros());
+        }
+
+        let property = &*property;
+        let name = |field| self.options.field_naming.name(field);
+
+        let mut s = serializer.serialize_struct("Property", self.field_count(property))?;
+        s.serialize_field(name("id"), &self.options.id_format.id(property.id))?;
 
         // Here's our lovely custom field
-        // This is the formal way to display Japanese addresses, though
-        // there are a couple of other variations that could have been used.
-        // For example, the chome, banchi, and go fields are sometimes displayed
-        // as 1-2-3, or 1丁目1-2, etc.
-        s.serialize_field(
-            "full_address",
-            &format!(
-                "{}{}{}{}丁目{}番地{}号{}",
-                &self.prefecture,
-                &self.city,
-                &self.town,
-                &self.chome,
-                &self.banchi,
-                &self.go,
-                &self.building,
-            ),
-        )?;
-
-        s.serialize_field("prefecture", &self.prefecture)?;
-        s.serialize_field("city", &self.city)?;
-        s.serialize_field("town", &self.town)?;
-        s.serialize_field("chome", &self.chome)?;
-        s.serialize_field("banchi", &self.banchi)?;
-        s.serialize_field("go", &self.go)?;
-        s.serialize_field("building", &self.building)?;
-        s.serialize_field("price", &self.price)?;
-        s.serialize_field("nearest_station", &self.nearest_station)?;
-        s.serialize_field("property_type", &self.property_type)?;
-        s.serialize_field("land_area", &self.land_area)?;
+        // Clients that don't need it can turn it off to save some bytes
+        if self.options.include_full_address {
+            let full_address = property.full_address_with(self.options.full_address_format);
+            s.serialize_field(name("full_address"), &full_address)?;
+        } else {
+            s.skip_field(name("full_address"))?;
+        }
+
+        if self.options.include_full_address_en {
+            s.serialize_field(name("full_address_en"), &property.full_address_en())?;
+        } else {
+            s.skip_field(name("full_address_en"))?;
+        }
+
+        s.serialize_field(name("prefecture"), &property.prefecture)?;
+        s.serialize_field(name("city"), &property.city)?;
+        s.serialize_field(name("town"), &property.town)?;
+        s.serialize_field(name("chome"), &property.chome)?;
+        s.serialize_field(name("banchi"), &property.banchi)?;
+        s.serialize_field(name("go"), &property.go)?;
+        s.serialize_field(name("building"), &property.building)?;
+        s.serialize_field(name("price"), &property.price)?;
+        s.serialize_field(name("nearest_station"), &property.nearest_station)?;
+        s.serialize_field(name("property_type"), &property.property_type)?;
+        s.serialize_field(name("canonical_type"), &property.canonical_type)?;
+        s.serialize_field(name("land_area"), &property.land_area)?;
+
+        // The parsed numbers, so that clients don't each need their own parser
+        match (property.price_value(), self.options.missing_values) {
+            (Some(price), _) => s.serialize_field(name("price_value"), &price)?,
+            (None, MissingValues::Null) => s.serialize_field(name("price_value"), &None::<u64>)?,
+            (None, MissingValues::Omit) => s.skip_field(name("price_value"))?,
+            (None, MissingValues::Zero) => s.serialize_field(name("price_value"), &0)?,
+        }
+
+        match (property.land_area_value(), self.options.missing_values) {
+            (Some(area), _) => s.serialize_field(name("land_area_value"), &area)?,
+            (None, MissingValues::Null) => {
+                s.serialize_field(name("land_area_value"), &None::<f64>)?
+            }
+            (None, MissingValues::Omit) => s.skip_field(name("land_area_value"))?,
+            (None, MissingValues::Zero) => s.serialize_field(name("land_area_value"), &0.0)?,
+        }
+
+        // Formatted for display, for clients that asked for it
+        if let Some(format) = self.options.price_format {
+            let formatted = property
+                .price_value()
+                .map(|price| numbers::format_price(price, format));
+
+            match (formatted, self.options.missing_values) {
+                (Some(formatted), _) => s.serialize_field(name("price_formatted"), &formatted)?,
+                (None, MissingValues::Null) => {
+                    s.serialize_field(name("price_formatted"), &None::<String>)?
+                }
+                (None, MissingValues::Omit) => s.skip_field(name("price_formatted"))?,
+                (None, MissingValues::Zero) => {
+                    s.serialize_field(name("price_formatted"), &numbers::format_price(0, format))?
+                }
+            }
+        } else {
+            s.skip_field(name("price_formatted"))?;
+        }
+
+        // Most properties haven't been geocoded,
+        // so we leave the coordinates out when they're missing
+        match property.latitude {
+            Some(latitude) => s.serialize_field(name("latitude"), &latitude)?,
+            None => s.skip_field(name("latitude"))?,
+        }
+
+        match property.longitude {
+            Some(longitude) => s.serialize_field(name("longitude"), &longitude)?,
+            None => s.skip_field(name("longitude"))?,
+        }
+
+        // Only incomplete properties are flagged, so that the
+        // common case doesn't need the extra bytes
+        if property.complete {
+            s.skip_field(name("complete"))?;
+        } else {
+            s.serialize_field(name("complete"), &property.complete)?;
+        }
+
+        // Like incomplete properties, only deleted ones are flagged
+        if property.deleted {
+            s.serialize_field(name("deleted"), &property.deleted)?;
+        } else {
+            s.skip_field(name("deleted"))?;
+        }
+
+        match property.updated_at {
+            Some(updated_at) => s.serialize_field(
+                name("updated_at"),
+                &self.options.timezone.format(updated_at),
+            )?,
+            None => s.skip_field(name("updated_at"))?,
+        }
+
+        // This is `null` for properties that weren't imported from a file
+        if self.options.include_raw {
+            s.serialize_field(name("raw"), &property.raw)?;
+        } else {
+            s.skip_field(name("raw"))?;
+        }
 
         s.end()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn writes_updated_at_in_the_timezone() {
+        let mut property = Property::from_input(
+            1,
+            PropertyInput::default(),
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        );
+        property.updated_at = Some(Utc.with_ymd_and_hms(2024, 6, 1, 0, 30, 0).unwrap());
+
+        let options = ViewOptions {
+            timezone: "+09:00".parse().unwrap(),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(property.view(&options)).unwrap();
+        assert_eq!(json["updated_at"], "2024-06-01T09:30:00+09:00");
+
+        let json = serde_json::to_value(property.view(&ViewOptions::default())).unwrap();
+        assert_eq!(json["updated_at"], "2024-06-01T00:30:00Z");
+    }
+
+    #[test]
+    fn leaves_out_updated_at_when_it_never_changed() {
+        let mut property = Property::from_input(
+            1,
+            PropertyInput::default(),
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        );
+        property.updated_at = None;
+
+        let json = serde_json::to_value(property.view(&ViewOptions::default())).unwrap();
+        assert!(json.get("updated_at").is_none());
+    }
+
+    /// A property in 日本橋, with every part of the address filled in
+    fn nihonbashi() -> Property {
+        let input = PropertyInput {
+            prefecture: "東京都".to_string(),
+            city: "中央区".to_string(),
+            town: "日本橋".to_string(),
+            chome: "4".to_string(),
+            banchi: "16".to_string(),
+            go: "12".to_string(),
+            building: "国立競技場".to_string(),
+            ..Default::default()
+        };
+        Property::from_input(
+            1,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        )
+    }
+
+    #[test]
+    fn writes_the_address_in_western_order() {
+        let mut property = nihonbashi();
+        assert_eq!(
+            property.full_address_en(),
+            "国立競技場, 4-16-12 日本橋, 中央区, Tokyo"
+        );
+
+        property.building.clear();
+        property.go.clear();
+        assert_eq!(property.full_address_en(), "4-16 日本橋, 中央区, Tokyo");
+
+        let options = ViewOptions {
+            include_full_address_en: true,
+            ..Default::default()
+        };
+        let json = serde_json::to_value(property.view(&options)).unwrap();
+        assert_eq!(json["full_address_en"], "4-16 日本橋, 中央区, Tokyo");
+
+        let json = serde_json::to_value(property.view(&ViewOptions::default())).unwrap();
+        assert!(json.get("full_address_en").is_none());
+    }
+
+    #[test]
+    fn compares_properties_by_content_without_the_id() {
+        let property = nihonbashi();
+        let mut same = nihonbashi();
+        same.id = 2;
+        same.updated_at = None;
+        assert_eq!(property.content_key(), same.content_key());
+
+        let mut repriced = nihonbashi();
+        repriced.price = "2000万円".to_string();
+        assert_ne!(property.content_key(), repriced.content_key());
+        // It's still the same place, though
+        assert_eq!(property.address_key(), repriced.address_key());
+    }
+
+    #[test]
+    fn sends_unparseable_numbers_as_asked() {
+        let mut property = nihonbashi();
+        property.price = "応相談".to_string();
+        property.land_area = "120".to_string();
+
+        let view = |missing_values| {
+            let options = ViewOptions {
+                missing_values,
+                ..Default::default()
+            };
+            serde_json::to_value(property.view(&options)).unwrap()
+        };
+
+        let json = view(MissingValues::Null);
+        assert!(json["price_value"].is_null());
+        assert_eq!(json["land_area_value"], 120.0);
+
+        let json = view(MissingValues::Omit);
+        assert!(json.get("price_value").is_none());
+        assert_eq!(json["land_area_value"], 120.0);
+
+        let json = view(MissingValues::Zero);
+        assert_eq!(json["price_value"], 0);
+    }
+
+    #[test]
+    fn writes_string_ids_zero_padded() {
+        let mut property = nihonbashi();
+        property.id = 42;
+
+        let options = ViewOptions {
+            id_format: IdFormat::String,
+            ..Default::default()
+        };
+        let json = serde_json::to_value(property.view(&options)).unwrap();
+        assert_eq!(json["id"], "000042");
+
+        let json = serde_json::to_value(property.view(&ViewOptions::default())).unwrap();
+        assert_eq!(json["id"], 42);
+
+        // Ids too long to pad are written out in full
+        property.id = 12_345_678;
+        let json = serde_json::to_value(property.view(&options)).unwrap();
+        assert_eq!(json["id"], "12345678");
+    }
+
+    #[test]
+    fn writes_field_names_in_camel_case_when_asked() {
+        let property = nihonbashi();
+        let options = ViewOptions {
+            field_naming: FieldNaming::Camel,
+            ..Default::default()
+        };
+
+        let json = serde_json::to_value(property.view(&options)).unwrap();
+        assert_eq!(json["fullAddress"], property.full_address());
+        assert_eq!(json["canonicalType"], "other");
+        assert!(json.get("nearestStation").is_some());
+        assert!(json.get("nearest_station").is_none());
+        // Names that are one word are the same either way
+        assert_eq!(json["town"], "日本橋");
+    }
+
+    #[test]
+    fn applies_station_aliases_to_input() {
+        let aliases = StationAliases::from_json(r#"{ "Tokyo Station": "東京駅" }"#).unwrap();
+        let input = PropertyInput {
+            nearest_station: " tokyo station".to_string(),
+            property_type: "マンション".to_string(),
+            ..Default::default()
+        };
+        let property = Property::from_input(1, input, &TypeMapping::default(), &aliases);
+
+        assert_eq!(property.nearest_station, "東京駅");
+        assert_eq!(property.canonical_type, CanonicalType::Condo);
+    }
+
+    #[test]
+    fn masks_the_parts_of_the_address_that_pinpoint_it() {
+        let mut property = nihonbashi();
+        property.go.clear();
+        property.latitude = Some(35.68);
+        property.raw = Some("東京都,中央区,日本橋,4,16,,国立競技場".to_string());
+
+        let options = ViewOptions {
+            mask_address: true,
+            include_raw: true,
+            ..Default::default()
+        };
+        let json = serde_json::to_value(property.view(&options)).unwrap();
+
+        assert_eq!(json["chome"], "4");
+        assert_eq!(json["banchi"], MASK);
+        assert_eq!(json["go"], "");
+        assert_eq!(json["building"], MASK);
+        let full_address = json["full_address"].as_str().unwrap();
+        assert!(!full_address.contains("16") && !full_address.contains("国立"));
+        assert!(json.get("latitude").is_none());
+        assert!(json["raw"].is_null());
+    }
+
+    #[test]
+    fn strips_leading_zeros_from_the_block_when_asked() {
+        let mut property = nihonbashi();
+        property.chome = "04".to_string();
+        property.go = "012".to_string();
+
+        let options = ViewOptions {
+            strip_leading_zeros: true,
+            ..Default::default()
+        };
+        let json = serde_json::to_value(property.view(&options)).unwrap();
+        assert_eq!(json["chome"], "4");
+        assert_eq!(json["go"], "12");
+        assert_eq!(
+            json["full_address"],
+            "東京都中央区日本橋4丁目16番地12号国立競技場"
+        );
+
+        let json = serde_json::to_value(property.view(&ViewOptions::default())).unwrap();
+        assert_eq!(json["chome"], "04");
+    }
+}
@@ -0,0 +1,175 @@
+//! Sorting the free-form property types from imported files into a few
+//! canonical ones, so that clients can filter and group by them
+
+use std::{collections::HashMap, fmt, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// The kinds of property that every type is sorted into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CanonicalType {
+    Land,
+    House,
+    Condo,
+    Commercial,
+    /// Any type we don't have a mapping for
+    #[default]
+    Other,
+}
+
+impl fmt::Display for CanonicalType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CanonicalType::Land => "land",
+            CanonicalType::House => "house",
+            CanonicalType::Condo => "condo",
+            CanonicalType::Commercial => "commercial",
+            CanonicalType::Other => "other",
+        })
+    }
+}
+
+impl FromStr for CanonicalType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "land" => Ok(CanonicalType::Land),
+            "house" => Ok(CanonicalType::House),
+            "condo" => Ok(CanonicalType::Condo),
+            "commercial" => Ok(CanonicalType::Commercial),
+            "other" => Ok(CanonicalType::Other),
+            other => Err(format!("unknown property type `{other}`")),
+        }
+    }
+}
+
+/// Parses a comma-separated list of canonical types, like `land,commercial`
+pub fn parse_types(s: &str) -> Result<Vec<CanonicalType>, String> {
+    s.split(',')
+        .filter(|canonical_type| !canonical_type.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// The types we know about out of the box, as they're usually written in listings
+const BUILT_IN: &[(&str, CanonicalType)] = &[
+    ("土地", CanonicalType::Land),
+    ("売地", CanonicalType::Land),
+    ("land", CanonicalType::Land),
+    ("戸建", CanonicalType::House),
+    ("戸建て", CanonicalType::House),
+    ("一戸建て", CanonicalType::House),
+    ("新築一戸建て", CanonicalType::House),
+    ("中古一戸建て", CanonicalType::House),
+    ("house", CanonicalType::House),
+    ("マンション", CanonicalType::Condo),
+    ("新築マンション", CanonicalType::Condo),
+    ("中古マンション", CanonicalType::Condo),
+    ("condo", CanonicalType::Condo),
+    ("店舗", CanonicalType::Commercial),
+    ("事務所", CanonicalType::Commercial),
+    ("ビル", CanonicalType::Commercial),
+    ("倉庫", CanonicalType::Commercial),
+    ("commercial", CanonicalType::Commercial),
+];
+
+/// Maps each property type to its canonical type
+#[derive(Debug, Clone)]
+pub struct TypeMapping {
+    /// Keyed by the type, trimmed and with ASCII letters lowercased
+    types: HashMap<String, CanonicalType>,
+}
+
+/// The reasons a type mapping file can't be loaded
+#[derive(Debug)]
+pub enum TypeMappingError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for TypeMappingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeMappingError::Io(error) => {
+                write!(f, "failed to read the property types file: {error}")
+            }
+            TypeMappingError::Json(error) => write!(f, "invalid property types file: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for TypeMappingError {}
+
+impl Default for TypeMapping {
+    fn default() -> Self {
+        TypeMapping {
+            types: BUILT_IN
+                .iter()
+                .map(|(name, canonical)| (key(name), *canonical))
+                .collect(),
+        }
+    }
+}
+
+impl TypeMapping {
+    /// Parses extra mappings from a JSON object mapping each type to its
+    /// canonical type, like `{ "アパート": "commercial" }`. These are added
+    /// to the built-in ones, and replace them for the same type.
+    pub fn from_json(text: &str) -> Result<Self, TypeMappingError> {
+        let extra: HashMap<String, CanonicalType> =
+            serde_json::from_str(text).map_err(TypeMappingError::Json)?;
+
+        let mut mapping = TypeMapping::default();
+        mapping.types.extend(
+            extra
+                .into_iter()
+                .map(|(name, canonical)| (key(&name), canonical)),
+        );
+
+        Ok(mapping)
+    }
+
+    /// Reads the extra mappings from a file
+    pub fn load(path: &Path) -> Result<Self, TypeMappingError> {
+        let text = std::fs::read_to_string(path).map_err(TypeMappingError::Io)?;
+        TypeMapping::from_json(&text)
+    }
+
+    /// The canonical type of a property type. Types without a mapping are `other`.
+    pub fn canonicalize(&self, property_type: &str) -> CanonicalType {
+        self.types
+            .get(&key(property_type))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Types are matched regardless of surrounding whitespace,
+/// or the case of English names
+fn key(name: &str) -> String {
+    name.trim().to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_list_of_types() {
+        assert_eq!(
+            parse_types(" land, Commercial ,"),
+            Ok(vec![CanonicalType::Land, CanonicalType::Commercial])
+        );
+        assert_eq!(parse_types(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn rejects_unknown_types() {
+        assert_eq!(
+            parse_types("land,comercial"),
+            Err("unknown property type `comercial`".to_string())
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Support for HTTP range requests, as described in RFC 9110
+
+use std::ops::Range;
+
+/// The part of a representation that the client asked for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByteRange {
+    /// No usable range was requested, so send the whole thing
+    Full,
+    /// Send just these bytes
+    Partial(Range<usize>),
+    /// The requested range lies outside of the content
+    Unsatisfiable,
+}
+
+/// Works out which bytes to send, given the value of a `Range` header
+/// and the total length of the content.
+///
+/// We only support a single range. Anything we don't understand,
+/// including multiple ranges, falls back to sending the full content,
+/// which the spec allows.
+pub fn parse_range(header: Option<&str>, len: usize) -> ByteRange {
+    let Some(spec) = header.and_then(|header| header.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = match (start.parse::<usize>(), end.parse::<usize>()) {
+        // bytes=10-20, where the end is inclusive
+        (Ok(start), Ok(end)) if start <= end => start..end.saturating_add(1).min(len),
+        // bytes=10-
+        (Ok(start), Err(_)) if end.is_empty() => start..len,
+        // bytes=-20, meaning the last 20 bytes
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+
+            len.saturating_sub(suffix)..len
+        }
+        _ => return ByteRange::Full,
+    };
+
+    // An empty range has no last byte to put in the `Content-Range` header
+    if range.start >= len || range.is_empty() {
+        return ByteRange::Unsatisfiable;
+    }
+
+    ByteRange::Partial(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_header_sends_everything() {
+        assert_eq!(parse_range(None, 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("items=0-10"), 100), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 100), ByteRange::Full);
+    }
+
+    #[test]
+    fn closed_ranges_include_the_end() {
+        assert_eq!(
+            parse_range(Some("bytes=10-19"), 100),
+            ByteRange::Partial(10..20)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=90-200"), 100),
+            ByteRange::Partial(90..100)
+        );
+    }
+
+    #[test]
+    fn open_and_suffix_ranges() {
+        assert_eq!(
+            parse_range(Some("bytes=1000-"), 1500),
+            ByteRange::Partial(1000..1500)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=-20"), 100),
+            ByteRange::Partial(80..100)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=-200"), 100),
+            ByteRange::Partial(0..100)
+        );
+        assert_eq!(parse_range(Some("bytes=-0"), 100), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn huge_ends_do_not_overflow() {
+        let header = format!("bytes=0-{}", usize::MAX);
+        assert_eq!(parse_range(Some(&header), 100), ByteRange::Partial(0..100));
+    }
+
+    #[test]
+    fn ranges_outside_the_content_are_unsatisfiable() {
+        assert_eq!(
+            parse_range(Some("bytes=100-"), 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range(Some("bytes=150-160"), 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(parse_range(Some("bytes=0-10"), 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-5"), 0), ByteRange::Unsatisfiable);
+    }
+}
@@ -0,0 +1,124 @@
+//! Helpers for shaping the JSON responses our handlers send back
+
+use axum::{
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+/// The media type clients send in their `Accept` header to opt into
+/// the versioned response envelope
+pub const V1_MEDIA_TYPE: &str = "application/vnd.japanprops.v1+json";
+
+/// The content type we send CSV data with
+pub const CSV_CONTENT_TYPE: &str = "text/csv; charset=utf-8";
+
+/// What to respond with when there are no properties to send
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyResponse {
+    /// An empty list, `[]`
+    #[default]
+    Array,
+    /// `204 No Content`, with no body at all
+    NoContent,
+}
+
+/// The query parameter for picking the [`EmptyResponse`]
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct EmptyParams {
+    #[serde(default)]
+    pub if_empty: EmptyResponse,
+}
+
+/// Wraps a response body along with the version of the API that produced it,
+/// so that clients can detect format changes as the API evolves
+#[derive(Debug, Serialize)]
+pub struct Envelope<T> {
+    pub api_version: &'static str,
+    pub data: T,
+}
+
+/// Checks if any of the media types in the `Accept` header match the given one.
+/// Parameters like `q=0.9` are ignored.
+fn accepts(headers: &HeaderMap, expected: &str) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|media_type| media_type.split(';').next())
+        .any(|media_type| media_type.trim().eq_ignore_ascii_case(expected))
+}
+
+/// Checks if the client asked for the versioned envelope
+pub fn wants_envelope(headers: &HeaderMap) -> bool {
+    accepts(headers, V1_MEDIA_TYPE)
+}
+
+/// Checks if the client asked for CSV instead of JSON
+pub fn wants_csv(headers: &HeaderMap) -> bool {
+    accepts(headers, "text/csv")
+}
+
+/// Checks if the client is a browser asking for a web page
+pub fn wants_html(headers: &HeaderMap) -> bool {
+    accepts(headers, "text/html")
+}
+
+/// A minimal page for browsers that end up somewhere that doesn't exist,
+/// so that they don't just see raw JSON
+pub const NOT_FOUND_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Not Found | Japanese Properties API</title>
+</head>
+<body>
+<h1>404 Not Found</h1>
+<p>The page you're looking for doesn't exist.</p>
+<p>This is an API, so try <a href="/properties">/properties</a> instead.</p>
+</body>
+</html>
+"#;
+
+/// Serializes the data as JSON, wrapped in the versioned envelope if the
+/// client asked for it. Otherwise, the data is sent bare as plain `application/json`.
+pub fn json_response<T: Serialize>(headers: &HeaderMap, data: T) -> Response {
+    if wants_envelope(headers) {
+        (
+            [(header::CONTENT_TYPE, V1_MEDIA_TYPE)],
+            Json(Envelope {
+                api_version: "1",
+                data,
+            }),
+        )
+            .into_response()
+    } else {
+        Json(data).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn finds_the_envelope_among_other_media_types() {
+        assert!(wants_envelope(&accept(
+            "text/html, application/vnd.japanprops.v1+json;q=0.9"
+        )));
+        assert!(wants_envelope(&accept(
+            "Application/VND.japanprops.v1+JSON"
+        )));
+        assert!(!wants_envelope(&accept("application/json")));
+        assert!(!wants_envelope(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,225 @@
+//! Remembering recent list responses, so that popular queries
+//! don't have to be filtered and serialized again on every request
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{self, Bytes},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+/// The header that says whether a response came from the cache
+pub const X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+
+/// How many responses are remembered. Lists can hold the whole dataset,
+/// so we keep this small to bound the memory use.
+pub const CAPACITY: usize = 32;
+
+/// How many bytes of bodies are remembered in all, no matter how many
+/// responses that comes to
+pub const MAX_BYTES: usize = 16 * 1024 * 1024;
+
+/// The biggest body that's worth remembering. Bigger ones would push
+/// everything else out of the cache, so they're sent without storing them.
+pub const MAX_ENTRY_BYTES: usize = 1024 * 1024;
+
+/// A response stored so that it can be sent again
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl CachedResponse {
+    /// Reads the whole body of a response, so that it can be stored
+    pub async fn read(response: Response) -> Result<Self, axum::Error> {
+        let (parts, body) = response.into_parts();
+        let body = body::to_bytes(body, usize::MAX).await?;
+
+        Ok(CachedResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+        })
+    }
+
+    /// The response, marked as a hit or a miss with the `X-Cache` header
+    pub fn into_response_with(self, hit: bool) -> Response {
+        let mut response = (self.status, self.headers, self.body).into_response();
+        let value = if hit { "HIT" } else { "MISS" };
+        response
+            .headers_mut()
+            .insert(X_CACHE, HeaderValue::from_static(value));
+        response
+    }
+}
+
+/// The most recent responses, by their cache key.
+/// Expired responses are forgotten as new ones come in, and once it's full,
+/// the oldest responses are forgotten to make room.
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    order: VecDeque<String>,
+    responses: HashMap<String, (Instant, CachedResponse)>,
+    /// The total size of the bodies in `responses`
+    bytes: usize,
+}
+
+impl ResponseCache {
+    /// The response stored for this key, if it's younger than the ttl
+    pub fn get(&self, key: &str, ttl: Duration) -> Option<&CachedResponse> {
+        self.responses
+            .get(key)
+            .filter(|(stored, _)| stored.elapsed() < ttl)
+            .map(|(_, response)| response)
+    }
+
+    /// Stores a response, unless its body is over [`MAX_ENTRY_BYTES`].
+    /// The ttl is the one it'll be read with, so that responses older
+    /// than it can be forgotten.
+    pub fn insert(&mut self, key: String, response: CachedResponse, ttl: Duration) {
+        if response.body.len() > MAX_ENTRY_BYTES {
+            return;
+        }
+
+        // Responses are stored in order, so the expired ones are at the front
+        while let Some(oldest) = self.order.front() {
+            match self.responses.get(oldest) {
+                Some((stored, _)) if stored.elapsed() < ttl => break,
+                _ => self.remove_oldest(),
+            }
+        }
+
+        self.bytes += response.body.len();
+
+        if let Some((_, replaced)) = self
+            .responses
+            .insert(key.clone(), (Instant::now(), response))
+        {
+            self.bytes -= replaced.body.len();
+            self.order.retain(|stored| *stored != key);
+        }
+
+        self.order.push_back(key);
+
+        while self.order.len() > CAPACITY || self.bytes > MAX_BYTES {
+            self.remove_oldest();
+        }
+    }
+
+    fn remove_oldest(&mut self) {
+        if let Some(oldest) = self.order.pop_front() {
+            if let Some((_, response)) = self.responses.remove(&oldest) {
+                self.bytes -= response.body.len();
+            }
+        }
+    }
+
+    /// Forgets every response, for when the data they were made from changes
+    pub fn clear(&mut self) {
+        self.order.clear();
+        self.responses.clear();
+        self.bytes = 0;
+    }
+}
+
+/// The key for a request, made of everything the response depends on:
+/// the query string, and the `Accept` header that picks the format
+pub fn key(query: Option<&str>, headers: &HeaderMap) -> String {
+    let accept = headers
+        .get(axum::http::header::ACCEPT)
+        .map_or(&b""[..], HeaderValue::as_bytes);
+
+    format!(
+        "{}\n{}",
+        String::from_utf8_lossy(accept),
+        query.unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &'static str) -> CachedResponse {
+        CachedResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(body.as_bytes()),
+        }
+    }
+
+    #[test]
+    fn forgets_the_oldest_responses_once_full() {
+        let mut cache = ResponseCache::default();
+        let ttl = Duration::from_secs(60);
+
+        for i in 0..=CAPACITY {
+            cache.insert(i.to_string(), response("[]"), ttl);
+        }
+
+        assert!(cache.get("0", ttl).is_none());
+        assert!(cache.get("1", ttl).is_some());
+        assert!(cache.get(&CAPACITY.to_string(), ttl).is_some());
+        assert!(cache.get("1", Duration::ZERO).is_none());
+
+        cache.clear();
+        assert!(cache.get("1", ttl).is_none());
+    }
+
+    #[test]
+    fn keys_requests_by_their_query_and_format() {
+        let mut csv = HeaderMap::new();
+        csv.insert(
+            axum::http::header::ACCEPT,
+            HeaderValue::from_static("text/csv"),
+        );
+
+        assert_eq!(key(Some("limit=1"), &HeaderMap::new()), "\nlimit=1");
+        assert_eq!(key(None, &csv), "text/csv\n");
+        assert_ne!(key(Some("a"), &csv), key(Some("a"), &HeaderMap::new()));
+    }
+
+    #[test]
+    fn bounds_the_bytes_it_remembers() {
+        let mut cache = ResponseCache::default();
+        let ttl = Duration::from_secs(60);
+        let sized = |len| CachedResponse {
+            body: Bytes::from(vec![b' '; len]),
+            ..response("")
+        };
+
+        cache.insert("big".to_string(), sized(MAX_ENTRY_BYTES + 1), ttl);
+        assert!(cache.get("big", ttl).is_none());
+
+        let fits = MAX_BYTES / MAX_ENTRY_BYTES;
+        for i in 0..=fits {
+            cache.insert(i.to_string(), sized(MAX_ENTRY_BYTES), ttl);
+        }
+
+        assert!(cache.get("0", ttl).is_none());
+        assert!(cache.get("1", ttl).is_some());
+        assert_eq!(cache.bytes, MAX_BYTES);
+
+        // Storing a key again replaces its body rather than adding to it
+        cache.insert("1".to_string(), response("[]"), ttl);
+        assert_eq!(cache.bytes, MAX_BYTES - MAX_ENTRY_BYTES + 2);
+    }
+
+    #[test]
+    fn forgets_expired_responses_as_new_ones_come_in() {
+        let mut cache = ResponseCache::default();
+
+        cache.insert("old".to_string(), response("[1]"), Duration::ZERO);
+        cache.insert("new".to_string(), response("[]"), Duration::ZERO);
+
+        assert!(!cache.responses.contains_key("old"));
+        assert_eq!(cache.order, ["new"]);
+        assert_eq!(cache.bytes, 2);
+    }
+}
@@ -0,0 +1,107 @@
+//! Finding properties like a given one, for "similar listings" recommendations
+
+use crate::property::Property;
+
+/// The number of similar properties returned when a client doesn't pass a limit
+pub const DEFAULT_LIMIT: usize = 10;
+
+/// How far a similar property's price can be from the target's, as a fraction
+const PRICE_TOLERANCE: f64 = 0.2;
+
+/// Finds the properties most like the target, closest in price first.
+///
+/// A similar property has the same type, prefecture, and city,
+/// and a price within 20% of the target's. If the target's price
+/// can't be parsed, there's nothing to compare against, so none are found.
+pub fn find_similar<'a>(
+    target: &Property,
+    properties: impl IntoIterator<Item = &'a Property>,
+    limit: usize,
+) -> Vec<&'a Property> {
+    let Some(price) = target.price_value() else {
+        return vec![];
+    };
+
+    let tolerance = (price as f64 * PRICE_TOLERANCE) as u64;
+    let (min, max) = (
+        price.saturating_sub(tolerance),
+        price.saturating_add(tolerance),
+    );
+
+    let mut similar: Vec<(u64, &Property)> = properties
+        .into_iter()
+        .filter(|property| {
+            property.id != target.id
+                && property.property_type == target.property_type
+                && property.prefecture == target.prefecture
+                && property.city == target.city
+        })
+        .filter_map(|property| {
+            let other = property
+                .price_value()
+                .filter(|other| (min..=max).contains(other))?;
+            Some((other.abs_diff(price), property))
+        })
+        .collect();
+
+    // Ties are broken by id, so the order doesn't change between requests
+    similar.sort_unstable_by_key(|(distance, property)| (*distance, property.id));
+
+    similar
+        .into_iter()
+        .take(limit)
+        .map(|(_, property)| property)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    use super::*;
+
+    fn property(id: usize, city: &str, property_type: &str, price: &str) -> Property {
+        let input = PropertyInput {
+            prefecture: "東京都".to_string(),
+            city: city.to_string(),
+            property_type: property_type.to_string(),
+            price: price.to_string(),
+            ..Default::default()
+        };
+        Property::from_input(
+            id,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        )
+    }
+
+    #[test]
+    fn finds_the_closest_prices_in_the_same_city_and_type() {
+        let target = property(1, "渋谷区", "マンション", "5000万円");
+        let properties = [
+            target.clone(),
+            property(2, "渋谷区", "マンション", "5800万円"),
+            property(3, "渋谷区", "マンション", "4900万円"),
+            property(4, "渋谷区", "マンション", "6500万円"),
+            property(5, "新宿区", "マンション", "5000万円"),
+            property(6, "渋谷区", "土地", "5000万円"),
+            property(7, "渋谷区", "マンション", "5100万円"),
+            property(8, "渋谷区", "マンション", "応相談"),
+        ];
+
+        let ids = |similar: Vec<&Property>| -> Vec<usize> {
+            similar.iter().map(|property| property.id).collect()
+        };
+        assert_eq!(ids(find_similar(&target, &properties, 10)), [3, 7, 2]);
+        assert_eq!(ids(find_similar(&target, &properties, 1)), [3]);
+    }
+
+    #[test]
+    fn finds_nothing_without_a_price_to_compare() {
+        let target = property(1, "渋谷区", "マンション", "応相談");
+        let properties = [property(2, "渋谷区", "マンション", "5000万円")];
+
+        assert!(find_similar(&target, &properties, 10).is_empty());
+    }
+}
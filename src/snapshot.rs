@@ -0,0 +1,101 @@
+//! Lightweight durability for the in-memory db
+//!
+//! Rather than pulling in a full database, we write the whole db out
+//! as JSON after each change, and read it back in on startup.
+
+use std::{io, path::Path};
+
+use crate::property::{Db, Property, ViewOptions};
+
+/// Reads the db back in from a snapshot file.
+/// Returns `None` if there's no snapshot yet.
+pub async fn load(path: &Path) -> io::Result<Option<Db>> {
+    let data = match tokio::fs::read(path).await {
+        Ok(data) => data,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    let properties: Vec<Property> = serde_json::from_slice(&data)?;
+
+    Ok(Some(
+        properties
+            .into_iter()
+            .map(|property| (property.id, property))
+            .collect(),
+    ))
+}
+
+/// Writes the db out to a snapshot file.
+///
+/// We write to a temporary file first and then rename it over the old
+/// snapshot, so that a crash partway through never leaves us with a
+/// half-written snapshot.
+pub async fn save<'a>(
+    path: &Path,
+    properties: impl IntoIterator<Item = &'a Property>,
+) -> io::Result<()> {
+    // The raw rows are left out of responses by default, but we keep them
+    let options = ViewOptions {
+        include_raw: true,
+        ..Default::default()
+    };
+    let properties: Vec<_> = properties
+        .into_iter()
+        .map(|property| property.view(&options))
+        .collect();
+    let data = serde_json::to_vec(&properties)?;
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    tokio::fs::write(&temp_path, data).await?;
+    tokio::fs::rename(&temp_path, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    use super::*;
+
+    fn property(id: usize, town: &str) -> Property {
+        let input = PropertyInput {
+            prefecture: "東京都".to_string(),
+            town: town.to_string(),
+            ..Default::default()
+        };
+        Property::from_input(
+            id,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        )
+    }
+
+    /// A path in the temp directory that's only used by one test
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("snapshot-{}-{name}.json", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn reads_back_what_it_saved() {
+        let path = temp_path("round-trip");
+        let mut saved = property(2, "神南");
+        saved.raw = Some("東京都,,神南".to_string());
+
+        save(&path, [&property(1, "梅田"), &saved]).await.unwrap();
+        let db = load(&path).await.unwrap().unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(db.len(), 2);
+        assert_eq!(db[&1].town, "梅田");
+        // The raw row is kept, even though it's left out of responses
+        assert_eq!(db[&2].raw.as_deref(), Some("東京都,,神南"));
+    }
+
+    #[tokio::test]
+    async fn has_nothing_to_load_before_the_first_save() {
+        assert!(load(&temp_path("missing")).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,183 @@
+//! Sorting the list of properties by one or more fields
+
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{export::COLUMNS, property::Property};
+
+/// The query parameter for sorting, like `sort_by=prefecture,-price`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SortParams {
+    pub sort_by: Option<String>,
+}
+
+/// One field to sort by, and which way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    /// The position of the field in [`COLUMNS`]
+    column: usize,
+    descending: bool,
+}
+
+/// A sort key that isn't one of the fields we can sort by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownSortField(pub String);
+
+impl fmt::Display for UnknownSortField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "can't sort by unknown field `{}`, expected one of: {}",
+            self.0,
+            COLUMNS.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownSortField {}
+
+impl FromStr for SortKey {
+    type Err = UnknownSortField;
+
+    /// Parses a field name, with a leading `-` to sort it in descending order
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, descending) = match s.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (s, false),
+        };
+
+        let column = COLUMNS
+            .iter()
+            .position(|column| *column == name)
+            .ok_or_else(|| UnknownSortField(s.to_string()))?;
+
+        Ok(SortKey { column, descending })
+    }
+}
+
+impl SortParams {
+    /// Parses each of the comma-separated keys, in order of priority
+    pub fn keys(&self) -> Result<Vec<SortKey>, UnknownSortField> {
+        self.sort_by
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|key| !key.trim().is_empty())
+            .map(str::parse)
+            .collect()
+    }
+}
+
+/// Sorts the properties by each key in turn, so that later keys only
+/// decide the order of properties that are equal on the earlier ones.
+///
+/// The sort is stable, so properties that are equal on every key stay
+/// in the order they were in, which is by id for lists from the db.
+pub fn sort(properties: &mut [&Property], keys: &[SortKey]) {
+    if keys.is_empty() {
+        return;
+    }
+
+    properties.sort_by(|a, b| {
+        keys.iter()
+            .map(|key| compare(a, b, *key))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+/// Compares two properties by one of the [`COLUMNS`].
+///
+/// The price and land area are compared as numbers. Values that can't be
+/// parsed come after all the others, whichever way we're sorting.
+fn compare(a: &Property, b: &Property, key: SortKey) -> Ordering {
+    let direction = |ordering: Ordering| {
+        if key.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    };
+
+    match COLUMNS[key.column] {
+        "id" => direction(a.id.cmp(&b.id)),
+        "price" => compare_numbers(a.price_value(), b.price_value(), direction),
+        "land_area" => compare_numbers(a.land_area_value(), b.land_area_value(), direction),
+        _ => {
+            // The content key has the same fields as the columns, without the id
+            let (a, b) = (a.content_key(), b.content_key());
+            direction(a[key.column - 1].cmp(b[key.column - 1]))
+        }
+    }
+}
+
+fn compare_numbers<T: PartialOrd>(
+    a: Option<T>,
+    b: Option<T>,
+    direction: impl Fn(Ordering) -> Ordering,
+) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => direction(a.partial_cmp(&b).unwrap_or(Ordering::Equal)),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    use super::*;
+
+    fn property(id: usize, prefecture: &str, price: &str) -> Property {
+        let input = PropertyInput {
+            prefecture: prefecture.to_string(),
+            price: price.to_string(),
+            ..Default::default()
+        };
+        Property::from_input(
+            id,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        )
+    }
+
+    fn sorted(properties: &[Property], sort_by: &str) -> Vec<usize> {
+        let params = SortParams {
+            sort_by: Some(sort_by.to_string()),
+        };
+        let mut properties: Vec<&Property> = properties.iter().collect();
+        sort(&mut properties, &params.keys().unwrap());
+        properties.iter().map(|property| property.id).collect()
+    }
+
+    #[test]
+    fn breaks_ties_with_the_later_keys() {
+        let properties = [
+            property(1, "東京都", "1000万円"),
+            property(2, "大阪府", "500万円"),
+            property(3, "東京都", "応相談"),
+            property(4, "東京都", "2000万円"),
+            property(5, "大阪府", "500万円"),
+        ];
+
+        assert_eq!(sorted(&properties, "prefecture,-price"), [2, 5, 4, 1, 3]);
+        // Prices that can't be parsed come last either way
+        assert_eq!(sorted(&properties, "price"), [2, 5, 1, 4, 3]);
+        assert_eq!(sorted(&properties, "-id"), [5, 4, 3, 2, 1]);
+        assert_eq!(sorted(&properties, ""), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn rejects_unknown_sort_fields() {
+        let params = SortParams {
+            sort_by: Some("price,-rent".to_string()),
+        };
+
+        assert_eq!(params.keys(), Err(UnknownSortField("-rent".to_string())));
+    }
+}
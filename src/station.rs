@@ -0,0 +1,90 @@
+//! Station aliases that operators can configure, so that the same station
+//! is written the same way no matter which source a file came from
+
+use std::{collections::HashMap, fmt, path::Path};
+
+/// Maps the other names of a station to the one we store, such as
+/// `Tokyo Station` and `東京` to `東京駅`
+#[derive(Debug, Clone, Default)]
+pub struct StationAliases {
+    /// Keyed by the alias, trimmed and with ASCII letters lowercased
+    aliases: HashMap<String, String>,
+}
+
+/// The reasons an alias file can't be loaded
+#[derive(Debug)]
+pub enum AliasesError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for AliasesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AliasesError::Io(error) => write!(f, "failed to read the aliases file: {error}"),
+            AliasesError::Json(error) => write!(f, "invalid aliases file: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for AliasesError {}
+
+impl StationAliases {
+    /// Parses the aliases from a JSON object mapping each alias to its station,
+    /// like `{ "Tokyo Station": "東京駅", "東京": "東京駅" }`
+    pub fn from_json(text: &str) -> Result<Self, AliasesError> {
+        let raw: HashMap<String, String> =
+            serde_json::from_str(text).map_err(AliasesError::Json)?;
+
+        let aliases = raw
+            .into_iter()
+            .map(|(alias, station)| (key(&alias), station.trim().to_string()))
+            .collect();
+
+        Ok(StationAliases { aliases })
+    }
+
+    /// Reads the aliases from a file
+    pub fn load(path: &Path) -> Result<Self, AliasesError> {
+        let text = std::fs::read_to_string(path).map_err(AliasesError::Io)?;
+        StationAliases::from_json(&text)
+    }
+
+    /// The station that a name is an alias for.
+    /// Names that aren't aliases are passed through as they are.
+    pub fn canonicalize<'a>(&'a self, station: &'a str) -> &'a str {
+        self.aliases
+            .get(&key(station))
+            .map_or(station, String::as_str)
+    }
+}
+
+/// Aliases are matched regardless of surrounding whitespace, or the case of
+/// romaji names, since those are the differences we see the most
+fn key(name: &str) -> String {
+    name.trim().to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonicalizes_aliases() {
+        let aliases =
+            StationAliases::from_json(r#"{ "Tokyo Station": " 東京駅 ", "東京": "東京駅" }"#)
+                .unwrap();
+
+        assert_eq!(aliases.canonicalize(" TOKYO station"), "東京駅");
+        assert_eq!(aliases.canonicalize("東京"), "東京駅");
+        assert_eq!(aliases.canonicalize("渋谷"), "渋谷");
+    }
+
+    #[test]
+    fn rejects_invalid_aliases_files() {
+        assert!(matches!(
+            StationAliases::from_json(r#"{ "東京": 1 }"#),
+            Err(AliasesError::Json(_))
+        ));
+    }
+}
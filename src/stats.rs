@@ -0,0 +1,630 @@
+//! Summary statistics over the stored properties
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    str::FromStr,
+};
+
+use serde::Serialize;
+
+use crate::property::Property;
+
+/// The bucket size used when a client doesn't pass one, ¥10,000,000
+pub const DEFAULT_BUCKET_SIZE: u64 = 10_000_000;
+
+/// The most buckets a histogram can have, so that a tiny bucket size
+/// on a wide range of prices can't make us build millions of them
+pub const MAX_BUCKETS: u64 = 1000;
+
+/// The buckets needed to cover the prices would be more than [`MAX_BUCKETS`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TooManyBuckets {
+    pub buckets: u64,
+}
+
+impl fmt::Display for TooManyBuckets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the prices would need {} buckets of this size, but there can be at most {MAX_BUCKETS}",
+            self.buckets
+        )
+    }
+}
+
+impl std::error::Error for TooManyBuckets {}
+
+/// The number of properties with a price in `min..max`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Bucket {
+    pub min: u64,
+    pub max: u64,
+    pub count: usize,
+}
+
+/// Counts the properties in each price bucket of the given size.
+///
+/// The buckets run from the cheapest property to the most expensive one
+/// without any gaps, so empty buckets in between are included with a count
+/// of zero. That way, the result can be drawn as a chart as it is.
+/// Properties with prices that can't be parsed are left out.
+///
+/// If that would take more than [`MAX_BUCKETS`] buckets, none are made,
+/// and the caller should ask for bigger ones.
+pub fn price_histogram<'a>(
+    properties: impl IntoIterator<Item = &'a Property>,
+    bucket_size: u64,
+) -> Result<Vec<Bucket>, TooManyBuckets> {
+    assert!(bucket_size > 0, "bucket size must be positive");
+
+    let mut counts = BTreeMap::new();
+    for price in properties.into_iter().filter_map(Property::price_value) {
+        *counts.entry(price / bucket_size).or_insert(0) += 1;
+    }
+
+    let (Some((&first, _)), Some((&last, _))) = (counts.first_key_value(), counts.last_key_value())
+    else {
+        return Ok(vec![]);
+    };
+
+    let buckets = last - first + 1;
+    if buckets > MAX_BUCKETS {
+        return Err(TooManyBuckets { buckets });
+    }
+
+    Ok((first..=last)
+        .map(|index| Bucket {
+            min: index * bucket_size,
+            max: index.saturating_add(1).saturating_mul(bucket_size),
+            count: counts.get(&index).copied().unwrap_or(0),
+        })
+        .collect())
+}
+
+/// The numeric fields we can find the range of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumericField {
+    Price,
+    LandArea,
+    PricePerSqm,
+}
+
+impl NumericField {
+    pub const NAMES: [&'static str; 3] = ["price", "land_area", "price_per_sqm"];
+}
+
+/// A field name that isn't one of the [`NumericField`]s
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownNumericField(pub String);
+
+impl fmt::Display for UnknownNumericField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown numeric field `{}`, expected one of: {}",
+            self.0,
+            NumericField::NAMES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownNumericField {}
+
+impl FromStr for NumericField {
+    type Err = UnknownNumericField;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "price" => Ok(NumericField::Price),
+            "land_area" => Ok(NumericField::LandArea),
+            "price_per_sqm" => Ok(NumericField::PricePerSqm),
+            other => Err(UnknownNumericField(other.to_string())),
+        }
+    }
+}
+
+/// The smallest and largest values of a field
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Range<T> {
+    pub min: T,
+    pub max: T,
+}
+
+/// Finds the smallest and largest of the values, or `None` if there aren't any.
+/// Values that can't be compared, like `NaN`, are skipped.
+pub fn range<T: PartialOrd + Copy>(values: impl IntoIterator<Item = T>) -> Option<Range<T>> {
+    values
+        .into_iter()
+        .filter(|value| value.partial_cmp(value).is_some())
+        .fold(None, |range, value| {
+            Some(match range {
+                None => Range {
+                    min: value,
+                    max: value,
+                },
+                Some(Range { min, max }) => Range {
+                    min: if value < min { value } else { min },
+                    max: if value > max { value } else { max },
+                },
+            })
+        })
+}
+
+/// The smallest box that contains every geocoded property,
+/// for fitting a map view around them
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Bounds {
+    pub min_latitude: f64,
+    pub max_latitude: f64,
+    pub min_longitude: f64,
+    pub max_longitude: f64,
+}
+
+/// Works out the bounding box of the properties that have coordinates,
+/// or `None` if none of them do
+pub fn bounds<'a>(properties: impl IntoIterator<Item = &'a Property>) -> Option<Bounds> {
+    properties
+        .into_iter()
+        .filter_map(Property::coordinates)
+        .fold(None, |bounds, (latitude, longitude)| {
+            Some(match bounds {
+                None => Bounds {
+                    min_latitude: latitude,
+                    max_latitude: latitude,
+                    min_longitude: longitude,
+                    max_longitude: longitude,
+                },
+                Some(bounds) => Bounds {
+                    min_latitude: bounds.min_latitude.min(latitude),
+                    max_latitude: bounds.max_latitude.max(latitude),
+                    min_longitude: bounds.min_longitude.min(longitude),
+                    max_longitude: bounds.max_longitude.max(longitude),
+                },
+            })
+        })
+}
+
+/// The percentiles used when a client doesn't ask for any
+pub const DEFAULT_PERCENTILES: [u8; 4] = [25, 50, 75, 90];
+
+/// A percentile that isn't a whole number from 0 to 100
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPercentile(pub String);
+
+impl fmt::Display for InvalidPercentile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid percentile `{}`, expected a whole number from 0 to 100",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidPercentile {}
+
+/// Parses a comma-separated list of percentiles, like `25,50,75`.
+/// Repeats are only counted once, and they're sorted in ascending order.
+pub fn parse_percentiles(list: &str) -> Result<Vec<u8>, InvalidPercentile> {
+    let mut percentiles = list
+        .split(',')
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|percentile| *percentile <= 100)
+                .ok_or_else(|| InvalidPercentile(value.trim().to_string()))
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+
+    percentiles.sort_unstable();
+    percentiles.dedup();
+    Ok(percentiles)
+}
+
+/// The prices at each percentile, written out like `{ "p25": 1000, "p50": 2000 }`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Percentiles(pub Vec<(u8, u64)>);
+
+impl Serialize for Percentiles {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_map(self.0.iter().map(|(p, price)| (format!("p{p}"), price)))
+    }
+}
+
+/// The spread of prices in one prefecture
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PricePercentiles {
+    /// How many of the properties had a price we could parse
+    pub count: usize,
+    #[serde(flatten)]
+    pub percentiles: Percentiles,
+}
+
+/// Finds the price at each percentile in each prefecture. Unlike the average,
+/// a few very expensive listings don't drag these up, which matters for
+/// real estate, where prices are far from evenly spread.
+///
+/// Percentiles between two prices are interpolated between them, so the
+/// 50th percentile of an even number of prices is the average of the middle two.
+/// Properties with prices that can't be parsed are left out, along with any
+/// prefectures that don't have any prices.
+pub fn price_percentiles<'a>(
+    properties: impl IntoIterator<Item = &'a Property>,
+    percentiles: &[u8],
+) -> BTreeMap<String, PricePercentiles> {
+    let mut prices: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+
+    for property in properties {
+        if let Some(price) = property.price_value() {
+            prices.entry(&property.prefecture).or_default().push(price);
+        }
+    }
+
+    prices
+        .into_iter()
+        .map(|(prefecture, mut prices)| {
+            prices.sort_unstable();
+
+            let percentiles = percentiles
+                .iter()
+                .map(|&percentile| (percentile, percentile_of(&prices, percentile)))
+                .collect();
+
+            let summary = PricePercentiles {
+                count: prices.len(),
+                percentiles: Percentiles(percentiles),
+            };
+
+            (prefecture.to_string(), summary)
+        })
+        .collect()
+}
+
+/// The value at a percentile of some sorted values, which can't be empty
+fn percentile_of(sorted: &[u64], percentile: u8) -> u64 {
+    let rank = f64::from(percentile) / 100.0 * (sorted.len() - 1) as f64;
+    let (lower, upper) = (sorted[rank.floor() as usize], sorted[rank.ceil() as usize]);
+
+    (lower as f64 + (upper as f64 - lower as f64) * rank.fract()).round() as u64
+}
+
+/// The first few groups out of an aggregation, for fields with
+/// too many distinct values to send all at once
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Groups<C> {
+    pub data: C,
+    /// How many groups there are in all
+    pub total: usize,
+    /// Whether any groups were left out
+    pub has_more: bool,
+    /// Lets the client know if their limit was clamped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// Keeps the first `limit` groups, clamped to at most `max` of them.
+/// The groups can be a list, or a map of them by name, like the prefectures.
+pub fn limit_groups<C>(groups: C, limit: usize, max: usize) -> Groups<C>
+where
+    C: IntoIterator + FromIterator<C::Item>,
+    C::IntoIter: ExactSizeIterator,
+{
+    let warning = (limit > max).then(|| {
+        format!("limit of {limit} exceeds the maximum number of groups, so it was clamped to {max}")
+    });
+
+    let groups = groups.into_iter();
+    let total = groups.len();
+    let kept = limit.min(max);
+
+    Groups {
+        data: groups.take(kept).collect(),
+        total,
+        has_more: kept < total,
+        warning,
+    }
+}
+
+/// The listings near one station
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StationSummary {
+    /// The station's name, without the trailing `駅`
+    pub station: String,
+    pub count: usize,
+    /// The average price in yen, out of the properties with a price we can parse.
+    /// This is `null` if none of them have one.
+    pub average_price: Option<u64>,
+}
+
+/// Normalizes a station name, so that `渋谷駅` and `渋谷` are the same station
+pub fn normalize_station(station: &str) -> &str {
+    let station = station.trim();
+    station.strip_suffix('駅').unwrap_or(station).trim_end()
+}
+
+/// Groups the properties by their nearest station,
+/// with the stations that have the most listings first
+pub fn aggregate_by_station<'a>(
+    properties: impl IntoIterator<Item = &'a Property>,
+) -> Vec<StationSummary> {
+    // The count, and the total and count of the parseable prices
+    let mut stations: HashMap<&str, (usize, u128, u64)> = HashMap::new();
+
+    for property in properties {
+        let entry = stations
+            .entry(normalize_station(&property.nearest_station))
+            .or_default();
+        entry.0 += 1;
+
+        if let Some(price) = property.price_value() {
+            entry.1 += u128::from(price);
+            entry.2 += 1;
+        }
+    }
+
+    let mut summaries: Vec<StationSummary> = stations
+        .into_iter()
+        .map(|(station, (count, total, priced))| StationSummary {
+            station: station.to_string(),
+            count,
+            average_price: (priced > 0).then(|| (total / u128::from(priced)) as u64),
+        })
+        .collect();
+
+    // Ties are broken by name, so the order doesn't change between requests
+    summaries.sort_unstable_by(|a, b| b.count.cmp(&a.count).then(a.station.cmp(&b.station)));
+    summaries
+}
+
+/// The number of listings in a city
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CityCount {
+    pub city: String,
+    pub count: usize,
+}
+
+/// Lists the distinct cities in a prefecture, sorted by name,
+/// along with how many properties are in each
+pub fn cities_in<'a>(
+    properties: impl IntoIterator<Item = &'a Property>,
+    prefecture: &str,
+) -> Vec<CityCount> {
+    let mut cities: BTreeMap<&str, usize> = BTreeMap::new();
+
+    for property in properties {
+        if property.prefecture == prefecture {
+            *cities.entry(&property.city).or_default() += 1;
+        }
+    }
+
+    cities
+        .into_iter()
+        .map(|(city, count)| CityCount {
+            city: city.to_string(),
+            count,
+        })
+        .collect()
+}
+
+/// Counts of listings for each prefecture and property type, like
+/// `{ "東京都": { "マンション": 12, "土地": 3 } }`.
+/// Combinations without any listings are left out.
+pub type Crosstab = BTreeMap<String, BTreeMap<String, usize>>;
+
+/// Counts the properties in each prefecture by their property type,
+/// to show what the market is made of in each region
+pub fn crosstab<'a>(properties: impl IntoIterator<Item = &'a Property>) -> Crosstab {
+    let mut counts: BTreeMap<&str, BTreeMap<&str, usize>> = BTreeMap::new();
+
+    for property in properties {
+        *counts
+            .entry(&property.prefecture)
+            .or_default()
+            .entry(&property.property_type)
+            .or_default() += 1;
+    }
+
+    counts
+        .into_iter()
+        .map(|(prefecture, types)| {
+            let types = types
+                .into_iter()
+                .map(|(property_type, count)| (property_type.to_string(), count))
+                .collect();
+
+            (prefecture.to_string(), types)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    fn priced(id: usize, price: &str) -> Property {
+        let input = PropertyInput {
+            prefecture: "東京都".to_string(),
+            price: price.to_string(),
+            ..Default::default()
+        };
+
+        Property::from_input(
+            id,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        )
+    }
+
+    #[test]
+    fn histogram_fills_in_empty_buckets() {
+        let properties = [priced(1, "5"), priced(2, "25"), priced(3, "abc")];
+        let buckets = price_histogram(&properties, 10).unwrap();
+
+        assert_eq!(
+            buckets,
+            vec![
+                Bucket {
+                    min: 0,
+                    max: 10,
+                    count: 1
+                },
+                Bucket {
+                    min: 10,
+                    max: 20,
+                    count: 0
+                },
+                Bucket {
+                    min: 20,
+                    max: 30,
+                    count: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn histogram_rejects_too_many_buckets() {
+        let properties = [priced(1, "1"), priced(2, "1000000000")];
+
+        assert!(price_histogram(&properties, 1).is_err());
+        assert!(price_histogram(&properties, 1_000_000).is_err());
+        assert!(price_histogram(&properties, 10_000_000).is_ok());
+    }
+
+    fn located(id: usize, latitude: Option<f64>, longitude: Option<f64>) -> Property {
+        Property {
+            latitude,
+            longitude,
+            ..priced(id, "1")
+        }
+    }
+
+    #[test]
+    fn bounds_cover_every_property_with_coordinates() {
+        let properties = [
+            located(1, Some(35.6), Some(139.7)),
+            located(2, Some(34.7), Some(135.5)),
+            located(3, Some(43.1), None),
+            located(4, None, None),
+        ];
+
+        assert_eq!(
+            bounds(&properties),
+            Some(Bounds {
+                min_latitude: 34.7,
+                max_latitude: 35.6,
+                min_longitude: 135.5,
+                max_longitude: 139.7,
+            })
+        );
+        assert_eq!(bounds(&properties[2..]), None);
+    }
+
+    fn near(id: usize, station: &str, price: &str) -> Property {
+        Property {
+            nearest_station: station.to_string(),
+            ..priced(id, price)
+        }
+    }
+
+    #[test]
+    fn groups_by_station_with_the_busiest_first() {
+        let properties = [
+            near(1, "渋谷駅", "100"),
+            near(2, "渋谷", "300"),
+            near(3, "新宿", "abc"),
+            near(4, "梅田", "50"),
+            near(5, " 渋谷 駅", ""),
+        ];
+
+        assert_eq!(
+            aggregate_by_station(&properties),
+            vec![
+                StationSummary {
+                    station: "渋谷".to_string(),
+                    count: 3,
+                    average_price: Some(200),
+                },
+                StationSummary {
+                    station: "新宿".to_string(),
+                    count: 1,
+                    average_price: None,
+                },
+                StationSummary {
+                    station: "梅田".to_string(),
+                    count: 1,
+                    average_price: Some(50),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_the_range_of_the_values() {
+        assert_eq!(
+            range([3.0, f64::NAN, 1.5, 7.0]),
+            Some(Range { min: 1.5, max: 7.0 })
+        );
+        assert_eq!(range([5_u64]), Some(Range { min: 5, max: 5 }));
+        assert_eq!(range(Vec::<u64>::new()), None);
+    }
+
+    #[test]
+    fn interpolates_percentiles_between_prices() {
+        let properties = [
+            priced(1, "400"),
+            priced(2, "100"),
+            priced(3, "応相談"),
+            priced(4, "300"),
+            priced(5, "200"),
+        ];
+
+        let percentiles = price_percentiles(&properties, &[0, 25, 50, 100]);
+        assert_eq!(
+            serde_json::to_value(&percentiles).unwrap(),
+            serde_json::json!({
+                "東京都": { "count": 4, "p0": 100, "p25": 175, "p50": 250, "p100": 400 }
+            })
+        );
+    }
+
+    #[test]
+    fn parses_lists_of_percentiles() {
+        assert_eq!(parse_percentiles("75, 25,75"), Ok(vec![25, 75]));
+        assert_eq!(
+            parse_percentiles("50,101"),
+            Err(InvalidPercentile("101".to_string()))
+        );
+        assert!(parse_percentiles("50,").is_err());
+    }
+
+    #[test]
+    fn keeps_the_first_few_groups() {
+        let groups = limit_groups(vec![1, 2, 3], 2, 10);
+        assert_eq!(groups.data, vec![1, 2]);
+        assert_eq!(groups.total, 3);
+        assert!(groups.has_more);
+        assert_eq!(groups.warning, None);
+
+        let groups = limit_groups(vec![1, 2, 3], 3, 10);
+        assert!(!groups.has_more);
+    }
+
+    #[test]
+    fn clamps_the_limit_to_the_maximum() {
+        let groups = limit_groups(vec![1, 2, 3], 5, 1);
+        assert_eq!(groups.data, vec![1]);
+        assert_eq!(
+            groups.warning.as_deref(),
+            Some("limit of 5 exceeds the maximum number of groups, so it was clamped to 1")
+        );
+    }
+}
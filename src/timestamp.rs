@@ -0,0 +1,101 @@
+//! Writing out timestamps in the timezone the operator configured
+
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+
+/// The timezone that timestamps in responses are written in.
+///
+/// Only fixed offsets are supported, like `+09:00` for Japan, which
+/// doesn't have daylight saving time, so that's all we need there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timezone(FixedOffset);
+
+impl Timezone {
+    pub const UTC: Timezone = Timezone(FixedOffset::east_opt(0).unwrap());
+
+    /// Writes out the time as RFC 3339, like `2024-06-01T09:30:00+09:00`.
+    /// Times in UTC end with `Z`, rather than `+00:00`.
+    pub fn format(self, time: DateTime<Utc>) -> String {
+        time.with_timezone(&self.0)
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+
+    /// Writes out the date of the time in this timezone, like `2024-06-01`
+    pub fn date(self, time: DateTime<Utc>) -> String {
+        time.with_timezone(&self.0).format("%Y-%m-%d").to_string()
+    }
+}
+
+impl Default for Timezone {
+    fn default() -> Self {
+        Timezone::UTC
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    /// Parses `UTC`, `Z`, or an offset from UTC like `+09:00` or `-0530`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid =
+            || format!("unknown timezone `{s}`, expected `UTC` or an offset like `+09:00`");
+
+        if s.eq_ignore_ascii_case("utc") || s.eq_ignore_ascii_case("z") {
+            return Ok(Timezone::UTC);
+        }
+
+        let (sign, offset) = match s.split_at_checked(1).ok_or_else(invalid)? {
+            ("+", offset) => (1, offset),
+            ("-", offset) => (-1, offset),
+            _ => return Err(invalid()),
+        };
+
+        let (hours, minutes) = offset
+            .split_once(':')
+            .or_else(|| offset.split_at_checked(2))
+            .ok_or_else(invalid)?;
+
+        let hours: i32 = parse_part(hours, 23).ok_or_else(invalid)?;
+        let minutes: i32 = parse_part(minutes, 59).ok_or_else(invalid)?;
+
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .map(Timezone)
+            .ok_or_else(invalid)
+    }
+}
+
+/// Parses the two digits of an offset's hours or minutes
+fn parse_part(part: &str, max: i32) -> Option<i32> {
+    if part.len() != 2 || !part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    part.parse().ok().filter(|value| *value <= max)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn parses_timezones() {
+        let time = Utc.with_ymd_and_hms(2024, 6, 1, 0, 30, 0).unwrap();
+        let format = |timezone: &str| timezone.parse::<Timezone>().unwrap().format(time);
+
+        assert_eq!(format("+09:00"), "2024-06-01T09:30:00+09:00");
+        assert_eq!(format("-0530"), "2024-05-31T19:00:00-05:30");
+        assert_eq!(format("utc"), "2024-06-01T00:30:00Z");
+        assert_eq!(format("+00:00"), "2024-06-01T00:30:00Z");
+    }
+
+    #[test]
+    fn rejects_unknown_timezones() {
+        for timezone in ["Asia/Tokyo", "+9", "+24:00", "+09:60", "09:00", ""] {
+            assert!(timezone.parse::<Timezone>().is_err(), "{timezone}");
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! Validation rules that operators can configure for imported rows
+
+use std::{collections::HashMap, fmt, path::Path};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    import::{Import, RowWarning, SkippedRow, FIELDS},
+    property::Property,
+    property_type::CanonicalType,
+};
+
+/// The rules for one field, as written in the rules file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawFieldRule {
+    required: bool,
+    max_length: Option<usize>,
+    pattern: Option<String>,
+}
+
+/// The rules for one field
+#[derive(Debug, Clone, Default)]
+pub struct FieldRule {
+    /// The field can't be empty
+    pub required: bool,
+    /// The most characters the field can have
+    pub max_length: Option<usize>,
+    /// A regular expression the field has to match. It isn't anchored,
+    /// so use `^` and `$` to match the whole value.
+    pub pattern: Option<Regex>,
+}
+
+/// The rules file: the rules for every property, along with extra rules
+/// for properties of each canonical type
+#[derive(Debug, Default, Deserialize)]
+struct RawRules {
+    #[serde(default)]
+    types: HashMap<CanonicalType, HashMap<String, RawFieldRule>>,
+    #[serde(flatten)]
+    fields: HashMap<String, RawFieldRule>,
+}
+
+/// The rules for each field, by name. Fields without rules accept anything.
+///
+/// These are loaded from a JSON file, such as:
+/// `{ "price": { "required": true, "max_length": 20, "pattern": "^[0-9,]+$" } }`
+///
+/// Rules that only apply to some kinds of property go under `types`,
+/// by canonical type, like `{ "types": { "land": { "land_area": { "required": true } } } }`
+#[derive(Debug, Clone, Default)]
+pub struct ValidationRules {
+    rules: Vec<(usize, FieldRule)>,
+    by_type: HashMap<CanonicalType, Vec<(usize, FieldRule)>>,
+}
+
+/// The reasons a rules file can't be loaded
+#[derive(Debug)]
+pub enum RulesError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnknownField(String),
+    InvalidPattern { field: String, error: regex::Error },
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RulesError::Io(error) => write!(f, "failed to read the rules file: {error}"),
+            RulesError::Json(error) => write!(f, "invalid rules file: {error}"),
+            RulesError::UnknownField(field) => write!(f, "rules contain unknown field `{field}`"),
+            RulesError::InvalidPattern { field, error } => {
+                write!(f, "invalid pattern for field `{field}`: {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RulesError {}
+
+impl ValidationRules {
+    /// Parses the rules from the JSON text of a rules file
+    pub fn from_json(text: &str) -> Result<Self, RulesError> {
+        let raw: RawRules = serde_json::from_str(text).map_err(RulesError::Json)?;
+
+        let by_type = raw
+            .types
+            .into_iter()
+            .map(|(canonical_type, rules)| Ok((canonical_type, parse_rules(rules)?)))
+            .collect::<Result<_, RulesError>>()?;
+
+        Ok(ValidationRules {
+            rules: parse_rules(raw.fields)?,
+            by_type,
+        })
+    }
+
+    /// Reads the rules from a file
+    pub fn load(path: &Path) -> Result<Self, RulesError> {
+        let text = std::fs::read_to_string(path).map_err(RulesError::Io)?;
+        ValidationRules::from_json(&text)
+    }
+
+    /// Checks a property against the rules, and then the rules
+    /// for its canonical type, returning the reason for the first rule it breaks
+    pub fn check(&self, property: &Property) -> Result<(), String> {
+        // The content key has the fields in the same order as `FIELDS`
+        let values = property.content_key();
+
+        check_rules(&self.rules, &values)?;
+
+        match self.by_type.get(&property.canonical_type) {
+            Some(rules) => check_rules(rules, &values)
+                .map_err(|error| format!("{error} for {} properties", property.canonical_type)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Compiles the rules for each field, by the field's index in `FIELDS`
+fn parse_rules(raw: HashMap<String, RawFieldRule>) -> Result<Vec<(usize, FieldRule)>, RulesError> {
+    let mut rules = Vec::with_capacity(raw.len());
+
+    for (field, rule) in raw {
+        let Some(index) = FIELDS.iter().position(|name| *name == field) else {
+            return Err(RulesError::UnknownField(field));
+        };
+
+        let pattern = rule
+            .pattern
+            .map(|pattern| Regex::new(&pattern))
+            .transpose()
+            .map_err(|error| RulesError::InvalidPattern {
+                field: field.clone(),
+                error,
+            })?;
+
+        rules.push((
+            index,
+            FieldRule {
+                required: rule.required,
+                max_length: rule.max_length,
+                pattern,
+            },
+        ));
+    }
+
+    // Checking in field order keeps the error messages predictable
+    rules.sort_unstable_by_key(|(index, _)| *index);
+
+    Ok(rules)
+}
+
+/// Checks the values of a property's fields against some rules
+fn check_rules(rules: &[(usize, FieldRule)], values: &[&str]) -> Result<(), String> {
+    for (index, rule) in rules {
+        let (field, value) = (FIELDS[*index], values[*index].trim());
+
+        // Optional fields that are left empty don't need to match anything
+        if value.is_empty() {
+            if rule.required {
+                return Err(format!("`{field}` is required"));
+            }
+
+            continue;
+        }
+
+        if let Some(max_length) = rule.max_length {
+            if value.chars().count() > max_length {
+                return Err(format!("`{field}` is longer than {max_length} characters"));
+            }
+        }
+
+        if let Some(pattern) = &rule.pattern {
+            if !pattern.is_match(value) {
+                return Err(format!("`{field}` doesn't match the pattern `{pattern}`"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks a property for values that can't be right for real estate,
+/// such as a negative price, or a plot of land with no area.
+/// Plots of land are found by their canonical type, so that `売地`
+/// and any configured types count too.
+pub fn sanity_check(property: &Property) -> Result<(), String> {
+    // Negative numbers don't parse as prices, so we check the raw value
+    if property.price.trim_start().starts_with(['-', '−']) {
+        return Err("`price` is negative".to_string());
+    }
+
+    match property.land_area_value() {
+        Some(area) if area < 0.0 => Err("`land_area` is negative".to_string()),
+        Some(area) if area == 0.0 && property.canonical_type == CanonicalType::Land => {
+            Err("`land_area` is zero for a plot of land".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The problems found in uploaded files, without importing them
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+    /// Whether every row would be imported without any warnings
+    pub valid: bool,
+    /// How many rows there are, not counting the header or blank lines
+    pub rows: usize,
+    /// The rows that would be skipped, and why
+    pub errors: Vec<SkippedRow>,
+    /// The rows that would be imported, but look wrong
+    pub warnings: Vec<RowWarning>,
+}
+
+impl ValidationReport {
+    /// Gathers up the problems from each file that was checked
+    pub fn new(imports: &[Import]) -> Self {
+        let errors: Vec<SkippedRow> = imports
+            .iter()
+            .flat_map(|import| import.skipped.iter().cloned())
+            .collect();
+        let warnings: Vec<RowWarning> = imports
+            .iter()
+            .flat_map(|import| import.warnings.iter().cloned())
+            .collect();
+        let rows = imports
+            .iter()
+            .map(|import| import.properties.len() + import.skipped.len())
+            .sum();
+
+        ValidationReport {
+            valid: errors.is_empty() && warnings.is_empty(),
+            rows,
+            errors,
+            warnings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{property::PropertyInput, property_type::TypeMapping, station::StationAliases};
+
+    use super::*;
+
+    fn property(property_type: &str, price: &str, land_area: &str) -> Property {
+        let input = PropertyInput {
+            property_type: property_type.to_string(),
+            price: price.to_string(),
+            land_area: land_area.to_string(),
+            ..Default::default()
+        };
+        Property::from_input(
+            1,
+            input,
+            &TypeMapping::default(),
+            &StationAliases::default(),
+        )
+    }
+
+    #[test]
+    fn rejects_negative_values() {
+        assert!(sanity_check(&property("戸建", "-100", "50")).is_err());
+        assert!(sanity_check(&property("戸建", "100", "-50")).is_err());
+        assert!(sanity_check(&property("戸建", "100", "50")).is_ok());
+    }
+
+    #[test]
+    fn rejects_land_without_area_by_its_canonical_type() {
+        assert!(sanity_check(&property("土地", "100", "0")).is_err());
+        assert!(sanity_check(&property("売地", "100", "0")).is_err());
+        assert!(sanity_check(&property("マンション", "100", "0")).is_ok());
+    }
+
+    #[test]
+    fn checks_the_configured_rules() {
+        let rules = ValidationRules::from_json(
+            r#"{
+                "price": { "required": true, "max_length": 6, "pattern": "^[0-9]+$" },
+                "property_type": { "required": true }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(rules.check(&property("土地", "100", "50")), Ok(()));
+        assert_eq!(
+            rules.check(&property("土地", " ", "50")),
+            Err("`price` is required".to_string())
+        );
+        assert_eq!(
+            rules.check(&property("土地", "1234567", "50")),
+            Err("`price` is longer than 6 characters".to_string())
+        );
+        assert_eq!(
+            rules.check(&property("土地", "100万", "50")),
+            Err("`price` doesn't match the pattern `^[0-9]+$`".to_string())
+        );
+        // The rules are checked in field order, not the order in the file
+        assert_eq!(
+            rules.check(&property("", "", "50")),
+            Err("`price` is required".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_rules_files() {
+        assert!(matches!(
+            ValidationRules::from_json(r#"{ "rent": { "required": true } }"#),
+            Err(RulesError::UnknownField(field)) if field == "rent"
+        ));
+        assert!(matches!(
+            ValidationRules::from_json(r#"{ "price": { "pattern": "(" } }"#),
+            Err(RulesError::InvalidPattern { field, .. }) if field == "price"
+        ));
+        assert!(matches!(
+            ValidationRules::from_json(r#"{ "price": { "requird": true } }"#),
+            Err(RulesError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn checks_the_rules_for_each_canonical_type() {
+        let rules = ValidationRules::from_json(
+            r#"{
+                "price": { "required": true },
+                "types": { "land": { "land_area": { "required": true } } }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(rules.check(&property("売地", "100", "50")), Ok(()));
+        assert_eq!(
+            rules.check(&property("売地", "100", "")),
+            Err("`land_area` is required for land properties".to_string())
+        );
+        assert_eq!(rules.check(&property("マンション", "100", "")), Ok(()));
+        // The rules for every property are still checked first
+        assert_eq!(
+            rules.check(&property("土地", "", "")),
+            Err("`price` is required".to_string())
+        );
+
+        assert!(matches!(
+            ValidationRules::from_json(r#"{ "types": { "castle": {} } }"#),
+            Err(RulesError::Json(_))
+        ));
+    }
+}
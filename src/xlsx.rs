@@ -0,0 +1,222 @@
+//! Importing property data from Excel workbooks
+
+use std::io::Cursor;
+
+use calamine::{Reader, Xlsx};
+
+use crate::{
+    archive,
+    import::{self, Import, ImportError, ImportOptions},
+};
+
+/// Checks if the data looks like an Excel workbook.
+/// Workbooks are ZIP archives, which we can tell apart from other
+/// archives by the workbook inside them.
+pub fn is_workbook(data: &[u8]) -> bool {
+    archive::is_zip(data) && archive::has_entry(data, "xl/workbook.xml")
+}
+
+/// Parses a worksheet from the workbook into properties,
+/// in the same way as if it had been uploaded as a CSV file.
+///
+/// The worksheet is picked by the `sheet` option, which can be either
+/// the name of a sheet or its index. Without it, we use the first sheet.
+pub fn parse_xlsx(data: &[u8], options: &ImportOptions) -> Result<Import, ImportError> {
+    let mut workbook = Xlsx::new(Cursor::new(data))
+        .map_err(|error| ImportError::InvalidWorkbook(error.to_string()))?;
+
+    let names = workbook.sheet_names();
+
+    let name = match options.sheet.as_deref() {
+        None => names.first(),
+        // A sheet's name takes priority, in case a sheet is named "2"
+        Some(sheet) => names.iter().find(|name| *name == sheet).or_else(|| {
+            sheet
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| names.get(index))
+        }),
+    };
+
+    let Some(name) = name.cloned() else {
+        return Err(ImportError::SheetNotFound {
+            requested: options.sheet.clone().unwrap_or_default(),
+            available: names,
+        });
+    };
+
+    let range = workbook
+        .worksheet_range(&name)
+        .map_err(|error| ImportError::InvalidWorkbook(error.to_string()))?;
+
+    let rows: Vec<Vec<String>> = range
+        .rows()
+        .map(|row| row.iter().map(ToString::to_string).collect())
+        .collect();
+
+    import::parse_rows(rows.iter(), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+
+    const ROW: [&str; 11] = [
+        "東京都",
+        "中央区",
+        "日本橋",
+        "4",
+        "16",
+        "12",
+        "",
+        "1000万円",
+        "東京",
+        "土地",
+        "80",
+    ];
+
+    /// A minimal workbook with these sheets, where each row is a list of
+    /// text cells, like a spreadsheet app would save
+    fn workbook(sheets: &[(&str, &[&[&str]])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        let options = SimpleFileOptions::default();
+        let mut file = |name: &str, contents: String| {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        };
+
+        let relationships = |targets: Vec<(String, String)>| {
+            let relationships: String = targets
+                .iter()
+                .enumerate()
+                .map(|(i, (kind, target))| {
+                    format!(
+                        r#"<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/{kind}" Target="{target}"/>"#,
+                        i + 1
+                    )
+                })
+                .collect();
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">{relationships}</Relationships>"#
+            )
+        };
+
+        file(
+            "[Content_Types].xml",
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>{}</Types>"#,
+                (1..=sheets.len())
+                    .map(|i| format!(r#"<Override PartName="/xl/worksheets/sheet{i}.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#))
+                    .collect::<String>()
+            ),
+        );
+        file(
+            "_rels/.rels",
+            relationships(vec![(
+                "officeDocument".to_string(),
+                "xl/workbook.xml".to_string(),
+            )]),
+        );
+        file(
+            "xl/workbook.xml",
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?><workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>{}</sheets></workbook>"#,
+                sheets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (name, _))| {
+                        format!(
+                            r#"<sheet name="{name}" sheetId="{0}" r:id="rId{0}"/>"#,
+                            i + 1
+                        )
+                    })
+                    .collect::<String>()
+            ),
+        );
+        file(
+            "xl/_rels/workbook.xml.rels",
+            relationships(
+                (1..=sheets.len())
+                    .map(|i| ("worksheet".to_string(), format!("worksheets/sheet{i}.xml")))
+                    .collect(),
+            ),
+        );
+
+        for (i, (_, rows)) in sheets.iter().enumerate() {
+            let rows: String = rows
+                .iter()
+                .map(|row| {
+                    let cells: String = row
+                        .iter()
+                        .map(|cell| format!(r#"<c t="inlineStr"><is><t>{cell}</t></is></c>"#))
+                        .collect();
+                    format!("<row>{cells}</row>")
+                })
+                .collect();
+            file(
+                &format!("xl/worksheets/sheet{}.xml", i + 1),
+                format!(
+                    r#"<?xml version="1.0" encoding="UTF-8"?><worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>{rows}</sheetData></worksheet>"#
+                ),
+            );
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut osaka = ROW;
+        osaka[0] = "大阪府";
+
+        workbook(&[
+            ("Summary", &[&["Exported 2024-06-01"]]),
+            ("東京", &[&import::FIELDS, &ROW]),
+            ("2", &[&import::FIELDS, &osaka]),
+        ])
+    }
+
+    fn sheet(sheet: Option<&str>) -> Result<Import, ImportError> {
+        let options = ImportOptions {
+            sheet: sheet.map(str::to_string),
+            ..Default::default()
+        };
+        parse_xlsx(&sample(), &options)
+    }
+
+    #[test]
+    fn reads_the_first_sheet_by_default() {
+        assert!(is_workbook(&sample()));
+
+        let import = sheet(None).unwrap();
+        assert!(import.properties.is_empty());
+    }
+
+    #[test]
+    fn picks_a_sheet_by_name_or_index() {
+        let import = sheet(Some("東京")).unwrap();
+        assert_eq!(import.properties[0].prefecture, "東京都");
+        assert_eq!(import.properties[0].land_area, "80");
+
+        let import = sheet(Some("1")).unwrap();
+        assert_eq!(import.properties[0].prefecture, "東京都");
+
+        // A sheet named like an index is picked by its name first
+        let import = sheet(Some("2")).unwrap();
+        assert_eq!(import.properties[0].prefecture, "大阪府");
+    }
+
+    #[test]
+    fn lists_the_sheets_when_one_is_missing() {
+        assert_eq!(
+            sheet(Some("大阪")).unwrap_err(),
+            ImportError::SheetNotFound {
+                requested: "大阪".to_string(),
+                available: vec!["Summary".to_string(), "東京".to_string(), "2".to_string()],
+            }
+        );
+    }
+}